@@ -0,0 +1,103 @@
+use crate::config::ColorMode;
+use appcui::graphics::Color;
+use std::sync::RwLock;
+
+static MODE: RwLock<ColorMode> = RwLock::new(ColorMode::Normal);
+
+/// Contrast ratio below which `high-contrast` replaces the foreground, the
+/// WCAG minimum for body text.
+const MIN_CONTRAST: f64 = 4.5;
+
+/// Simulated sight of each deficiency, as RGB matrices.
+const PROTANOPIA: [[f64; 3]; 3] = [[0.567, 0.433, 0.0], [0.558, 0.442, 0.0], [0.0, 0.242, 0.758]];
+const DEUTERANOPIA: [[f64; 3]; 3] = [[0.625, 0.375, 0.0], [0.7, 0.3, 0.0], [0.0, 0.3, 0.7]];
+const TRITANOPIA: [[f64; 3]; 3] = [[0.95, 0.05, 0.0], [0.0, 0.433, 0.567], [0.0, 0.475, 0.525]];
+
+/// Moves the difference a deficiency hides into channels that are still seen.
+const SHIFT: [[f64; 3]; 3] = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+
+/// Use `mode` for the colors programs write.
+pub fn set_mode(mode: ColorMode) {
+    *MODE.write().unwrap() = mode;
+}
+
+fn mode() -> ColorMode {
+    *MODE.read().unwrap()
+}
+
+/// Remap a color a program asked for so the difference with its neighbours
+/// stays visible in the color-blind modes (daltonization).
+pub fn remap((r, g, b): (u8, u8, u8)) -> Color {
+    let simulation = match mode() {
+        ColorMode::Protanopia => PROTANOPIA,
+        ColorMode::Deuteranopia => DEUTERANOPIA,
+        ColorMode::Tritanopia => TRITANOPIA,
+        ColorMode::Normal | ColorMode::HighContrast => return Color::RGB(r, g, b),
+    };
+
+    let original = [r as f64, g as f64, b as f64];
+    let seen = multiply(&simulation, original);
+    let error = [original[0] - seen[0], original[1] - seen[1], original[2] - seen[2]];
+    let shift = multiply(&SHIFT, error);
+
+    let channel = |i: usize| (original[i] + shift[i]).round().clamp(0.0, 255.0) as u8;
+    Color::RGB(channel(0), channel(1), channel(2))
+}
+
+/// Colors of a cell, the foreground replaced by black or white in the
+/// `high-contrast` mode when it is too close to the background.
+pub fn cell_colors(foreground: Color, background: Color) -> (Color, Color) {
+    if mode() != ColorMode::HighContrast {
+        return (foreground, background);
+    }
+    let (Color::RGB(fr, fg, fb), Color::RGB(br, bg, bb)) = (foreground, background) else {
+        return (foreground, background);
+    };
+
+    let background_luminance = luminance(br, bg, bb);
+    if contrast(luminance(fr, fg, fb), background_luminance) >= MIN_CONTRAST {
+        return (foreground, background);
+    }
+
+    let foreground = match contrast(1.0, background_luminance) >= contrast(0.0, background_luminance) {
+        true => Color::RGB(255, 255, 255),
+        false => Color::RGB(0, 0, 0),
+    };
+    (foreground, background)
+}
+
+fn multiply(matrix: &[[f64; 3]; 3], color: [f64; 3]) -> [f64; 3] {
+    matrix.map(|row| row[0] * color[0] + row[1] * color[1] + row[2] * color[2])
+}
+
+/// Relative luminance, as WCAG defines it.
+fn luminance(r: u8, g: u8, b: u8) -> f64 {
+    let linear = |channel: u8| {
+        let channel = channel as f64 / 255.0;
+        if channel <= 0.03928 { channel / 12.92 } else { ((channel + 0.055) / 1.055).powf(2.4) }
+    };
+    0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+}
+
+fn contrast(a: f64, b: f64) -> f64 {
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The mode is global, so every mode is checked in the one test.
+    #[test]
+    fn high_contrast_replaces_only_unreadable_foregrounds() {
+        set_mode(ColorMode::HighContrast);
+        let dark = Color::RGB(30, 30, 30);
+        let light = Color::RGB(220, 220, 220);
+        assert!(matches!(cell_colors(Color::RGB(60, 60, 60), dark), (Color::RGB(255, 255, 255), Color::RGB(30, 30, 30))));
+        assert!(matches!(cell_colors(Color::RGB(190, 190, 190), light), (Color::RGB(0, 0, 0), Color::RGB(220, 220, 220))));
+        assert!(matches!(cell_colors(Color::RGB(200, 200, 0), dark), (Color::RGB(200, 200, 0), Color::RGB(30, 30, 30))));
+
+        set_mode(ColorMode::Normal);
+        assert!(matches!(cell_colors(Color::RGB(60, 60, 60), dark), (Color::RGB(60, 60, 60), Color::RGB(30, 30, 30))));
+    }
+}
@@ -0,0 +1,102 @@
+use crate::config::{KeyBinding, Keybindings};
+use appcui::input::{Key, KeyModifier};
+
+/// Everything the keybindings and the command palette can do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    CommandPalette,
+    ClipboardManager,
+    Processes,
+    RespawnWindow,
+    DuplicateWindow,
+    PipeWindow,
+    CloseWindow,
+    PlayRecording,
+    NoArrange,
+    Cascade,
+    Vertical,
+    Horizontal,
+    Grid,
+    Exit,
+}
+
+/// Every action, in the order the command palette lists them.
+pub const ACTIONS: [Action; 14] = [
+    Action::CommandPalette,
+    Action::ClipboardManager,
+    Action::Processes,
+    Action::RespawnWindow,
+    Action::DuplicateWindow,
+    Action::PipeWindow,
+    Action::CloseWindow,
+    Action::PlayRecording,
+    Action::NoArrange,
+    Action::Cascade,
+    Action::Vertical,
+    Action::Horizontal,
+    Action::Grid,
+    Action::Exit,
+];
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::CommandPalette => "Command palette",
+            Action::ClipboardManager => "Window: Clipboard history",
+            Action::Processes => "Window: Processes",
+            Action::RespawnWindow => "Window: Respawn",
+            Action::DuplicateWindow => "Window: Duplicate",
+            Action::PipeWindow => "Window: Pipe screen to a command",
+            Action::CloseWindow => "Window: Close",
+            Action::PlayRecording => "Desktop: Play recording",
+            Action::NoArrange => "Tilling: No arrangement",
+            Action::Cascade => "Tilling: Cascade",
+            Action::Vertical => "Tilling: Vertical",
+            Action::Horizontal => "Tilling: Horizontal",
+            Action::Grid => "Tilling: Grid",
+            Action::Exit => "Desktop: Exit",
+        }
+    }
+
+    /// Whether it acts on the focused window, so has nothing to do on an empty desktop.
+    pub fn acts_on_window(self) -> bool {
+        matches!(
+            self,
+            Action::ClipboardManager
+                | Action::Processes
+                | Action::RespawnWindow
+                | Action::DuplicateWindow
+                | Action::PipeWindow
+                | Action::CloseWindow
+        )
+    }
+
+    /// The key running it, for the actions that have one.
+    pub fn binding(self, keybindings: &Keybindings) -> Option<&KeyBinding> {
+        match self {
+            Action::CommandPalette => Some(&keybindings.command_palette),
+            Action::ClipboardManager => Some(&keybindings.clipboard_manager),
+            Action::Processes => Some(&keybindings.processes),
+            Action::RespawnWindow => Some(&keybindings.respawn_window),
+            Action::DuplicateWindow => Some(&keybindings.duplicate_window),
+            Action::PipeWindow => Some(&keybindings.pipe_window),
+            Action::CloseWindow => Some(&keybindings.close_window),
+            _ => None,
+        }
+    }
+}
+
+/// The action `key` is bound to, if any.
+pub fn bound_to(keybindings: &Keybindings, key: Key) -> Option<Action> {
+    ACTIONS.into_iter().find(|action| action.binding(keybindings).is_some_and(|binding| matches(binding, key)))
+}
+
+/// Whether `key` is the one `binding` names, with exactly its modifiers.
+fn matches(binding: &KeyBinding, key: Key) -> bool {
+    use KeyModifier as KM;
+
+    key.modifier.contains(KM::Ctrl) == binding.ctrl
+        && key.modifier.contains(KM::Alt) == binding.alt
+        && key.modifier.contains(KM::Shift) == binding.shift
+        && key.code == binding.key
+}
@@ -0,0 +1,217 @@
+use anyhow::Context;
+use chrono::{Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::time::Duration;
+
+/// Size after which the log is rotated, keeping a single older file.
+const MAX_LOG_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Entries waiting for the writer thread, past which new ones are dropped.
+const QUEUE_SIZE: usize = 1024;
+
+/// How often `logs --follow` looks for new entries.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Activity log of a session started with `serve --activity-log`.
+pub fn activity_path(dir: &Path, session: &str) -> PathBuf {
+    dir.join(format!("{}.activity.log", session))
+}
+
+/// Where the previous log goes once the current one is full.
+fn rotated_path(path: &Path) -> PathBuf {
+    path.with_extension("log.1")
+}
+
+/// Something worth recording about a session.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Activity {
+    SessionStarted,
+    /// PTY output, invalid UTF-8 replaced.
+    Output { data: String },
+    ClientAttached { client: String },
+    ClientDetached { client: String },
+    /// The PTY was resized to fit the attached clients.
+    Resized { cols: u16, rows: u16 },
+    Renamed { name: String },
+    ChildExited { status: String },
+}
+
+/// One line of the log.
+#[derive(Serialize, Deserialize, Debug)]
+struct Record {
+    time: String,
+    #[serde(flatten)]
+    activity: Activity,
+}
+
+/// Appends timestamped activity as JSON lines, rotating the file once it
+/// grows past `MAX_LOG_SIZE`.
+pub struct ActivityLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl ActivityLog {
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size })
+    }
+
+    /// Record an entry, dropping it if the log cannot be written.
+    pub fn record(&mut self, activity: Activity) {
+        let record = Record { time: Local::now().to_rfc3339_opts(SecondsFormat::Millis, false), activity };
+        let Ok(mut line) = serde_json::to_string(&record) else { return };
+        line.push('\n');
+
+        if self.size + line.len() as u64 > MAX_LOG_SIZE && self.rotate().is_err() {
+            return;
+        }
+
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.size += line.len() as u64;
+        }
+    }
+
+    fn rotate(&mut self) -> anyhow::Result<()> {
+        fs::rename(&self.path, rotated_path(&self.path))?;
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Follow a session rename, taking the rotated file along.
+    pub fn rename(&mut self, path: PathBuf) -> anyhow::Result<()> {
+        fs::rename(&self.path, &path).context("failed to rename the activity log")?;
+        let rotated = rotated_path(&self.path);
+        if rotated.exists() {
+            fs::rename(&rotated, rotated_path(&path)).context("failed to rename the activity log")?;
+        }
+        self.path = path;
+        Ok(())
+    }
+}
+
+enum Request {
+    Record(Activity),
+    Rename(PathBuf, SyncSender<anyhow::Result<()>>),
+}
+
+/// Hands entries to a thread writing the log, so a slow disk never holds up
+/// the session's output.
+pub struct ActivityWriter {
+    requests: SyncSender<Request>,
+}
+
+impl ActivityWriter {
+    pub fn spawn(mut log: ActivityLog) -> Self {
+        let (requests, rx): (SyncSender<Request>, Receiver<Request>) = mpsc::sync_channel(QUEUE_SIZE);
+        std::thread::spawn(move || {
+            for request in rx {
+                match request {
+                    Request::Record(activity) => log.record(activity),
+                    Request::Rename(path, reply) => {
+                        let _ = reply.send(log.rename(path));
+                    }
+                }
+            }
+        });
+        Self { requests }
+    }
+
+    /// Queue an entry, dropping it if the writer is that far behind.
+    pub fn record(&self, activity: Activity) {
+        let _ = self.requests.try_send(Request::Record(activity));
+    }
+
+    /// Move the log once the entries queued before are written.
+    pub fn rename(&self, path: PathBuf) -> anyhow::Result<()> {
+        let (reply, done) = mpsc::sync_channel(1);
+        self.requests.send(Request::Rename(path, reply)).context("the activity log writer stopped")?;
+        done.recv().context("the activity log writer stopped")?
+    }
+}
+
+/// The log may record anything typed into the session, so it is private.
+fn open_append(path: &Path) -> anyhow::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to open activity log {:?}", path))
+}
+
+/// `desktop-tui logs`: print a session's activity, then keep printing new
+/// entries with `follow`, across rotations.
+pub async fn show(path: &Path, follow: bool) -> anyhow::Result<()> {
+    if !path.exists() {
+        anyhow::bail!("No activity log at {:?}, was the session started with `serve --activity-log`?", path);
+    }
+
+    let rotated = rotated_path(path);
+    if rotated.exists() {
+        print_lines(&mut BufReader::new(File::open(&rotated)?))?;
+    }
+
+    let mut file = File::open(path)?;
+    let mut reader = BufReader::new(file.try_clone()?);
+    print_lines(&mut reader)?;
+
+    while follow {
+        tokio::time::sleep(FOLLOW_INTERVAL).await;
+
+        // A new inode means the log was rotated, read the new file from its start.
+        if let Ok(metadata) = fs::metadata(path)
+            && metadata.ino() != file.metadata()?.ino()
+        {
+            print_lines(&mut reader)?;
+            file = File::open(path)?;
+            reader = BufReader::new(file.try_clone()?);
+        }
+
+        print_lines(&mut reader)?;
+    }
+
+    Ok(())
+}
+
+/// Print every complete line left in `reader`, leaving a partly written one for later.
+fn print_lines(reader: &mut BufReader<File>) -> anyhow::Result<()> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {
+            return Ok(());
+        }
+        if !line.ends_with('\n') {
+            reader.seek(SeekFrom::Current(-(read as i64)))?;
+            return Ok(());
+        }
+
+        match serde_json::from_str::<Record>(&line) {
+            Ok(record) => println!("{} {}", record.time, describe(&record.activity)),
+            Err(_) => print!("{}", line),
+        }
+    }
+}
+
+fn describe(activity: &Activity) -> String {
+    match activity {
+        Activity::SessionStarted => String::from("session started"),
+        Activity::Output { data } => format!("output {:?}", data),
+        Activity::ClientAttached { client } => format!("client attached: {}", client),
+        Activity::ClientDetached { client } => format!("client detached: {}", client),
+        Activity::Resized { cols, rows } => format!("resized to {}x{}", cols, rows),
+        Activity::Renamed { name } => format!("renamed to '{}'", name),
+        Activity::ChildExited { status } => format!("child exited ({})", status),
+    }
+}
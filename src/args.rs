@@ -1,5 +1,15 @@
+use crate::client::ConnectOptions;
+use crate::completions::session_candidates;
+use crate::config::{Backend, ColorMode, Config, ThemeName};
+use crate::environment::EnvVar;
+use crate::hooks::Hook;
+use crate::server::{IdleAction, TermSize};
+use crate::transport::ListenAddr;
 use clap::{Parser, Subcommand};
+use clap_complete::engine::ArgValueCandidates;
+use clap_complete::Shell;
 use std::path::PathBuf;
+use tracing::level_filters::LevelFilter;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -8,6 +18,35 @@ pub struct Args {
     #[arg(default_value = None)]
     pub shortcut_dir: Option<PathBuf>,
 
+    /// Config file (defaults to ~/.config/desktop-tui/config.toml)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Desktop theme: default, dark-gray or light
+    #[arg(long, global = true)]
+    pub theme: Option<ThemeName>,
+
+    /// Rendering backend: auto or crossterm, termios and ncurses when built with their feature
+    #[arg(long, global = true)]
+    pub backend: Option<Backend>,
+
+    /// Color adjustment of what programs draw in windows: normal, deuteranopia, protanopia,
+    /// tritanopia or high-contrast (the desktop's own colors come from --theme)
+    #[arg(long, global = true)]
+    pub color_mode: Option<ColorMode>,
+
+    /// Draw anything outside ASCII with a look-alike or `?`, for terminals lacking glyphs
+    #[arg(long, global = true)]
+    pub ascii_only: bool,
+
+    /// Least severe log records kept: off, error, warn, info, debug or trace
+    #[arg(long, global = true, default_value = "info")]
+    pub log_level: LevelFilter,
+
+    /// Append logs to this file instead of stderr (the desktop only logs to a file)
+    #[arg(long, global = true)]
+    pub log_file: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -16,23 +55,216 @@ pub struct Args {
 pub enum Commands {
     /// Run desktop-tui directly (default when no subcommand)
     Run {
-        #[arg(default_value = ".")]
-        shortcut_dir: PathBuf,
+        shortcut_dir: Option<PathBuf>,
+        /// File the desktop layout is snapshotted to (set by `serve`)
+        #[arg(long, hide = true)]
+        layout_file: Option<PathBuf>,
+        /// Reopen the windows saved in a layout file (set by `serve --resume`)
+        #[arg(long, hide = true)]
+        restore: Option<PathBuf>,
+        /// Name of the session serving the desktop (set by `serve`)
+        #[arg(long, hide = true)]
+        session: Option<String>,
     },
     /// Start desktop-tui as a daemon with session support
     Serve {
-        #[arg(default_value = ".")]
-        shortcut_dir: PathBuf,
+        shortcut_dir: Option<PathBuf>,
         /// Session name
-        #[arg(long, default_value = "default")]
-        session: String,
+        #[arg(long, add = ArgValueCandidates::new(session_candidates))]
+        session: Option<String>,
+        /// Additional address to listen on (tcp://host:port or unix:///path), can be repeated
+        #[arg(long)]
+        listen: Vec<ListenAddr>,
+        /// PEM certificate chain for TCP listeners
+        #[arg(long, requires = "tls_key")]
+        tls_cert: Option<PathBuf>,
+        /// PEM private key for TCP listeners
+        #[arg(long, requires = "tls_cert")]
+        tls_key: Option<PathBuf>,
+        /// Keep the session at this size (COLSxROWS) instead of fitting the smallest client
+        #[arg(long)]
+        force_size: Option<TermSize>,
+        /// Reopen the windows of a session stopped by SIGTERM, in its saved shortcut directory
+        #[arg(long)]
+        resume: bool,
+        /// Stay attached to the terminal and log to stderr instead of running in the background
+        #[arg(long)]
+        foreground: bool,
+        /// Run a shell command on a session event (EVENT=COMMAND), can be repeated.
+        /// Events: session-created, client-attached, client-detached, child-exited,
+        /// bell-in-background-window
+        #[arg(long = "hook", value_name = "EVENT=COMMAND")]
+        hooks: Vec<Hook>,
+        /// Serve this shell command (e.g. "nvim notes.md") instead of the desktop
+        #[arg(long, conflicts_with = "resume")]
+        command: Option<String>,
+        /// Set a variable for the served program (NAME=VALUE), can be repeated
+        #[arg(long = "env", value_name = "NAME=VALUE")]
+        env: Vec<EnvVar>,
+        /// Client variable to take over on attach, `LC_*` style prefixes allowed, can be
+        /// repeated. Defaults to LANG, LANGUAGE, LC_*, DISPLAY, WAYLAND_DISPLAY, XAUTHORITY,
+        /// SSH_AUTH_SOCK, SSH_AGENT_PID, SSH_CONNECTION and KRB5CCNAME
+        #[arg(long, value_name = "NAME")]
+        update_environment: Vec<String>,
+        /// Record output, attaches, detaches and resizes for `desktop-tui logs`
+        #[arg(long)]
+        activity_log: bool,
+        /// Link this session name to a running session instead of starting one: both share
+        /// the desktop, each with its own clients and current window
+        #[arg(long, value_name = "SESSION", add = ArgValueCandidates::new(session_candidates), conflicts_with_all = ["resume", "command", "listen", "hooks"])]
+        group: Option<String>,
+        /// Act on clients that sent no input for this many minutes, after a warning
+        #[arg(long, value_name = "MINUTES", value_parser = clap::value_parser!(u64).range(1..))]
+        idle_timeout: Option<u64>,
+        /// What happens to idle clients: detach, or lock until the session token is typed
+        #[arg(long, default_value = "detach", requires = "idle_timeout")]
+        idle_action: IdleAction,
     },
     /// Attach to a running session
     Attach {
+        /// Session name, or `user@host:session` to attach to another machine's over SSH
+        #[arg(add = ArgValueCandidates::new(session_candidates))]
+        session: Option<String>,
+        /// Attach to a remote server over TLS (host:port)
+        #[arg(long)]
+        remote: Option<String>,
+        /// PEM CA certificate used to verify the remote server
+        #[arg(long, requires = "remote")]
+        tls_ca: Option<PathBuf>,
+        /// File containing the session token (defaults to the local session's token)
+        #[arg(long)]
+        token_file: Option<PathBuf>,
+        /// Watch the session without sending any input (Ctrl+C detaches)
+        #[arg(long)]
+        read_only: bool,
+        /// Keep retrying when the connection breaks instead of exiting
+        #[arg(long)]
+        reconnect: bool,
+        /// Detach every other client attached to the session
+        #[arg(long)]
+        detach_others: bool,
+        /// Drive the session with JSON commands on stdin and get JSON events on stdout
+        #[arg(long, short = 'C', conflicts_with = "reconnect")]
+        control: bool,
+        /// Show only this desktop window (id or title) full-screen instead of the whole desktop
+        #[arg(long, conflicts_with = "detach_others")]
+        window: Option<String>,
+    },
+    /// List sessions with their uptime, clients and size
+    List {
+        /// Print the listing as JSON
+        #[arg(long)]
+        json: bool,
+        /// Remove the sockets, tokens and pidfiles of sessions whose server is gone
+        #[arg(long)]
+        clean: bool,
+    },
+    /// Stop a session: SIGTERM its child, SIGKILL after a grace period
+    Kill {
+        #[command(flatten)]
+        target: SessionTarget,
+    },
+    /// Rename a session
+    Rename {
+        #[command(flatten)]
+        target: SessionTarget,
+        /// New session name
+        name: String,
+    },
+    /// Detach every client attached to a session
+    DetachClients {
+        #[command(flatten)]
+        target: SessionTarget,
+    },
+    /// Show the clients of a session one window (id or title), or the whole desktop if omitted
+    SelectWindow {
+        #[command(flatten)]
+        target: SessionTarget,
+        /// Window id or title
+        window: Option<String>,
+    },
+    /// Print the activity log of a session started with `serve --activity-log`
+    Logs {
         /// Session name
-        #[arg(default_value = "default")]
-        session: String,
+        #[arg(long, add = ArgValueCandidates::new(session_candidates))]
+        session: Option<String>,
+        /// Keep printing new entries as they are written
+        #[arg(long, short)]
+        follow: bool,
     },
-    /// List active sessions
-    List,
+    /// Type keys into a session without attaching (escapes like \n, \t, \e, \x03 are understood)
+    Send {
+        #[command(flatten)]
+        target: SessionTarget,
+        /// Keys to send
+        keys: String,
+        /// Treat KEYS as hex-encoded raw bytes (e.g. "1b5b41")
+        #[arg(long)]
+        hex: bool,
+    },
+    /// Record what a session shows to an asciicast v2 file, until it ends or Ctrl+C
+    Record {
+        #[command(flatten)]
+        target: SessionTarget,
+        /// File to write, e.g. out.cast
+        output: PathBuf,
+    },
+    /// Print a completion script for a shell
+    Completions {
+        shell: Shell,
+    },
+    /// Print the manpage
+    Man,
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Run a program with variables set or removed, in place of this process
+    /// (used by desktop windows)
+    #[command(hide = true)]
+    Exec {
+        #[arg(long)]
+        set: Vec<EnvVar>,
+        #[arg(long)]
+        unset: Vec<String>,
+        /// Program and arguments, after `--`
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the effective configuration: config file, environment and flags merged
+    Show,
+}
+
+/// Which session a management command talks to.
+#[derive(clap::Args, Debug)]
+pub struct SessionTarget {
+    /// Session name
+    #[arg(long, add = ArgValueCandidates::new(session_candidates))]
+    pub session: Option<String>,
+    /// Talk to a remote server over TLS (host:port)
+    #[arg(long)]
+    pub remote: Option<String>,
+    /// PEM CA certificate used to verify the remote server
+    #[arg(long, requires = "remote")]
+    pub tls_ca: Option<PathBuf>,
+    /// File containing the session token (defaults to the local session's token)
+    #[arg(long)]
+    pub token_file: Option<PathBuf>,
+}
+
+impl SessionTarget {
+    /// Connection options, the session defaulting to the configured one.
+    pub fn resolve(self, config: &Config) -> ConnectOptions {
+        ConnectOptions {
+            session: self.session.unwrap_or_else(|| config.session.clone()),
+            remote: self.remote,
+            tls_ca: self.tls_ca,
+            token_file: self.token_file,
+        }
+    }
 }
@@ -0,0 +1,67 @@
+use anyhow::Context;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// Environment variable holding the token for remote attaches.
+pub const TOKEN_ENV: &str = "DESKTOP_TUI_TOKEN";
+
+/// Length in bytes of the random secret (hex encoded on disk).
+const TOKEN_LEN: usize = 32;
+
+/// Return the token file path for the given session directory and name.
+pub fn token_path(dir: &Path, session: &str) -> PathBuf {
+    dir.join(format!("{}.token", session))
+}
+
+/// Generate a fresh token and write it to `path`, readable by the owner only.
+pub fn create_token(path: &Path) -> anyhow::Result<String> {
+    let mut bytes = [0u8; TOKEN_LEN];
+    fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .context("cannot read random bytes for session token")?;
+
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("cannot create token file {:?}", path))?;
+    file.write_all(token.as_bytes())?;
+
+    Ok(token)
+}
+
+/// Read a token from a file, ignoring surrounding whitespace.
+pub fn read_token(path: &Path) -> anyhow::Result<String> {
+    let token = fs::read_to_string(path)
+        .with_context(|| format!("cannot read session token {:?}", path))?;
+    Ok(token.trim().to_owned())
+}
+
+/// Compare two tokens without short-circuiting on the first differing byte.
+pub fn tokens_match(expected: &str, given: &str) -> bool {
+    let (expected, given) = (expected.as_bytes(), given.as_bytes());
+
+    if expected.len() != given.len() {
+        return false;
+    }
+
+    expected
+        .iter()
+        .zip(given)
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Restrict a file or directory to the given mode.
+pub fn set_mode(path: &Path, mode: u32) -> anyhow::Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("cannot set permissions on {:?}", path))
+}
@@ -1,131 +1,959 @@
-use crate::protocol::{self, Message};
-use crate::server::socket_path;
-use anyhow::Context;
-use crossterm::terminal::{disable_raw_mode, enable_raw_mode, size as terminal_size};
+use crate::auth;
+use crate::control;
+use crate::crash::TerminalGuard;
+use crate::environment;
+use crate::protocol::{self, AttachedClient, ClientIdentity, ControlRequest, Framing, Message, Negotiated, ProtocolError, SessionInfo};
+use crate::server::{self, session_dir, socket_path};
+use crate::terminal_emulation::osc52;
+use crate::transport::{self, BoxedStream};
+use anyhow::{anyhow, bail, Context};
+use crossterm::terminal::{enable_raw_mode, size as terminal_size};
+use serde::Serialize;
 use std::fs;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::UnixStream;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, Stdout};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::mpsc;
+use tracing::debug;
 
-pub async fn attach(session: String) -> anyhow::Result<()> {
-    let sock = socket_path(&session)?;
+/// First and longest wait between reconnection attempts.
+const RECONNECT_MIN_DELAY: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// How long one reconnection attempt may take to connect and handshake.
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Exit status of ssh when the connection failed, rather than the remote command.
+const SSH_FAILURE: i32 = 255;
+
+/// How long `kill` waits for the session socket to disappear.
+const KILL_WAIT: Duration = Duration::from_secs(10);
+
+/// How long `list` waits for a session to describe itself.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Bracketed paste mode: the terminal wraps pasted text in these markers.
+const ENABLE_BRACKETED_PASTE: &[u8] = b"\x1b[?2004h";
+const DISABLE_BRACKETED_PASTE: &[u8] = b"\x1b[?2004l";
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// How to reach a session, shared by `attach` and the management commands.
+pub struct ConnectOptions {
+    pub session: String,
+    /// `host:port` of a server started with `serve --listen tcp://…`.
+    pub remote: Option<String>,
+    /// CA certificate used to verify the remote server.
+    pub tls_ca: Option<PathBuf>,
+    /// File holding the session token, defaults to the local session's token file.
+    pub token_file: Option<PathBuf>,
+}
+
+/// Options for `desktop-tui attach`.
+pub struct AttachOptions {
+    pub connection: ConnectOptions,
+    /// Only watch the session, the server drops any input from this client.
+    pub read_only: bool,
+    /// Retry with backoff instead of exiting when the connection breaks.
+    pub reconnect: bool,
+    /// Detach every other client when attaching, like `tmux attach -d`.
+    pub detach_others: bool,
+    /// Speak JSON lines on stdin and stdout instead of acting as a terminal.
+    pub control: bool,
+    /// Show only this desktop window, by id or title.
+    pub window: Option<String>,
+}
+
+/// Find the token to present to the server.
+///
+/// An explicit `--token-file` wins, then `DESKTOP_TUI_TOKEN`, then the
+/// token file the local server wrote into the session directory.
+fn resolve_token(options: &ConnectOptions) -> anyhow::Result<String> {
+    if let Some(path) = &options.token_file {
+        return auth::read_token(path);
+    }
+
+    if let Ok(token) = std::env::var(auth::TOKEN_ENV) {
+        return Ok(token.trim().to_owned());
+    }
+
+    if options.remote.is_some() {
+        bail!("Remote sessions require --token-file or the {} env var", auth::TOKEN_ENV);
+    }
+
+    auth::read_token(&auth::token_path(&session_dir()?, &options.session))
+}
+
+/// Greet the server, agree on a protocol version and features, then
+/// present the session token and wait for the server's verdict.
+///
+/// Returns the version and features both sides support.
+async fn handshake(stream: &mut BoxedStream, token: String, read_only: bool) -> anyhow::Result<Negotiated> {
+    stream.write_all(&protocol::encode(&protocol::hello(), Framing::Legacy)?).await?;
+
+    let negotiated = match protocol::decode(stream).await {
+        Ok(Message::Hello { version, features }) => {
+            let version = protocol::negotiate_version(version).map_err(|reason| anyhow!("Server {}", reason))?;
+            debug!("Negotiated protocol version {} with features {:?}.", version, features);
+            Negotiated { version, features }
+        }
+        Ok(Message::Rejected { reason }) => bail!("Server rejected the connection: {}", reason),
+        Ok(other) => bail!("Unexpected message during handshake: {:?}", other),
+        Err(e) => {
+            return Err(e).context("Server closed the connection or speaks an incompatible protocol, is it up to date?");
+        }
+    };
+
+    if read_only && !negotiated.has(protocol::FEATURE_READ_ONLY) {
+        bail!("Server does not support read-only attach");
+    }
+
+    stream.write_all(&protocol::encode(&Message::Auth { token, read_only }, Framing::Legacy)?).await?;
+
+    match protocol::decode(stream).await.context("Server closed the connection during authentication")? {
+        Message::Authenticated => {
+            debug!("Authenticated.");
+            Ok(negotiated)
+        }
+        Message::Rejected { reason } => bail!("Server rejected the connection: {}", reason),
+        other => bail!("Unexpected message during authentication: {:?}", other),
+    }
+}
+
+/// Open a stream to the session, locally through its Unix socket or remotely over TLS.
+async fn connect(options: &ConnectOptions) -> anyhow::Result<BoxedStream> {
+    if let Some(remote) = &options.remote {
+        debug!("Connecting to {} over TLS.", remote);
+        return transport::connect_tcp(remote, options.tls_ca.as_deref()).await;
+    }
+
+    let sock = socket_path(&options.session)?;
 
     if !sock.exists() {
         anyhow::bail!(
             "No session named '{}' found at {:?}. Use `desktop-tui list` to see active sessions.",
-            session,
+            options.session,
             sock
         );
     }
 
-    let stream = UnixStream::connect(&sock)
-        .await
-        .context("Failed to connect to session socket")?;
+    debug!("Connecting to {:?}.", sock);
+    transport::connect_unix(&sock).await
+}
+
+/// Why a connection to the server ended.
+enum ConnectionEnd {
+    /// The user left (stdin closed or Ctrl+C while read-only) or the server detached us.
+    Detached,
+    /// The server is shutting down, there is nothing to reconnect to.
+    Ended,
+    /// The connection broke, reconnecting may help.
+    Lost(String),
+    /// The server would not attach us, e.g. to a window that does not exist.
+    Refused(String),
+}
+
+/// Connect, run the handshake and start attaching, detaching other clients
+/// first if asked to.
+///
+/// The token is resolved on every call since a restarted local server writes a new one.
+pub async fn open(options: &AttachOptions, detach_others: bool) -> anyhow::Result<(BoxedStream, Negotiated)> {
+    let token = resolve_token(&options.connection)?;
+    let mut stream = connect(&options.connection).await?;
+    let negotiated = handshake(&mut stream, token, options.read_only).await?;
+
+    // Version 1 servers attach implicitly after authentication.
+    if let Some(window) = &options.window {
+        if negotiated.version < 8 {
+            bail!("Server is too old for --window, please upgrade it");
+        }
+        let attach = Message::AttachWindow { identity: local_identity(), window: window.clone() };
+        send(&mut stream, &attach, negotiated.framing()).await?;
+    } else if negotiated.version >= 4 {
+        send(&mut stream, &Message::AttachAs { identity: local_identity(), detach_others }, negotiated.framing()).await?;
+    } else if detach_others {
+        bail!("Server is too old for --detach-others, please upgrade it");
+    } else if negotiated.version >= 2 {
+        send(&mut stream, &Message::Attach, negotiated.framing()).await?;
+    }
+
+    Ok((stream, negotiated))
+}
+
+/// Describe this client to the server.
+fn local_identity() -> ClientIdentity {
+    ClientIdentity {
+        hostname: nix::unistd::gethostname()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        pid: std::process::id(),
+        tty: fs::read_link("/proc/self/fd/0")
+            .ok()
+            .map(|path| path.display().to_string())
+            .filter(|path| path.starts_with("/dev/")),
+    }
+}
 
-    eprintln!("[attach] Connected to session '{}'.", session);
+/// Run a one-shot management request against a session.
+pub async fn control(options: &ConnectOptions, request: ControlRequest) -> anyhow::Result<()> {
+    let token = resolve_token(options)?;
+    let mut stream = connect(options).await?;
+    let negotiated = handshake(&mut stream, token, false).await?;
 
-    // Put the local terminal into raw mode so every keystroke is forwarded.
-    enable_raw_mode().context("Failed to enable raw mode")?;
+    if negotiated.version < request.min_version() {
+        bail!("Server is too old for this command, please upgrade it");
+    }
 
-    let (mut reader, mut writer) = stream.into_split();
+    send(&mut stream, &Message::Control(request), negotiated.framing()).await?;
 
-    // Send initial resize before entering the event loop.
-    if let Ok((cols, rows)) = terminal_size() {
-        let msg = Message::Resize { cols, rows };
-        let encoded = protocol::encode(&msg)?;
-        writer.write_all(&encoded).await?;
+    // Skip pings or output that may arrive before the answer.
+    loop {
+        match protocol::decode(&mut stream).await.context("Server closed the connection")? {
+            Message::Done => return Ok(()),
+            Message::Rejected { reason } => bail!("Server refused the request: {}", reason),
+            _ => {}
+        }
     }
+}
+
+/// `desktop-tui kill`: stop the session and wait for its socket to go away.
+pub async fn kill_session(options: ConnectOptions) -> anyhow::Result<()> {
+    control(&options, ControlRequest::Kill).await?;
+
+    if options.remote.is_none() {
+        let sock = socket_path(&options.session)?;
+        let deadline = Instant::now() + KILL_WAIT;
+        while sock.exists() && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        if sock.exists() {
+            bail!("Session '{}' did not shut down within {:?}", options.session, KILL_WAIT);
+        }
+    }
+
+    println!("Session '{}' killed.", options.session);
+    Ok(())
+}
+
+/// `desktop-tui rename`: give a session a new name.
+pub async fn rename_session(options: ConnectOptions, name: String) -> anyhow::Result<()> {
+    control(&options, ControlRequest::Rename { name: name.clone() }).await?;
+    println!("Session '{}' renamed to '{}'.", options.session, name);
+    Ok(())
+}
 
-    // Task: read from server, write to stdout.
-    let stdout_task = tokio::spawn(async move {
-        let mut stdout = tokio::io::stdout();
+/// `desktop-tui detach-clients`: kick every client attached to a session.
+pub async fn detach_clients(options: ConnectOptions) -> anyhow::Result<()> {
+    control(&options, ControlRequest::DetachClients).await?;
+    println!("Detached all clients from session '{}'.", options.session);
+    Ok(())
+}
+
+/// `desktop-tui serve --group`: have a running session serve another name too.
+pub async fn link_session(options: ConnectOptions, name: String) -> anyhow::Result<()> {
+    control(&options, ControlRequest::Link { name: name.clone() }).await?;
+    println!("Session '{}' linked to '{}', attach with `desktop-tui attach {}`.", name, options.session, name);
+    Ok(())
+}
+
+/// `desktop-tui select-window`: change what the clients of a session name are shown.
+pub async fn select_window(options: ConnectOptions, window: Option<String>) -> anyhow::Result<()> {
+    control(&options, ControlRequest::SelectWindow { window: window.clone() }).await?;
+    match window {
+        Some(window) => println!("Session '{}' now shows window '{}'.", options.session, window),
+        None => println!("Session '{}' now shows the whole desktop.", options.session),
+    }
+    Ok(())
+}
+
+/// `desktop-tui send`: type keys into a session without attaching.
+pub async fn send_keys(options: ConnectOptions, keys: &str, hex: bool) -> anyhow::Result<()> {
+    let data = if hex { decode_hex(keys)? } else { unescape(keys)? };
+    if data.is_empty() {
+        bail!("Nothing to send");
+    }
+
+    control(&options, ControlRequest::SendKeys { data }).await
+}
+
+/// Expand the backslash escapes scripts use to spell control keys.
+fn unescape(keys: &str) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(keys.len());
+    let mut chars = keys.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('e') => bytes.push(0x1b),
+            Some('0') => bytes.push(0),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&digits, 16)
+                    .map_err(|_| anyhow!("Invalid escape '\\x{}', expected two hex digits", digits))?;
+                bytes.push(byte);
+            }
+            Some(other) => bail!("Unknown escape '\\{}'", other),
+            None => bail!("Trailing backslash in keys"),
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Decode hex digits into raw bytes, ignoring whitespace between them.
+fn decode_hex(hex: &str) -> anyhow::Result<Vec<u8>> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        bail!("Hex input has an odd number of digits");
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).unwrap_or("");
+            u8::from_str_radix(pair, 16).map_err(|_| anyhow!("Invalid hex byte '{}'", pair))
+        })
+        .collect()
+}
+
+/// Keep trying to reconnect with exponential backoff.
+///
+/// Each attempt gets [`RECONNECT_TIMEOUT`] to connect and handshake, so a
+/// connect hanging after a suspend or network change is retried.
+///
+/// Returns `None` if the user gave up by pressing Ctrl+C or closing stdin,
+/// otherwise the new connection and what was typed meanwhile.
+async fn reconnect(options: &AttachOptions, input_rx: &mut mpsc::Receiver<LocalInput>) -> Option<(BoxedStream, Negotiated, Vec<LocalInput>)> {
+    let mut delay = RECONNECT_MIN_DELAY;
+    let mut typed = Vec::new();
+
+    loop {
+        // Typing does not push the attempt back.
+        let deadline = tokio::time::Instant::now() + delay;
         loop {
-            match protocol::decode(&mut reader).await {
-                Ok(Message::Data(bytes)) => {
-                    if stdout.write_all(&bytes).await.is_err() {
-                        break;
-                    }
-                    let _ = stdout.flush().await;
-                }
-                Ok(Message::Detach) | Err(_) => break,
-                _ => {}
+            tokio::select! {
+                input = input_rx.recv() => if !keep_typed(options, input, &mut typed) {
+                    return None;
+                },
+                _ = tokio::time::sleep_until(deadline) => break,
             }
         }
-    });
 
-    // Task: read from stdin, send to server.
-    let stdin_task = tokio::spawn(async move {
+        // Others that attached meanwhile are left alone.
+        let attempt = tokio::time::timeout(RECONNECT_TIMEOUT, open(options, false));
+        tokio::pin!(attempt);
+        let result = loop {
+            tokio::select! {
+                result = &mut attempt => break result,
+                input = input_rx.recv() => if !keep_typed(options, input, &mut typed) {
+                    return None;
+                },
+            }
+        };
+
+        let error = match result {
+            Ok(Ok((stream, negotiated))) => return Some((stream, negotiated, typed)),
+            Ok(Err(e)) => format!("{:#}", e),
+            Err(_) => format!("no answer within {:?}", RECONNECT_TIMEOUT),
+        };
+        eprint!("[attach] Reconnect failed: {}, retrying in {:?}.\r\n", error, delay);
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+    }
+}
+
+/// Keep what was typed while reconnecting.
+///
+/// Returns `false` if the user gave up by pressing Ctrl+C or closing stdin.
+fn keep_typed(options: &AttachOptions, input: Option<LocalInput>, typed: &mut Vec<LocalInput>) -> bool {
+    match input {
+        Some(LocalInput::Keys(bytes)) if bytes.contains(&0x03) => false,
+        Some(_) if options.read_only => true,
+        Some(input) => {
+            typed.push(input);
+            true
+        }
+        None => false,
+    }
+}
+
+/// `user@host:session` given to `attach`: a session on another machine,
+/// reached by running `desktop-tui attach` there over SSH.
+pub struct SshTarget {
+    /// `user@host`, as given to ssh.
+    pub destination: String,
+    /// The remote's default session when omitted.
+    pub session: Option<String>,
+}
+
+impl SshTarget {
+    /// Recognize `user@host[:session]`, local session names have no `@`.
+    pub fn parse(target: &str) -> Option<Self> {
+        let (user, rest) = target.split_once('@')?;
+        if user.is_empty() || rest.is_empty() {
+            return None;
+        }
+        let (host, session) = match rest.split_once(':') {
+            Some((host, session)) => (host, Some(session.to_owned()).filter(|session| !session.is_empty())),
+            None => (rest, None),
+        };
+        Some(Self { destination: format!("{}@{}", user, host), session })
+    }
+}
+
+/// Attach to a session on another machine by running `desktop-tui attach`
+/// there through ssh, which also puts the local terminal in raw mode.
+///
+/// With `reconnect`, ssh is started again when the connection drops, while
+/// the remote client keeps its own reconnection to the server.
+pub async fn attach_over_ssh(target: SshTarget, options: AttachOptions) -> anyhow::Result<()> {
+    if options.connection.remote.is_some() || options.connection.tls_ca.is_some() || options.connection.token_file.is_some() {
+        bail!("--remote, --tls-ca and --token-file do not apply to user@host sessions, the remote machine finds its own token");
+    }
+
+    let mut delay = RECONNECT_MIN_DELAY;
+    let mut detach_others = options.detach_others;
+
+    loop {
+        let mut remote = vec![String::from("desktop-tui"), String::from("attach")];
+        remote.extend(target.session.iter().map(|session| shell_quote(session)));
+        if options.read_only {
+            remote.push(String::from("--read-only"));
+        }
+        if options.reconnect {
+            remote.push(String::from("--reconnect"));
+        }
+        if detach_others {
+            remote.push(String::from("--detach-others"));
+        }
+        if options.control {
+            remote.push(String::from("--control"));
+        }
+        if let Some(window) = &options.window {
+            remote.push(String::from("--window"));
+            remote.push(shell_quote(window));
+        }
+
+        // Control mode speaks JSON lines, which a remote terminal would mangle.
+        let tty = if options.control { "-T" } else { "-t" };
+        debug!("Running `ssh {} -- {} {}`.", tty, target.destination, remote.join(" "));
+        // A destination starting with `-` must not be taken for an option.
+        let status = tokio::process::Command::new("ssh")
+            .arg(tty)
+            .arg("--")
+            .arg(&target.destination)
+            .arg(remote.join(" "))
+            .status()
+            .await
+            .context("Failed to run ssh")?;
+
+        match status.code() {
+            Some(0) => return Ok(()),
+            Some(SSH_FAILURE) if options.reconnect => {
+                eprintln!("[attach] SSH connection to {} lost, reconnecting in {:?} (Ctrl+C to give up).", target.destination, delay);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+                // Others that attached meanwhile are left alone.
+                detach_others = false;
+            }
+            Some(SSH_FAILURE) => bail!("SSH connection to {} failed or was lost", target.destination),
+            Some(code) => bail!("desktop-tui attach on {} exited with status {}", target.destination, code),
+            None => bail!("ssh was killed by a signal"),
+        }
+    }
+}
+
+/// Quote `value` for the remote shell ssh runs the command with.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+pub async fn attach(options: AttachOptions) -> anyhow::Result<()> {
+    let (mut stream, mut negotiated) = open(&options, options.detach_others).await?;
+
+    let session = match &options.connection.remote {
+        Some(remote) => format!("{}@{}", options.connection.session, remote),
+        None => options.connection.session.clone(),
+    };
+
+    if options.control {
+        return control::run(stream, &negotiated, &session, options.read_only).await;
+    }
+
+    match options.read_only {
+        true => eprintln!("[attach] Connected to session '{}' (read-only, Ctrl+C to detach).", session),
+        false => eprintln!("[attach] Connected to session '{}'.", session),
+    }
+    if let Some(window) = &options.window {
+        eprintln!("[attach] Showing window '{}' only.", window);
+    }
+
+    // Put the local terminal into raw mode so every keystroke is forwarded.
+    enable_raw_mode().context("Failed to enable raw mode")?;
+    let terminal = TerminalGuard;
+
+    // Have pastes marked so they can be shared as the session's clipboard.
+    let mut stdout = tokio::io::stdout();
+    let _ = stdout.write_all(ENABLE_BRACKETED_PASTE).await;
+    let _ = stdout.flush().await;
+
+    // Task: read from stdin. It outlives individual connections so no
+    // keystroke is lost across a reconnect.
+    let (input_tx, mut input_rx) = mpsc::channel::<LocalInput>(64);
+    tokio::spawn(async move {
         let mut stdin = tokio::io::stdin();
         let mut buf = vec![0u8; 1024];
+        let mut pastes = PasteFilter::default();
         loop {
             match stdin.read(&mut buf).await {
                 Ok(0) | Err(_) => break,
                 Ok(n) => {
-                    let data = buf[..n].to_vec();
-                    let msg = Message::Data(data);
-                    match protocol::encode(&msg) {
-                        Ok(encoded) => {
-                            if writer.write_all(&encoded).await.is_err() {
-                                break;
-                            }
+                    for input in pastes.feed(&buf[..n]) {
+                        if input_tx.send(input).await.is_err() {
+                            return;
                         }
-                        Err(_) => break,
                     }
                 }
             }
         }
     });
 
-    // Wait for either task to finish (client disconnect or server gone).
-    tokio::select! {
-        _ = stdout_task => {}
-        _ = stdin_task => {}
+    let mut winch = signal(SignalKind::window_change()).context("Failed to watch terminal resizes")?;
+    // Typed while reconnecting, sent once connected again.
+    let mut replay = Vec::new();
+
+    loop {
+        let end = run_connection(stream, &negotiated, options.read_only, std::mem::take(&mut replay), &mut input_rx, &mut winch, &mut stdout).await;
+
+        match end {
+            ConnectionEnd::Detached => break,
+            ConnectionEnd::Ended => {
+                eprint!("\r\n[attach] Session '{}' ended.", session);
+                break;
+            }
+            ConnectionEnd::Lost(reason) if options.reconnect => {
+                eprint!("\r\n[attach] Connection lost ({}), reconnecting (Ctrl+C to give up).\r\n", reason);
+                match reconnect(&options, &mut input_rx).await {
+                    Some((new_stream, new_negotiated, typed)) => {
+                        eprint!("[attach] Reconnected to session '{}'.\r\n", session);
+                        stream = new_stream;
+                        negotiated = new_negotiated;
+                        replay = typed;
+                    }
+                    None => break,
+                }
+            }
+            ConnectionEnd::Lost(reason) => {
+                eprint!("\r\n[attach] Connection lost ({}).", reason);
+                break;
+            }
+            ConnectionEnd::Refused(reason) => {
+                eprint!("\r\n[attach] Server refused to attach: {}.", reason);
+                break;
+            }
+        }
     }
 
     // Restore terminal mode before returning.
-    let _ = disable_raw_mode();
+    let _ = stdout.write_all(DISABLE_BRACKETED_PASTE).await;
+    let _ = stdout.flush().await;
+    drop(terminal);
     eprintln!("\r\n[attach] Detached from session '{}'.", session);
 
     Ok(())
 }
 
-pub fn list_sessions() -> anyhow::Result<()> {
+/// Pump one connection: server output to stdout, stdin to the server,
+/// terminal resizes and heartbeats, until it ends.
+async fn run_connection(
+    stream: BoxedStream,
+    negotiated: &Negotiated,
+    read_only: bool,
+    replay: Vec<LocalInput>,
+    input_rx: &mut mpsc::Receiver<LocalInput>,
+    winch: &mut Signal,
+    stdout: &mut Stdout,
+) -> ConnectionEnd {
+    let framing = negotiated.framing();
+    let (reader, mut writer) = tokio::io::split(stream);
+    let (mut server_rx, mut reader_task) = protocol::spawn_reader(reader, framing);
+
+    // The server expects a ping at least every interval, silence means the link is dead.
+    let heartbeat = negotiated.has(protocol::FEATURE_HEARTBEAT);
+    let clipboard = negotiated.has(protocol::FEATURE_CLIPBOARD);
+    let mut liveness_timer = tokio::time::interval(protocol::HEARTBEAT_INTERVAL);
+    let mut last_seen = Instant::now();
+
+    // Send our size first, and again after every reconnect.
+    let mut pending = Vec::new();
+    if let Ok((cols, rows)) = terminal_size() {
+        pending.push(Message::Resize { cols, rows });
+    }
+    for input in replay {
+        forward(input, clipboard, &mut pending);
+    }
+
+    let end = 'connection: loop {
+        for msg in pending.drain(..) {
+            if let Err(e) = send(&mut writer, &msg, framing).await {
+                break 'connection ConnectionEnd::Lost(format!("{:#}", e));
+            }
+        }
+
+        tokio::select! {
+            // Message from server.
+            result = server_rx.recv() => {
+                last_seen = Instant::now();
+                match result {
+                    Some(Message::Data(bytes)) => {
+                        if stdout.write_all(&bytes).await.is_err() {
+                            break ConnectionEnd::Detached;
+                        }
+                        let _ = stdout.flush().await;
+                    }
+                    Some(Message::CompressedData(compressed)) => {
+                        let bytes = match protocol::decompress(&compressed) {
+                            Ok(bytes) => bytes,
+                            Err(e) => break ConnectionEnd::Lost(format!("{:#}", e)),
+                        };
+                        if stdout.write_all(&bytes).await.is_err() {
+                            break ConnectionEnd::Detached;
+                        }
+                        let _ = stdout.flush().await;
+                    }
+                    // Copied in the desktop: hand it to the local terminal's clipboard.
+                    Some(Message::Clipboard(data)) => {
+                        let _ = stdout.write_all(&osc52(&data)).await;
+                        let _ = stdout.flush().await;
+                    }
+                    Some(Message::Ping) => pending.push(Message::Pong),
+                    Some(Message::RequestEnvironment(patterns)) => {
+                        pending.push(Message::Environment(environment::capture(&patterns)));
+                    }
+                    Some(Message::Detach) => break ConnectionEnd::Detached,
+                    Some(Message::Shutdown) => break ConnectionEnd::Ended,
+                    Some(Message::Rejected { reason }) => break ConnectionEnd::Refused(reason),
+                    Some(_) => {}
+                    None => {
+                        let reason = match (&mut reader_task).await {
+                            Ok(Some(e)) if !matches!(e, ProtocolError::Closed) => e.to_string(),
+                            _ => String::from("server closed the connection"),
+                        };
+                        break ConnectionEnd::Lost(reason);
+                    }
+                }
+            }
+
+            // Input from stdin.
+            input = input_rx.recv() => {
+                match input {
+                    // Nothing is forwarded while watching, Ctrl+C leaves.
+                    Some(LocalInput::Keys(bytes)) if read_only => {
+                        if bytes.contains(&0x03) {
+                            break ConnectionEnd::Detached;
+                        }
+                    }
+                    Some(LocalInput::Paste(_)) if read_only => {}
+                    Some(input) => forward(input, clipboard, &mut pending),
+                    None => break ConnectionEnd::Detached,
+                }
+            }
+
+            // Local terminal resized.
+            _ = winch.recv() => {
+                if let Ok((cols, rows)) = terminal_size() {
+                    pending.push(Message::Resize { cols, rows });
+                }
+            }
+
+            _ = liveness_timer.tick(), if heartbeat => {
+                if last_seen.elapsed() > protocol::HEARTBEAT_TIMEOUT {
+                    break ConnectionEnd::Lost(String::from("server stopped responding"));
+                }
+            }
+        }
+    };
+
+    reader_task.abort();
+    end
+}
+
+/// Queue the messages sending `input` to the server.
+fn forward(input: LocalInput, clipboard: bool, pending: &mut Vec<Message>) {
+    match input {
+        LocalInput::Keys(bytes) => pending.push(Message::Data(bytes)),
+        // A paste becomes the session's clipboard and is typed as usual.
+        LocalInput::Paste(bytes) => {
+            if clipboard {
+                pending.push(Message::Clipboard(bytes.clone()));
+            }
+            pending.push(Message::Data(bytes));
+        }
+    }
+}
+
+/// Terminal input, with bracketed pastes told apart from typing.
+enum LocalInput {
+    Keys(Vec<u8>),
+    Paste(Vec<u8>),
+}
+
+/// Splits bracketed pastes out of stdin, even when a paste spans several reads.
+#[derive(Default)]
+struct PasteFilter {
+    buf: Vec<u8>,
+    /// Text of the paste being read, if inside one.
+    paste: Option<Vec<u8>>,
+}
+
+impl PasteFilter {
+    fn feed(&mut self, bytes: &[u8]) -> Vec<LocalInput> {
+        self.buf.extend_from_slice(bytes);
+        let mut inputs = Vec::new();
+
+        loop {
+            match &mut self.paste {
+                None => {
+                    // Keys are passed on at once: holding back a lone ESC
+                    // in case it starts a marker would delay the Escape key.
+                    let Some(start) = find(&self.buf, PASTE_START) else {
+                        if !self.buf.is_empty() {
+                            inputs.push(LocalInput::Keys(std::mem::take(&mut self.buf)));
+                        }
+                        break;
+                    };
+                    if start > 0 {
+                        inputs.push(LocalInput::Keys(self.buf[..start].to_vec()));
+                    }
+                    self.buf.drain(..start + PASTE_START.len());
+                    self.paste = Some(Vec::new());
+                }
+                Some(paste) => {
+                    let Some(end) = find(&self.buf, PASTE_END) else {
+                        // Keep what may be the start of the end marker for the next read.
+                        let keep = (1..PASTE_END.len())
+                            .rev()
+                            .find(|&n| self.buf.ends_with(&PASTE_END[..n]))
+                            .unwrap_or(0);
+                        paste.extend(self.buf.drain(..self.buf.len() - keep));
+                        break;
+                    };
+                    paste.extend_from_slice(&self.buf[..end]);
+                    self.buf.drain(..end + PASTE_END.len());
+                    if let Some(paste) = self.paste.take() {
+                        inputs.push(LocalInput::Paste(paste));
+                    }
+                }
+            }
+        }
+
+        inputs
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Encode and write a single message.
+pub async fn send(writer: &mut (impl AsyncWriteExt + Unpin), msg: &Message, framing: Framing) -> anyhow::Result<()> {
+    writer.write_all(&protocol::encode(msg, framing)?).await?;
+    Ok(())
+}
+
+/// Ask a session for its metadata and attached clients.
+pub async fn query(options: &ConnectOptions) -> anyhow::Result<(SessionInfo, Vec<AttachedClient>)> {
+    let token = resolve_token(options)?;
+    let mut stream = connect(options).await?;
+    let negotiated = handshake(&mut stream, token, false).await?;
+
+    if negotiated.version < 3 {
+        bail!("server is too old to describe itself");
+    }
+
+    send(&mut stream, &Message::Query, negotiated.framing()).await?;
+
+    let info = loop {
+        match protocol::decode(&mut stream).await.context("Server closed the connection")? {
+            Message::Info(info) => break info,
+            Message::Rejected { reason } => bail!("Server refused the request: {}", reason),
+            _ => {}
+        }
+    };
+
+    // Servers before version 4 do not list their clients.
+    if negotiated.version < 4 {
+        return Ok((info, Vec::new()));
+    }
+
+    loop {
+        if let Message::Clients(clients) = protocol::decode(&mut stream).await.context("Server closed the connection")? {
+            return Ok((info, clients));
+        }
+    }
+}
+
+/// One line of `desktop-tui list`.
+#[derive(Serialize)]
+struct Listing {
+    name: String,
+    active: bool,
+    info: Option<SessionInfo>,
+    clients: Vec<AttachedClient>,
+    /// Why a live session could not be described.
+    error: Option<String>,
+    /// Whether the files of a stale session were removed.
+    cleaned: bool,
+}
+
+/// `desktop-tui list`, removing what dead servers left behind with `clean`.
+pub async fn list_sessions(json: bool, clean: bool) -> anyhow::Result<()> {
     let home = std::env::var("HOME").context("HOME env var not set")?;
     let dir = std::path::PathBuf::from(home).join(".local/share/desktop-tui");
 
+    let mut listings = Vec::new();
+    if dir.exists() {
+        let entries = fs::read_dir(&dir).context("Failed to read session directory")?;
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("sock") {
+                continue;
+            }
+
+            let session_name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("<unknown>")
+                .to_owned();
+
+            // Check if the server is actually alive, by its socket or pidfile.
+            let alive = server::session_alive(&dir, &session_name);
+
+            let mut listing = Listing {
+                name: session_name,
+                active: alive,
+                info: None,
+                clients: Vec::new(),
+                error: None,
+                cleaned: false,
+            };
+            if !alive && clean {
+                server::remove_stale_session(&dir, &listing.name)?;
+                listing.cleaned = true;
+            }
+            if alive {
+                let options = ConnectOptions { session: listing.name.clone(), remote: None, tls_ca: None, token_file: None };
+                match tokio::time::timeout(QUERY_TIMEOUT, query(&options)).await {
+                    Ok(Ok((info, clients))) => {
+                        listing.info = Some(info);
+                        listing.clients = clients;
+                    }
+                    Ok(Err(e)) => listing.error = Some(format!("{:#}", e)),
+                    Err(_) => listing.error = Some(String::from("timed out")),
+                }
+            }
+            listings.push(listing);
+        }
+    }
+    listings.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&listings)?);
+        return Ok(());
+    }
+
     if !dir.exists() {
         println!("No sessions found (session directory does not exist).");
         return Ok(());
     }
 
-    let entries = fs::read_dir(&dir).context("Failed to read session directory")?;
+    if listings.is_empty() {
+        println!("No sessions found.");
+    }
 
-    let mut found = false;
-    for entry in entries.filter_map(|e| e.ok()) {
-        let path = entry.path();
-        if path.extension().and_then(|e| e.to_str()) != Some("sock") {
-            continue;
+    for listing in &listings {
+        match (&listing.info, &listing.error) {
+            (Some(info), _) => println!(
+                "  {} (active) pid {}, up {}, {} client(s), {}x{}, {}",
+                listing.name,
+                info.child_pid,
+                format_uptime(info.uptime_secs),
+                info.clients,
+                info.cols,
+                info.rows,
+                info.shortcut_dir
+            ),
+            (None, Some(error)) => println!("  {} (active, no details: {})", listing.name, error),
+            (None, None) if listing.cleaned => println!("  {} (stale, removed)", listing.name),
+            (None, None) => println!("  {} (stale, `list --clean` removes it)", listing.name),
+        }
+
+        for client in &listing.clients {
+            let who = match &client.identity {
+                Some(identity) => identity.to_string(),
+                None => String::from("unidentified client"),
+            };
+            let mode = if client.read_only { ", read-only" } else { "" };
+            println!(
+                "      client {}: {} via {}, attached {}{}",
+                client.id,
+                who,
+                client.peer,
+                format_uptime(client.attached_secs),
+                mode
+            );
         }
+    }
 
-        let session_name = path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("<unknown>");
+    Ok(())
+}
 
-        // Check if socket is actually alive by attempting a connection.
-        let alive = std::os::unix::net::UnixStream::connect(&path).is_ok();
+/// Render an uptime with its two most significant units, e.g. `2h 05m`.
+fn format_uptime(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
 
-        if alive {
-            println!("  {} (active)", session_name);
-            found = true;
-        } else {
-            println!("  {} (stale)", session_name);
-            found = true;
-        }
+    if days > 0 {
+        format!("{}d {:02}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {:02}m", hours, minutes)
+    } else {
+        format!("{}m {:02}s", minutes, secs % 60)
     }
+}
 
-    if !found {
-        println!("No sessions found.");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssh_targets_need_a_user_and_a_host() {
+        let target = SshTarget::parse("me@box:work").unwrap();
+        assert_eq!(target.destination, "me@box");
+        assert_eq!(target.session.as_deref(), Some("work"));
+
+        let target = SshTarget::parse("me@box").unwrap();
+        assert_eq!(target.destination, "me@box");
+        assert_eq!(target.session, None);
+        assert_eq!(SshTarget::parse("me@box:").unwrap().session, None);
+
+        for local in ["work", "@box", "me@", ""] {
+            assert!(SshTarget::parse(local).is_none(), "{:?} is a local session", local);
+        }
     }
 
-    Ok(())
+    #[test]
+    fn shell_quote_survives_quotes_and_metacharacters() {
+        assert_eq!(shell_quote("work"), "'work'");
+        assert_eq!(shell_quote("it's $HOME; rm"), "'it'\\''s $HOME; rm'");
+    }
 }
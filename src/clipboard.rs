@@ -0,0 +1,104 @@
+use crate::config;
+use crate::search_list::{Item, SearchList};
+use chrono::Local;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Text copied in the desktop, newest first. The front entry is what
+/// programs get when they read the clipboard with OSC 52.
+static HISTORY: Mutex<VecDeque<Entry>> = Mutex::new(VecDeque::new());
+
+/// Characters of an entry shown in the clipboard manager.
+const PREVIEW_LENGTH: usize = 60;
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub data: Vec<u8>,
+    pub source: Source,
+    /// Local time of the copy, `HH:MM:SS`.
+    pub time: String,
+}
+
+#[derive(Clone, Debug)]
+pub enum Source {
+    /// A program copied it with OSC 52, in the window with this title.
+    Window(String),
+    /// An attached client shared its clipboard.
+    Client,
+    /// Copied again from the clipboard manager.
+    Manager,
+}
+
+/// Make `data` the clipboard, keeping `config.clipboard_history` entries. Copying
+/// an entry again moves it to the front.
+pub fn record(data: Vec<u8>, source: Source) {
+    let mut history = HISTORY.lock().unwrap();
+    history.retain(|entry| entry.data != data);
+    history.push_front(Entry { data, source, time: Local::now().format("%H:%M:%S").to_string() });
+    history.truncate(config::get().clipboard_history.max(1));
+}
+
+/// The clipboard, if anything was copied.
+pub fn current() -> Option<Vec<u8>> {
+    HISTORY.lock().unwrap().front().map(|entry| entry.data.clone())
+}
+
+pub fn history() -> Vec<Entry> {
+    HISTORY.lock().unwrap().iter().cloned().collect()
+}
+
+/// Bytes typing `data` into a program as a paste. Control characters but
+/// tabs and line breaks are dropped so a copied text cannot run commands or
+/// end the paste early, and the paste is bracketed if the program wants it.
+pub fn paste_sequence(data: &[u8], bracketed: bool) -> Vec<u8> {
+    let text: String = String::from_utf8_lossy(data)
+        .chars()
+        .filter(|&c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+
+    let mut sequence = Vec::new();
+    if bracketed {
+        sequence.extend_from_slice(b"\x1b[200~");
+    }
+    sequence.extend_from_slice(text.as_bytes());
+    if bracketed {
+        sequence.extend_from_slice(b"\x1b[201~");
+    }
+    sequence
+}
+
+/// What to do with the entry picked in the clipboard manager.
+pub enum Choice {
+    Copy(Vec<u8>),
+    Paste(Vec<u8>),
+}
+
+/// Browse and search the clipboard history, then copy or paste an entry.
+pub fn manage(mut entries: Vec<Entry>) -> Option<Choice> {
+    let items = entries
+        .iter()
+        .map(|entry| Item { line: describe(entry), text: String::from_utf8_lossy(&entry.data).into_owned() })
+        .collect();
+    let (button, index) = SearchList::new("Clipboard history", items, &["&Copy", "&Paste"]).show()?;
+    let data = entries.swap_remove(index).data;
+    Some(if button == 0 { Choice::Copy(data) } else { Choice::Paste(data) })
+}
+
+/// One line of the list: when, where from, and the start of the text on a single line.
+fn describe(entry: &Entry) -> String {
+    let source = match &entry.source {
+        Source::Window(title) => title.as_str(),
+        Source::Client => "client",
+        Source::Manager => "history",
+    };
+    let text: String = String::from_utf8_lossy(&entry.data)
+        .chars()
+        .map(|c| match c {
+            '\n' => '⏎',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .take(PREVIEW_LENGTH)
+        .collect();
+    format!("{} [{}] {}", entry.time, source, text)
+}
@@ -0,0 +1,26 @@
+use crate::args::Args;
+use crate::server;
+use clap::CommandFactory;
+use clap_complete::engine::CompletionCandidate;
+use clap_complete::Shell;
+
+/// `desktop-tui completions SHELL`: a completion script for the commands and flags.
+///
+/// Session names are completed too when the script is generated by the binary
+/// at completion time instead, e.g. `source <(COMPLETE=bash desktop-tui)`.
+pub fn print_completions(shell: Shell) {
+    let mut command = Args::command();
+    let name = command.get_name().to_owned();
+    clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+}
+
+/// `desktop-tui man`: the manpage, in roff.
+pub fn print_man() -> anyhow::Result<()> {
+    clap_mangen::Man::new(Args::command()).render(&mut std::io::stdout())?;
+    Ok(())
+}
+
+/// Running sessions, offered wherever a session name is expected.
+pub fn session_candidates() -> Vec<CompletionCandidate> {
+    server::running_sessions().into_iter().map(CompletionCandidate::new).collect()
+}
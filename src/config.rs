@@ -0,0 +1,566 @@
+use anyhow::{anyhow, bail, Context};
+use appcui::backend::Type;
+use appcui::prelude::KeyCode;
+use appcui::system::Themes;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock, RwLock};
+
+/// Config file read instead of `~/.config/desktop-tui/config.toml`.
+pub const CONFIG_ENV: &str = "DESKTOP_TUI_CONFIG";
+pub const THEME_ENV: &str = "DESKTOP_TUI_THEME";
+pub const BACKEND_ENV: &str = "DESKTOP_TUI_BACKEND";
+pub const COLOR_MODE_ENV: &str = "DESKTOP_TUI_COLOR_MODE";
+pub const SHORTCUT_DIR_ENV: &str = "DESKTOP_TUI_SHORTCUT_DIR";
+/// Also set for hooks, so `desktop-tui` commands they run default to their session.
+pub const SESSION_ENV: &str = "DESKTOP_TUI_SESSION";
+
+/// The effective configuration, replaced as a whole on reload while callers
+/// may still hold the previous one.
+static CONFIG: RwLock<Option<Arc<Config>>> = RwLock::new(None);
+
+/// Settings from the config file, overridden by environment variables, then
+/// by command line flags.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub theme: ThemeName,
+    pub backend: Backend,
+    pub color_mode: ColorMode,
+    /// Shortcut directory of `run` and `serve` when none is given.
+    pub shortcut_dir: PathBuf,
+    /// Session of `serve`, `attach` and the management commands when none is given.
+    pub session: String,
+    /// The 16 ANSI colors as `#rrggbb`, black to bright white, used by programs in windows.
+    pub palette: Option<Vec<String>>,
+    /// Copied texts kept in the clipboard manager.
+    pub clipboard_history: usize,
+    /// Seconds without input after which background windows and widgets slow down.
+    pub idle_after: u64,
+    /// Draw anything outside ASCII with a look-alike or `?`, for minimal TTYs.
+    pub ascii_only: bool,
+    /// Open each shortcut's windows where and as large as its last one was.
+    pub remember_geometry: bool,
+    pub keybindings: Keybindings,
+    pub widgets: Widgets,
+    /// Characters the hosting terminal cannot display, each with one to draw instead.
+    pub substitutions: BTreeMap<String, String>,
+    /// File the settings were read from, if any.
+    #[serde(skip)]
+    pub file: Option<PathBuf>,
+    /// Flags applied on top of the file, again whenever it is reloaded.
+    #[serde(skip)]
+    pub overrides: Overrides,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            theme: ThemeName::Default,
+            backend: Backend::Auto,
+            color_mode: ColorMode::Normal,
+            shortcut_dir: PathBuf::from("."),
+            session: String::from("default"),
+            palette: None,
+            clipboard_history: 50,
+            idle_after: 30,
+            ascii_only: false,
+            remember_geometry: true,
+            keybindings: Keybindings::default(),
+            widgets: Widgets::default(),
+            substitutions: BTreeMap::new(),
+            file: None,
+            overrides: Overrides::default(),
+        }
+    }
+}
+
+/// Settings given as command line flags, which win over the config file and
+/// the environment.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Overrides {
+    pub theme: Option<ThemeName>,
+    pub backend: Option<Backend>,
+    pub color_mode: Option<ColorMode>,
+    pub ascii_only: bool,
+}
+
+impl Overrides {
+    pub fn apply(self, config: &mut Config) {
+        if let Some(theme) = self.theme {
+            config.theme = theme;
+        }
+        if let Some(backend) = self.backend {
+            config.backend = backend;
+        }
+        if let Some(color_mode) = self.color_mode {
+            config.color_mode = color_mode;
+        }
+        if self.ascii_only {
+            config.ascii_only = true;
+        }
+        config.overrides = self;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keybindings {
+    /// Terminates the program of the focused window.
+    pub close_window: KeyBinding,
+    /// Opens the clipboard history over the focused window.
+    pub clipboard_manager: KeyBinding,
+    /// Lists the processes of the focused window, to signal one of them.
+    pub processes: KeyBinding,
+    /// Stops the program of the focused window and runs it again in place.
+    pub respawn_window: KeyBinding,
+    /// Opens the focused window's command again in a new window, from the same directory.
+    pub duplicate_window: KeyBinding,
+    /// Sends the text on the focused window's screen to a command run in a new window.
+    pub pipe_window: KeyBinding,
+    /// Searches every action and shortcut to run one.
+    pub command_palette: KeyBinding,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self {
+            close_window: KeyBinding { ctrl: true, alt: false, shift: false, key: KeyCode::C },
+            clipboard_manager: KeyBinding { ctrl: true, alt: true, shift: false, key: KeyCode::V },
+            processes: KeyBinding { ctrl: true, alt: true, shift: false, key: KeyCode::P },
+            respawn_window: KeyBinding { ctrl: true, alt: true, shift: false, key: KeyCode::R },
+            duplicate_window: KeyBinding { ctrl: true, alt: true, shift: false, key: KeyCode::D },
+            pipe_window: KeyBinding { ctrl: true, alt: true, shift: false, key: KeyCode::S },
+            command_palette: KeyBinding { ctrl: true, alt: false, shift: true, key: KeyCode::P },
+        }
+    }
+}
+
+/// Parts of the app bar that can be hidden.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Widgets {
+    pub clock: bool,
+    pub arrange_menu: bool,
+    /// Charge of the first battery.
+    pub battery: bool,
+    /// Address and link state of the interface with the default route.
+    pub network: bool,
+    /// `hostname@session`.
+    pub hostname: bool,
+}
+
+impl Default for Widgets {
+    fn default() -> Self {
+        Self { clock: true, arrange_menu: true, battery: false, network: false, hostname: false }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemeName {
+    Default,
+    DarkGray,
+    Light,
+}
+
+impl ThemeName {
+    pub fn theme(self) -> Themes {
+        match self {
+            ThemeName::Default => Themes::Default,
+            ThemeName::DarkGray => Themes::DarkGray,
+            ThemeName::Light => Themes::Light,
+        }
+    }
+}
+
+impl FromStr for ThemeName {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(ThemeName::Default),
+            "dark-gray" => Ok(ThemeName::DarkGray),
+            "light" => Ok(ThemeName::Light),
+            _ => bail!("unknown theme '{}', expected default, dark-gray or light", s),
+        }
+    }
+}
+
+impl fmt::Display for ThemeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ThemeName::Default => "default",
+            ThemeName::DarkGray => "dark-gray",
+            ThemeName::Light => "light",
+        })
+    }
+}
+
+/// How the desktop is drawn. `auto` lets appcui pick the best backend for
+/// the terminal, the others are an escape hatch when it picks badly.
+/// Termios and ncurses are only offered when built with their feature.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    Auto,
+    Crossterm,
+    #[cfg(feature = "termios")]
+    Termios,
+    #[cfg(feature = "ncurses")]
+    Ncurses,
+}
+
+impl Backend {
+    /// The appcui backend, `None` to let it detect one.
+    pub fn kind(self) -> Option<Type> {
+        match self {
+            Backend::Auto => None,
+            Backend::Crossterm => Some(Type::CrossTerm),
+            #[cfg(feature = "termios")]
+            Backend::Termios => Some(Type::Termios),
+            #[cfg(feature = "ncurses")]
+            Backend::Ncurses => Some(Type::NcursesTerminal),
+        }
+    }
+
+    /// Every backend this build can draw with.
+    fn compiled() -> Vec<Backend> {
+        // Only pushed to with a backend feature enabled.
+        #[allow(unused_mut)]
+        let mut backends = vec![Backend::Auto, Backend::Crossterm];
+        #[cfg(feature = "termios")]
+        backends.push(Backend::Termios);
+        #[cfg(feature = "ncurses")]
+        backends.push(Backend::Ncurses);
+        backends
+    }
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let backends = Backend::compiled();
+        if let Some(backend) = backends.iter().find(|backend| backend.to_string() == s) {
+            return Ok(*backend);
+        }
+
+        let mut names: Vec<String> = backends.iter().map(Backend::to_string).collect();
+        let last = names.pop().unwrap_or_default();
+        bail!("unknown backend '{}', expected {} or {}", s, names.join(", "), last)
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Backend::Auto => "auto",
+            Backend::Crossterm => "crossterm",
+            #[cfg(feature = "termios")]
+            Backend::Termios => "termios",
+            #[cfg(feature = "ncurses")]
+            Backend::Ncurses => "ncurses",
+        })
+    }
+}
+
+/// How the colors programs write are adjusted: remapped for a color
+/// vision deficiency, or with text pushed to black or white when it is
+/// hard to read on its background. The desktop's own widgets are drawn by
+/// appcui in the theme's colors, which these modes leave alone.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorMode {
+    Normal,
+    Deuteranopia,
+    Protanopia,
+    Tritanopia,
+    HighContrast,
+}
+
+impl FromStr for ColorMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(ColorMode::Normal),
+            "deuteranopia" => Ok(ColorMode::Deuteranopia),
+            "protanopia" => Ok(ColorMode::Protanopia),
+            "tritanopia" => Ok(ColorMode::Tritanopia),
+            "high-contrast" => Ok(ColorMode::HighContrast),
+            _ => bail!("unknown color mode '{}', expected normal, deuteranopia, protanopia, tritanopia or high-contrast", s),
+        }
+    }
+}
+
+impl fmt::Display for ColorMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColorMode::Normal => "normal",
+            ColorMode::Deuteranopia => "deuteranopia",
+            ColorMode::Protanopia => "protanopia",
+            ColorMode::Tritanopia => "tritanopia",
+            ColorMode::HighContrast => "high-contrast",
+        })
+    }
+}
+
+/// Keys a binding can name, in the order of their names.
+const LETTER_KEYS: [KeyCode; 26] = [
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G, KeyCode::H, KeyCode::I,
+    KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N, KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R,
+    KeyCode::S, KeyCode::T, KeyCode::U, KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+];
+const DIGIT_KEYS: [KeyCode; 10] = [
+    KeyCode::N0, KeyCode::N1, KeyCode::N2, KeyCode::N3, KeyCode::N4, KeyCode::N5, KeyCode::N6, KeyCode::N7, KeyCode::N8, KeyCode::N9,
+];
+const FUNCTION_KEYS: [KeyCode; 12] = [
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6, KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10,
+    KeyCode::F11, KeyCode::F12,
+];
+
+/// A key with modifiers, written like `ctrl+c` or `alt+shift+f4`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct KeyBinding {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    /// A letter, digit or function key.
+    pub key: KeyCode,
+}
+
+impl FromStr for KeyBinding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key = parts.pop().filter(|key| !key.is_empty()).ok_or_else(|| anyhow!("missing key in '{}'", s))?;
+        let mut binding = KeyBinding { ctrl: false, alt: false, shift: false, key: KeyCode::None };
+
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" => binding.ctrl = true,
+                "alt" => binding.alt = true,
+                "shift" => binding.shift = true,
+                _ => bail!("unknown modifier '{}' in '{}'", modifier, s),
+            }
+        }
+
+        let mut chars = key.chars();
+        binding.key = match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() => LETTER_KEYS[(c.to_ascii_uppercase() as u8 - b'A') as usize],
+            (Some(c), None) if c.is_ascii_digit() => DIGIT_KEYS[(c as u8 - b'0') as usize],
+            _ => match key.to_ascii_uppercase().strip_prefix('F').map(str::parse::<usize>) {
+                Some(Ok(n @ 1..=12)) => FUNCTION_KEYS[n - 1],
+                _ => bail!("unknown key '{}' in '{}', expected a letter, a digit or F1 to F12", key, s),
+            },
+        };
+
+        Ok(binding)
+    }
+}
+
+impl TryFrom<String> for KeyBinding {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<KeyBinding> for String {
+    fn from(binding: KeyBinding) -> Self {
+        binding.to_string()
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (set, name) in [(self.ctrl, "ctrl+"), (self.alt, "alt+"), (self.shift, "shift+")] {
+            if set {
+                f.write_str(name)?;
+            }
+        }
+        let position = |keys: &[KeyCode]| keys.iter().position(|&key| key == self.key);
+        if let Some(index) = position(&LETTER_KEYS) {
+            write!(f, "{}", (b'a' + index as u8) as char)
+        } else if let Some(index) = position(&DIGIT_KEYS) {
+            write!(f, "{}", index)
+        } else if let Some(index) = position(&FUNCTION_KEYS) {
+            write!(f, "f{}", index + 1)
+        } else {
+            write!(f, "{:?}", self.key)
+        }
+    }
+}
+
+impl Config {
+    /// The palette as RGB, if one is configured.
+    pub fn palette(&self) -> anyhow::Result<Option<[(u8, u8, u8); 16]>> {
+        let Some(colors) = &self.palette else { return Ok(None) };
+        if colors.len() != 16 {
+            bail!("palette needs 16 colors, got {}", colors.len());
+        }
+
+        let mut palette = [(0, 0, 0); 16];
+        for (slot, color) in palette.iter_mut().zip(colors) {
+            *slot = parse_color(color).with_context(|| format!("invalid palette color '{}'", color))?;
+        }
+        Ok(Some(palette))
+    }
+
+    /// The substitution table, by character.
+    pub fn substitutions(&self) -> anyhow::Result<HashMap<char, char>> {
+        let single = |s: &str| {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(c),
+                _ => Err(anyhow!("substitutions map one character to one character, got '{}'", s)),
+            }
+        };
+        self.substitutions.iter().map(|(from, to)| Ok((single(from)?, single(to)?))).collect()
+    }
+}
+
+fn parse_color(color: &str) -> anyhow::Result<(u8, u8, u8)> {
+    let hex = color.strip_prefix('#').filter(|hex| hex.len() == 6 && hex.is_ascii()).ok_or_else(|| anyhow!("expected #rrggbb"))?;
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+    Ok((channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// `~/.config/desktop-tui`, honoring `XDG_CONFIG_HOME`.
+pub fn config_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("desktop-tui"))
+}
+
+/// `~/.config/desktop-tui/config.toml`.
+pub fn default_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+/// Read the config file and apply the environment overrides. A file named by
+/// `--config` or `DESKTOP_TUI_CONFIG` must exist, the default one need not.
+pub fn load(path: Option<&Path>) -> anyhow::Result<Config> {
+    let explicit = path.map(Path::to_path_buf).or_else(|| env::var_os(CONFIG_ENV).map(PathBuf::from));
+
+    let mut config = match explicit.clone().or_else(default_path) {
+        Some(file) if explicit.is_some() || file.exists() => {
+            let content = fs::read_to_string(&file).with_context(|| format!("Failed to read config file {:?}", file))?;
+            let mut config: Config = toml::from_str(&content).with_context(|| format!("Invalid config file {:?}", file))?;
+            config.file = Some(file);
+            config
+        }
+        _ => Config::default(),
+    };
+
+    if let Ok(theme) = env::var(THEME_ENV) {
+        config.theme = theme.parse().with_context(|| format!("Invalid {}", THEME_ENV))?;
+    }
+    if let Ok(backend) = env::var(BACKEND_ENV) {
+        config.backend = backend.parse().with_context(|| format!("Invalid {}", BACKEND_ENV))?;
+    }
+    if let Ok(color_mode) = env::var(COLOR_MODE_ENV) {
+        config.color_mode = color_mode.parse().with_context(|| format!("Invalid {}", COLOR_MODE_ENV))?;
+    }
+    apply_session_overrides(&mut config);
+
+    config.palette().with_context(|| format!("Invalid config file {:?}", config.file))?;
+    config.substitutions().with_context(|| format!("Invalid config file {:?}", config.file))?;
+    Ok(config)
+}
+
+/// Like `load`, for commands that only need the session defaults: a broken
+/// config file or variable is reported and the defaults are used instead.
+pub fn load_or_default(path: Option<&Path>) -> Config {
+    load(path).unwrap_or_else(|e| {
+        eprintln!("Ignoring the configuration: {:#}", e);
+        let mut config = Config::default();
+        apply_session_overrides(&mut config);
+        config
+    })
+}
+
+/// The overrides that cannot fail, which pick the session and its shortcuts.
+fn apply_session_overrides(config: &mut Config) {
+    if let Some(dir) = env::var_os(SHORTCUT_DIR_ENV) {
+        config.shortcut_dir = PathBuf::from(dir);
+    }
+    if let Ok(session) = env::var(SESSION_ENV) {
+        config.session = session;
+    }
+}
+
+/// Make the effective configuration visible to the desktop's widgets,
+/// replacing the one they saw so far.
+pub fn init(config: Config) {
+    *CONFIG.write().unwrap() = Some(Arc::new(config));
+}
+
+/// The configuration given to `init`, defaults before that.
+pub fn get() -> Arc<Config> {
+    static DEFAULT: OnceLock<Arc<Config>> = OnceLock::new();
+    let config = CONFIG.read().unwrap().clone();
+    config.unwrap_or_else(|| DEFAULT.get_or_init(|| Arc::new(Config::default())).clone())
+}
+
+/// `desktop-tui config show`.
+pub fn show(config: &Config) -> anyhow::Result<()> {
+    match &config.file {
+        Some(file) => println!("# Read from {:?}, with environment and flag overrides", file),
+        None => println!("# No config file, defaults with environment and flag overrides"),
+    }
+    print!("{}", toml::to_string(config)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keybindings_parse_modifiers_and_keys() {
+        let binding: KeyBinding = "Ctrl+Alt+v".parse().unwrap();
+        assert_eq!(binding, KeyBinding { ctrl: true, alt: true, shift: false, key: KeyCode::V });
+
+        let binding: KeyBinding = "shift + F12".parse().unwrap();
+        assert_eq!(binding, KeyBinding { ctrl: false, alt: false, shift: true, key: KeyCode::F12 });
+
+        let binding: KeyBinding = "alt+1".parse().unwrap();
+        assert_eq!(binding.key, KeyCode::N1);
+    }
+
+    #[test]
+    fn keybindings_display_as_they_are_written() {
+        for written in ["ctrl+c", "alt+shift+f4", "ctrl+alt+0", "p"] {
+            assert_eq!(written.parse::<KeyBinding>().unwrap().to_string(), written);
+        }
+    }
+
+    #[test]
+    fn substitutions_map_single_characters() {
+        let mut config = Config::default();
+        config.substitutions.insert(String::from("─"), String::from("-"));
+        assert_eq!(config.substitutions().unwrap(), HashMap::from([('─', '-')]));
+
+        config.substitutions.insert(String::from("→"), String::from("->"));
+        assert!(config.substitutions().is_err());
+    }
+
+    #[test]
+    fn invalid_keybindings_are_refused() {
+        for invalid in ["", "ctrl+", "meta+c", "ctrl+f13", "ctrl+enter", "ctrl+é"] {
+            assert!(invalid.parse::<KeyBinding>().is_err(), "{:?} was accepted", invalid);
+        }
+    }
+}
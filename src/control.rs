@@ -0,0 +1,171 @@
+use crate::client::send;
+use crate::environment;
+use crate::protocol::{self, Message, Negotiated, WindowEvent};
+use crate::transport::BoxedStream;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Stdout};
+
+/// Read by `attach --control` from stdin, one JSON object per line, e.g.
+/// `{"command":"send-keys","keys":"ls\n"}`.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum ControlCommand {
+    /// Type text into the session, JSON escapes spell control keys.
+    SendKeys { keys: String },
+    /// Report a size for this client, negotiated like a terminal's.
+    Resize { cols: u16, rows: u16 },
+    /// Open a window of the named shortcut.
+    NewWindow { shortcut: String },
+    Detach,
+}
+
+/// Written by `attach --control` to stdout, one JSON object per line, e.g.
+/// `{"event":"window-opened","id":1,"shortcut":"htop","title":"htop"}`.
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum ControlEvent {
+    /// First event, once the session streams.
+    Attached { session: String, version: u32, read_only: bool },
+    /// PTY output, base64 encoded since it need not be UTF-8.
+    Output { data: String },
+    WindowOpened { id: u64, shortcut: String, title: String },
+    TitleChanged { id: u64, title: String },
+    WindowClosed { id: u64 },
+    /// Text copied in the desktop, base64 encoded.
+    Clipboard { data: String },
+    /// A command was not understood or not carried out, the stream goes on.
+    Error { message: String },
+    /// Last event: the client left, was detached or lost the connection.
+    Detached { reason: String },
+}
+
+impl From<WindowEvent> for ControlEvent {
+    fn from(event: WindowEvent) -> Self {
+        match event {
+            WindowEvent::Opened { id, shortcut, title } => ControlEvent::WindowOpened { id, shortcut, title },
+            WindowEvent::TitleChanged { id, title } => ControlEvent::TitleChanged { id, title },
+            WindowEvent::Closed { id } => ControlEvent::WindowClosed { id },
+        }
+    }
+}
+
+/// Drive an attached connection from JSON commands on stdin, reporting
+/// session output and window changes as JSON events on stdout.
+pub async fn run(stream: BoxedStream, negotiated: &Negotiated, session: &str, read_only: bool) -> anyhow::Result<()> {
+    let framing = negotiated.framing();
+    let (reader, mut writer) = tokio::io::split(stream);
+    let (mut server_rx, reader_task) = protocol::spawn_reader(reader, framing);
+    let mut stdout = tokio::io::stdout();
+    let mut commands = BufReader::new(tokio::io::stdin()).lines();
+
+    // Servers before version 5 neither report nor open windows.
+    let windows = negotiated.version >= 5;
+    if windows {
+        send(&mut writer, &Message::WatchWindows, framing).await?;
+    }
+
+    let attached = ControlEvent::Attached { session: session.to_owned(), version: negotiated.version, read_only };
+    emit(&mut stdout, &attached).await?;
+
+    let heartbeat = negotiated.has(protocol::FEATURE_HEARTBEAT);
+    let mut liveness_timer = tokio::time::interval(protocol::HEARTBEAT_INTERVAL);
+    let mut last_seen = Instant::now();
+
+    let reason = loop {
+        tokio::select! {
+            result = server_rx.recv() => {
+                last_seen = Instant::now();
+                let event = match result {
+                    Some(Message::Data(bytes)) => ControlEvent::Output { data: BASE64.encode(bytes) },
+                    Some(Message::CompressedData(compressed)) => match protocol::decompress(&compressed) {
+                        Ok(bytes) => ControlEvent::Output { data: BASE64.encode(bytes) },
+                        Err(e) => break format!("{:#}", e),
+                    },
+                    Some(Message::Window(event)) => event.into(),
+                    Some(Message::Clipboard(data)) => ControlEvent::Clipboard { data: BASE64.encode(data) },
+                    Some(Message::Ping) => {
+                        if send(&mut writer, &Message::Pong, framing).await.is_err() {
+                            break String::from("server closed the connection");
+                        }
+                        continue;
+                    }
+                    Some(Message::RequestEnvironment(patterns)) => {
+                        let answer = Message::Environment(environment::capture(&patterns));
+                        if send(&mut writer, &answer, framing).await.is_err() {
+                            break String::from("server closed the connection");
+                        }
+                        continue;
+                    }
+                    Some(Message::Detach) => break String::from("detached by the server"),
+                    Some(Message::Shutdown) => break String::from("session ended"),
+                    Some(Message::Rejected { reason }) => break format!("refused by the server: {}", reason),
+                    Some(_) => continue,
+                    None => break String::from("server closed the connection"),
+                };
+
+                if emit(&mut stdout, &event).await.is_err() {
+                    break String::from("stdout closed");
+                }
+            }
+
+            line = commands.next_line() => {
+                let Ok(Some(line)) = line else { break String::from("stdin closed") };
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                let message = match serde_json::from_str::<ControlCommand>(&line) {
+                    Ok(ControlCommand::Detach) => {
+                        let _ = send(&mut writer, &Message::Detach, framing).await;
+                        break String::from("detached");
+                    }
+                    Ok(ControlCommand::SendKeys { .. } | ControlCommand::NewWindow { .. }) if read_only => {
+                        Err(String::from("read-only clients cannot change the session"))
+                    }
+                    Ok(ControlCommand::SendKeys { keys }) => Ok(Message::Data(keys.into_bytes())),
+                    Ok(ControlCommand::Resize { cols, rows }) => Ok(Message::Resize { cols, rows }),
+                    Ok(ControlCommand::NewWindow { .. }) if !windows => {
+                        Err(String::from("server is too old to open windows, please upgrade it"))
+                    }
+                    Ok(ControlCommand::NewWindow { shortcut }) => Ok(Message::OpenWindow { shortcut }),
+                    Err(e) => Err(format!("invalid command: {}", e)),
+                };
+
+                match message {
+                    Ok(message) => {
+                        if let Err(e) = send(&mut writer, &message, framing).await {
+                            break format!("{:#}", e);
+                        }
+                    }
+                    Err(message) => {
+                        if emit(&mut stdout, &ControlEvent::Error { message }).await.is_err() {
+                            break String::from("stdout closed");
+                        }
+                    }
+                }
+            }
+
+            _ = liveness_timer.tick(), if heartbeat => {
+                if last_seen.elapsed() > protocol::HEARTBEAT_TIMEOUT {
+                    break String::from("server stopped responding");
+                }
+            }
+        }
+    };
+
+    reader_task.abort();
+    let _ = emit(&mut stdout, &ControlEvent::Detached { reason }).await;
+    Ok(())
+}
+
+/// Write one event as a line of JSON.
+async fn emit(stdout: &mut Stdout, event: &ControlEvent) -> anyhow::Result<()> {
+    let mut line = serde_json::to_string(event)?;
+    line.push('\n');
+    stdout.write_all(line.as_bytes()).await?;
+    stdout.flush().await?;
+    Ok(())
+}
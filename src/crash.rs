@@ -0,0 +1,69 @@
+use crate::server::session_dir;
+use chrono::{Local, SecondsFormat};
+use crossterm::cursor::Show;
+use crossterm::event::{DisableBracketedPaste, DisableMouseCapture};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, LeaveAlternateScreen};
+use std::backtrace::Backtrace;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::panic::PanicHookInfo;
+use std::path::PathBuf;
+
+/// Where panics are recorded, shared by every desktop-tui process of the user.
+pub fn crash_log_path() -> anyhow::Result<PathBuf> {
+    Ok(session_dir()?.join("crash.log"))
+}
+
+/// Record the panic in the crash log before the default hook prints it.
+/// A process owning the terminal gives it back first, so the message lands
+/// on a usable terminal; others must not write escape sequences to theirs.
+pub fn install_panic_hook(owns_terminal: bool) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if owns_terminal {
+            restore_terminal();
+        }
+        let _ = record(info);
+        default_hook(info);
+    }));
+}
+
+/// Undo what the desktop, or a session relayed by a client, may have turned
+/// on: raw mode, the alternate screen, a hidden cursor, mouse reporting and
+/// bracketed paste.
+pub fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(std::io::stdout(), DisableMouseCapture, DisableBracketedPaste, LeaveAlternateScreen, Show);
+}
+
+/// Restores the terminal when dropped, including on an early return or while unwinding.
+pub struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+fn record(info: &PanicHookInfo) -> anyhow::Result<()> {
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(crash_log_path()?)?;
+
+    let args: Vec<String> = std::env::args().collect();
+    writeln!(
+        log,
+        "{} desktop-tui {} (pid {}, `{}`) {}\n{}\n",
+        Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+        env!("CARGO_PKG_VERSION"),
+        std::process::id(),
+        args.join(" "),
+        info,
+        Backtrace::force_capture(),
+    )?;
+    Ok(())
+}
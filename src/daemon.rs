@@ -0,0 +1,117 @@
+use crate::server::session_dir;
+use anyhow::{bail, Context};
+use nix::unistd::{fork, setsid, ForkResult};
+use std::fs::{self, File, OpenOptions};
+use std::io::{PipeWriter, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+/// File holding the pid of a daemonized session's server.
+pub fn pid_path(dir: &Path, session: &str) -> PathBuf {
+    dir.join(format!("{}.pid", session))
+}
+
+/// File a daemonized session's server logs to.
+pub fn log_path(dir: &Path, session: &str) -> PathBuf {
+    dir.join(format!("{}.log", session))
+}
+
+/// Pid recorded in a pidfile, if that process is still alive and runs
+/// desktop-tui: the pid of a crashed daemon may belong to another program by now.
+pub fn running_pid(pid_path: &Path) -> Option<i32> {
+    let pid = fs::read_to_string(pid_path).ok()?.trim().parse().ok()?;
+    // Signal 0 only checks whether the process exists.
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    (alive && runs_desktop_tui(pid)).then_some(pid)
+}
+
+/// Whether `pid` runs a binary named like this one, which still holds after
+/// the binary was upgraded under a running server.
+fn runs_desktop_tui(pid: i32) -> bool {
+    let name = |path: &Path| path.file_name().map(|name| name.to_string_lossy().trim_end_matches(" (deleted)").to_owned());
+    let Ok(exe) = fs::read_link(format!("/proc/{}/exe", pid)) else { return false };
+    std::env::current_exe().is_ok_and(|own| name(&own) == name(&exe))
+}
+
+/// Held by the daemon until it is ready to accept clients.
+///
+/// Dropping it without calling `signal` tells the launching process that
+/// startup failed.
+pub struct Ready {
+    pipe: PipeWriter,
+    pid_path: PathBuf,
+}
+
+impl Ready {
+    /// Write the pidfile and let the launching process exit.
+    pub fn signal(mut self) -> anyhow::Result<()> {
+        let pid = std::process::id();
+        fs::write(&self.pid_path, format!("{}\n", pid))
+            .with_context(|| format!("Failed to write pidfile {:?}", self.pid_path))?;
+        let _ = write!(self.pipe, "{}", pid);
+        Ok(())
+    }
+}
+
+/// Detach `serve` from the launching terminal.
+///
+/// Forks twice with `setsid` in between so the daemon can never reacquire a
+/// controlling terminal, points stdin at /dev/null and stdout/stderr at the
+/// session's log file. The launching process waits until the daemon reports
+/// it is ready (or gives up) and exits; only the daemon returns from this
+/// function.
+///
+/// Must be called before any thread is started: only the calling thread
+/// survives a fork.
+pub fn daemonize(session: &str) -> anyhow::Result<Ready> {
+    let dir = session_dir()?;
+    let pid_path = pid_path(&dir, session);
+    if let Some(pid) = running_pid(&pid_path) {
+        bail!("Session '{}' is already running (pid {})", session, pid);
+    }
+
+    let log_path = log_path(&dir, session);
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(&log_path)
+        .with_context(|| format!("Failed to open log file {:?}", log_path))?;
+
+    let (mut ready_rx, ready_tx) = std::io::pipe()?;
+
+    if let ForkResult::Parent { .. } = unsafe { fork() }.context("fork failed")? {
+        // Only the daemon may hold the write end, so EOF means it is gone.
+        drop(ready_tx);
+
+        let mut pid = String::new();
+        let _ = ready_rx.read_to_string(&mut pid);
+
+        if pid.is_empty() {
+            eprintln!("Session '{}' failed to start, see {:?}", session, log_path);
+            exit(1);
+        }
+
+        println!("Session '{}' started in the background (pid {}), logging to {:?}", session, pid, log_path);
+        exit(0);
+    }
+
+    drop(ready_rx);
+    setsid().context("setsid failed")?;
+
+    if let ForkResult::Parent { .. } = unsafe { fork() }.context("fork failed")? {
+        // Exit at once so that init adopts the daemon.
+        unsafe { libc::_exit(0) };
+    }
+
+    let null = File::open("/dev/null")?;
+    unsafe {
+        libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO);
+        libc::dup2(log.as_raw_fd(), libc::STDOUT_FILENO);
+        libc::dup2(log.as_raw_fd(), libc::STDERR_FILENO);
+    }
+
+    Ok(Ready { pipe: ready_tx, pid_path })
+}
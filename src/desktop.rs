@@ -1,18 +1,40 @@
 use crate::desktop::mydesktop::Commands;
-use crate::shortcut::Shortcut;
+use crate::actions::{self, Action};
+use crate::config;
+use crate::events::{self, DesktopEvent};
+use crate::geometry::{self, Geometries, WindowGeometry};
+use crate::idle;
+use crate::indicators::{self, Indicators};
+use crate::layout::{self, DesktopLayout, WindowLayout};
+use crate::onboarding::{self, WelcomeWindow};
+use crate::palette;
+use crate::plugins::{self, PluginEvent, PluginWidget, Plugins};
+use crate::protocol::WindowEvent;
+use crate::recording;
+use crate::reload::{ConfigWatcher, ReloadNotice};
+use crate::server;
+use crate::pipe::{PipeDialog, PipeRequest};
+use crate::playback::PlaybackWindow;
+use crate::shortcut::{Shortcut, TerminalOptions, WindowOptions};
 use crate::tui_window::TuiWindow;
 use crate::utils::time_to_string;
+use appcui::dialogs::{Location, OpenFileDialogFlags};
 use appcui::prelude::appbar::MenuButton;
 use appcui::prelude::menu::{Command, SingleChoice};
 use appcui::prelude::*;
 use appcui::ui::appbar::Side;
 use std::collections::HashMap;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// How often the clock, indicators and timer hooks are updated.
+const TICK: Duration = Duration::from_millis(2000);
 
 #[Desktop(
     events = [AppBarEvents, MenuEvents, DesktopEvents, TimerEvents],
-    overwrite = OnPaint,
-    commands = [Exit, NoArrange, Cascade, Vertical, Horizontal, Grid, AppVisibilityToggle, OpenApp, CloseApp, AppCommand, None]
+    overwrite = OnPaint+OnKeyPressed,
+    commands = [Exit, PlayRecording, NoArrange, Cascade, Vertical, Horizontal, Grid, AppVisibilityToggle, OpenApp, CloseApp, AppCommand, None]
 )]
 pub struct MyDesktop {
     pub arrange_method: Option<desktop::ArrangeWindowsMethod>,
@@ -23,11 +45,38 @@ pub struct MyDesktop {
     pub app_menu_buttons: Vec<Handle<MenuButton>>,
     pub shortcuts: Vec<Shortcut>,
     pub app_windows: HashMap<usize, Vec<Handle<TuiWindow>>>,
+    /// Id of every open window, to report it once closed.
+    pub window_ids: Vec<(Handle<TuiWindow>, u64)>,
     pub time_label: Handle<appbar::Label>,
+    pub battery_label: Handle<appbar::Label>,
+    pub network_label: Handle<appbar::Label>,
+    pub host_label: Handle<appbar::Label>,
+    /// Indicator texts last shown in the app bar.
+    pub indicators: Indicators,
+    pub config_watcher: ConfigWatcher,
+    /// Where the layout is snapshotted when running inside a session.
+    pub layout_file: Option<PathBuf>,
+    pub shortcut_dir: PathBuf,
+    /// Layout to rebuild on start, when the session is resumed.
+    pub restore: Option<DesktopLayout>,
+    pub saved_layout: Option<DesktopLayout>,
+    /// Where the geometry of each shortcut's last window is kept.
+    pub geometry_file: Option<PathBuf>,
+    pub geometries: Geometries,
+    /// Shortcuts to autostart later, with when.
+    pub pending_autostart: Vec<(Instant, usize)>,
+    /// When the timer is next due for more than autostarting.
+    pub next_tick: Instant,
+    pub plugins: Option<Plugins>,
+    /// Windows opened by plugins, by the id they were given.
+    pub plugin_widgets: HashMap<u64, Handle<PluginWidget>>,
 }
 
 impl MyDesktop {
-    pub fn new(shortcuts: Vec<Shortcut>) -> Self {
+    pub fn new(shortcuts: Vec<Shortcut>, shortcut_dir: PathBuf) -> Self {
+        let geometry_file = server::session_dir().ok().map(|dir| geometry::geometry_path(&dir));
+        let geometries = geometry_file.as_deref().map(geometry::load).unwrap_or_default();
+
         Self {
             base: Desktop::new(),
             arrange_method: None,
@@ -37,12 +86,35 @@ impl MyDesktop {
             app_menues: vec![Handle::None; shortcuts.len()],
             app_menu_buttons: vec![Handle::None; shortcuts.len()],
             app_windows: HashMap::new(),
+            window_ids: Vec::new(),
             time_label: Handle::None,
+            battery_label: Handle::None,
+            network_label: Handle::None,
+            host_label: Handle::None,
+            indicators: indicators::current(),
+            config_watcher: ConfigWatcher::watch_current(),
+            layout_file: None,
+            shortcut_dir,
+            restore: None,
+            saved_layout: None,
+            geometry_file,
+            geometries,
+            pending_autostart: Vec::new(),
+            next_tick: Instant::now() + TICK,
+            plugins: None,
+            plugin_widgets: HashMap::new(),
             shortcuts,
         }
     }
+
+    /// Keep the layout snapshotted in `layout_file`, rebuilding `restore` first if given.
+    pub fn with_layout(mut self, layout_file: PathBuf, restore: Option<DesktopLayout>) -> Self {
+        self.layout_file = Some(layout_file);
+        self.restore = restore;
+        self
+    }
     
-    pub fn create_window(&mut self, index: usize, command: String, args: Vec<String>) -> anyhow::Result<()> {
+    pub fn create_window(&mut self, index: usize, command: String, args: Vec<String>, current_dir: Option<&Path>) -> anyhow::Result<Handle<TuiWindow>> {
         let app_name = self.shortcuts[index].name.clone();
         let window = self.shortcuts[index].window.clone();
         let terminal = self.shortcuts[index].terminal.clone();
@@ -53,42 +125,376 @@ impl MyDesktop {
             args,
             window,
             terminal,
+            current_dir,
         )?;
 
-        let win_handle = self.add_window(window);
+        let win_handle = self.add_tui_window(window, &app_name);
         self.app_windows.entry(index).or_default().push(win_handle);
 
-        Ok(())
+        Ok(win_handle)
     }
-}
 
-impl OnPaint for MyDesktop {
-    fn on_paint(&self, surface: &mut Surface, theme: &Theme) {
-        surface.clear(theme.desktop.character);
+    /// Open a window of a shortcut where its last one was, unless turned off.
+    pub fn open_shortcut(&mut self, index: usize, command: String, args: Vec<String>) -> anyhow::Result<Handle<TuiWindow>> {
+        let win_handle = self.create_window(index, command, args, None)?;
+
+        if config::get().remember_geometry
+            && let Some(saved) = self.geometries.get(&self.shortcuts[index].name).copied()
+            && let Some(window) = self.window_mut(win_handle)
+        {
+            window.set_position(saved.x, saved.y);
+            window.set_size(saved.width, saved.height);
+        }
+
+        Ok(win_handle)
     }
-}
 
-impl DesktopEvents for MyDesktop {
-    fn on_start(&mut self) {
-        let shortcuts = self.shortcuts.clone();
-        let mut desktop_menu = Menu::new();
+    /// Note where the last open window of each shortcut is, and write it down
+    /// when something moved.
+    fn remember_geometries(&mut self) {
+        if !config::get().remember_geometry {
+            return;
+        }
+        let Some(geometry_file) = self.geometry_file.clone() else { return };
+
+        let mut changed = Geometries::new();
+        for (index, handles) in self.app_windows.clone() {
+            let name = self.shortcuts[index].name.clone();
+            let Some(&win_handle) = handles.iter().rev().find(|handle| self.window_mut(**handle).is_some()) else { continue };
+            let Some(window) = self.window_mut(win_handle) else { continue };
+            let position = window.position();
+            let size = window.size();
+            let current = WindowGeometry { x: position.x, y: position.y, width: size.width as u16, height: size.height as u16 };
+
+            if self.geometries.get(&name) != Some(&current) {
+                changed.insert(name, current);
+            }
+        }
+        if changed.is_empty() {
+            return;
+        }
+        self.geometries.extend(changed.clone());
 
-        desktop_menu.add(Command::new("Exit", Key::None, Commands::Exit));
+        // Other desktops share the file, only what changed here is replaced.
+        let mut stored = geometry::load(&geometry_file);
+        stored.extend(changed);
+        if let Err(e) = geometry::save(&geometry_file, &stored) {
+            warn!("Failed to save the window geometry: {:#}", e);
+        }
+    }
 
-        let desktop_menu_button = self.appbar().add(MenuButton::new("Desktop", desktop_menu, 0, Side::Left));
+    /// Add a window, letting clients and plugins know about it.
+    fn add_tui_window(&mut self, window: TuiWindow, shortcut: &str) -> Handle<TuiWindow> {
+        let id = window.id;
+        let title = window.program_title.clone();
 
-        let mut tilling_menu = Menu::new();
+        let win_handle = self.add_window(window);
+        self.window_ids.push((win_handle, id));
 
-        tilling_menu.add(SingleChoice::new("No arrangement", Key::None, Commands::NoArrange, true));
-        tilling_menu.add(SingleChoice::new("Cascade", Key::None, Commands::Cascade, false));
-        tilling_menu.add(SingleChoice::new("Vertical", Key::None, Commands::Vertical, false));
-        tilling_menu.add(SingleChoice::new("Horizontal", Key::None, Commands::Horizontal, false));
-        tilling_menu.add(SingleChoice::new("Grid", Key::None, Commands::Grid, false));
+        debug!("Opened window {} of '{}'.", id, shortcut);
+        events::report(&DesktopEvent::Window(WindowEvent::Opened { id, title: title.clone(), shortcut: shortcut.to_owned() }));
+        self.run_plugins(PluginEvent::WindowOpen { id, title });
 
-        let arrange_menu_button = self.appbar().add(MenuButton::new("Tilling", tilling_menu, 1, Side::Left));
+        win_handle
+    }
 
-        let separator = self.appbar().add(appbar::Separator::new(2, Side::Left));
+    /// Send the focused window's screen to a command asked for, run in a new
+    /// window. It belongs to no shortcut, and is left out of the layout since
+    /// its input is gone once read.
+    fn pipe_window(&mut self) {
+        let Some(win_handle) = self.focused_window() else { return };
+        let Some(window) = self.window_mut(win_handle) else { return };
+        let title = window.program_title.clone();
+        let Some(command) = PipeDialog::new(&title).show() else { return };
+        let Some(window) = self.window_mut(win_handle) else { return };
+        let request = PipeRequest { command, input: window.screen_text().into_bytes() };
+
+        let window = request.program().and_then(|(program, args)| {
+            TuiWindow::new(
+                &format!("| {}", request.command),
+                program,
+                args,
+                WindowOptions { resizable: true, close_button: true, fixed_position: false, size: None },
+                TerminalOptions { padding: Some((0, 0)), background_color: None },
+                None,
+            )
+        });
+
+        match window {
+            Ok(window) => {
+                self.add_tui_window(window, "");
+            }
+            Err(e) => warn!("Failed to pipe into '{}': {:#}", request.command, e),
+        }
+    }
+
+    /// Open the windows clients asked for, by shortcut name.
+    fn open_requested_windows(&mut self) {
+        for name in events::take_window_requests() {
+            self.open_named(&name);
+        }
+    }
+
+    /// Open a window of the shortcut called `name`.
+    fn open_named(&mut self, name: &str) {
+        let Some(index) = self.shortcuts.iter().position(|shortcut| shortcut.name == name) else {
+            warn!("There is no shortcut called '{}' to open.", name);
+            return;
+        };
+
+        let command = self.shortcuts[index].command.clone();
+        let args = self.shortcuts[index].args.clone();
+        if let Err(e) = self.open_shortcut(index, command, args) {
+            warn!("Failed to open a window of '{}': {:#}", name, e);
+        }
+    }
+
+    /// The terminal window with the focus, if any.
+    fn focused_window(&mut self) -> Option<Handle<TuiWindow>> {
+        let handles: Vec<Handle<TuiWindow>> = self.window_ids.iter().map(|(handle, _)| *handle).collect();
+        handles.into_iter().find(|&handle| self.window_mut(handle).is_some_and(|window| window.has_focus()))
+    }
+
+    /// Search every action and shortcut and run the one picked. Actions on a
+    /// window are only offered when a terminal window has the focus.
+    fn command_palette(&mut self) {
+        let focused = self.focused_window();
+        let names = self.shortcuts.iter().map(|shortcut| shortcut.name.clone()).collect();
+        match palette::pick(names, focused.is_some()) {
+            Some(palette::Choice::Action(action)) => self.run_action(action),
+            Some(palette::Choice::Open(name)) => self.open_named(&name),
+            None => {}
+        }
+    }
 
+    /// Open a copy of the focused window: same shortcut and command, started
+    /// from the directory the program is in.
+    fn duplicate_window(&mut self) {
+        let Some(win_handle) = self.focused_window() else { return };
+        let Some(index) = self.app_windows.iter().find(|(_, handles)| handles.contains(&win_handle)).map(|(index, _)| *index) else {
+            return;
+        };
+        let Some(window) = self.window_mut(win_handle) else { return };
+
+        let id = window.id;
+        let command = window.command.clone();
+        let args = window.args.clone();
+        let cwd = window.pid.and_then(layout::process_cwd);
+        if let Err(e) = self.create_window(index, command, args, cwd.as_deref()) {
+            warn!("Failed to duplicate window {}: {:#}", id, e);
+        }
+    }
+
+    /// Write the indicators in their app bar labels.
+    fn show_indicators(&mut self, indicators: Indicators) {
+        let labels = [
+            (self.battery_label, &indicators.battery),
+            (self.network_label, &indicators.network),
+            (self.host_label, &indicators.host),
+        ];
+        for (handle, text) in labels {
+            if let Some(text) = text
+                && let Some(label) = self.appbar().get_mut(handle)
+            {
+                label.set_caption(text);
+            }
+        }
+        self.indicators = indicators;
+    }
+
+    /// Pick an asciicast file and open a window playing it.
+    fn play_recording(&mut self) {
+        let Some(path) = dialogs::open("Play recording", "", Location::Path(self.shortcut_dir.as_path()), None, OpenFileDialogFlags::Icons | OpenFileDialogFlags::CheckIfFileExists) else {
+            return;
+        };
+
+        match recording::load(&path) {
+            Ok(cast) => {
+                let title = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                self.add_window(PlaybackWindow::new(&title, cast));
+            }
+            Err(e) => dialogs::error("Could not play the recording", &format!("{:#}", e)),
+        }
+    }
+
+    /// Report the windows that went away since the last call.
+    fn report_closed_windows(&mut self) {
+        let mut open = Vec::new();
+
+        for (win_handle, id) in std::mem::take(&mut self.window_ids) {
+            if self.window_mut(win_handle).is_some() {
+                open.push((win_handle, id));
+            } else {
+                debug!("Window {} closed.", id);
+                events::report(&DesktopEvent::Window(WindowEvent::Closed { id }));
+            }
+        }
+
+        self.window_ids = open;
+    }
+
+    /// Open the autostart shortcuts without a delay, and schedule the others.
+    fn schedule_autostart(&mut self) {
+        let now = Instant::now();
+        for (index, shortcut) in self.shortcuts.clone().iter().enumerate() {
+            let Some(autostart) = &shortcut.autostart else { continue };
+            if autostart.delay == 0 {
+                self.autostart(index);
+            } else {
+                self.pending_autostart.push((now + Duration::from_secs(autostart.delay), index));
+            }
+        }
+    }
+
+    /// Have the timer fire on the next tick, or sooner when an autostart
+    /// window is due before it.
+    fn restart_timer(&mut self) {
+        let due = self.pending_autostart.iter().map(|(at, _)| *at).fold(self.next_tick, Instant::min);
+        let interval = due.saturating_duration_since(Instant::now()).max(Duration::from_millis(1));
+        if let Some(timer) = self.timer() {
+            timer.start(interval);
+        }
+    }
+
+    /// Open the scheduled autostart windows whose delay passed.
+    fn open_autostart_windows(&mut self) {
+        let now = Instant::now();
+        let (due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_autostart).into_iter().partition(|(at, _)| *at <= now);
+        self.pending_autostart = pending;
+
+        for (_, index) in due {
+            self.autostart(index);
+        }
+    }
+
+    /// Open a window of an autostart shortcut where it asks for.
+    fn autostart(&mut self, index: usize) {
+        let shortcut = self.shortcuts[index].clone();
+        let Some(autostart) = shortcut.autostart else { return };
+
+        let win_handle = match self.open_shortcut(index, shortcut.command, shortcut.args) {
+            Ok(win_handle) => win_handle,
+            Err(e) => {
+                warn!("Failed to autostart '{}': {:#}", shortcut.name, e);
+                return;
+            }
+        };
+
+        if let Some(window) = self.window_mut(win_handle) {
+            if let Some(position) = autostart.position {
+                window.set_position(position.x, position.y);
+            }
+            if let Some(size) = autostart.size {
+                window.set_size(size.width as u16, size.height as u16);
+            }
+        }
+    }
+
+    /// Reopen the windows of a saved layout, skipping shortcuts that no longer exist.
+    fn restore_layout(&mut self, layout: DesktopLayout) {
+        for saved in layout.windows {
+            let Some(index) = self.shortcuts.iter().position(|shortcut| shortcut.name == saved.shortcut) else {
+                continue;
+            };
+
+            let Ok(win_handle) = self.create_window(index, saved.command, saved.args, saved.cwd.as_deref()) else {
+                continue;
+            };
+
+            if let Some(window) = self.window_mut(win_handle) {
+                window.set_position(saved.x, saved.y);
+                window.set_size(saved.width, saved.height);
+                window.set_visible(saved.visible);
+            }
+        }
+    }
+
+    /// Run the plugin callbacks for `event`, then do what they asked for.
+    fn run_plugins(&mut self, event: PluginEvent) {
+        if self.plugins.is_none() {
+            return;
+        }
+
+        let mut windows = Vec::new();
+        for (win_handle, id) in self.window_ids.clone() {
+            if let Some(window) = self.window_mut(win_handle) {
+                windows.push((id, window.program_title.clone()));
+            }
+        }
+
+        let Some(plugins) = &self.plugins else { return };
+        let actions = plugins.emit(event, windows);
+        for action in actions {
+            match action {
+                plugins::Action::CreateWidget { id, title, text } => {
+                    let handle = self.add_window(PluginWidget::new(&title, &text));
+                    self.plugin_widgets.insert(id, handle);
+                }
+                plugins::Action::SetWidgetText { id, text } => {
+                    if let Some(&handle) = self.plugin_widgets.get(&id)
+                        && let Some(widget) = self.window_mut(handle)
+                    {
+                        widget.set_text(&text);
+                    }
+                }
+                plugins::Action::SendKeys { window, keys } => {
+                    let handle = self.window_ids.iter().find(|(_, id)| *id == window).map(|(handle, _)| *handle);
+                    if let Some(handle) = handle
+                        && let Some(window) = self.window_mut(handle)
+                    {
+                        window.send_input(keys.into_bytes());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run an action from its key or the command palette, those on a window
+    /// on the focused one.
+    fn run_action(&mut self, action: Action) {
+        let method = match action {
+            Action::CommandPalette => return self.command_palette(),
+            Action::DuplicateWindow => return self.duplicate_window(),
+            Action::PipeWindow => return self.pipe_window(),
+            Action::ClipboardManager | Action::Processes | Action::RespawnWindow | Action::CloseWindow => {
+                if let Some(win_handle) = self.focused_window()
+                    && let Some(window) = self.window_mut(win_handle)
+                {
+                    window.run_action(action);
+                }
+                return;
+            }
+            Action::PlayRecording => return self.play_recording(),
+            Action::Exit => return self.close_all(),
+            Action::NoArrange => None,
+            Action::Cascade => Some(desktop::ArrangeWindowsMethod::Cascade),
+            Action::Vertical => Some(desktop::ArrangeWindowsMethod::Vertical),
+            Action::Horizontal => Some(desktop::ArrangeWindowsMethod::Horizontal),
+            Action::Grid => Some(desktop::ArrangeWindowsMethod::Grid),
+        };
+
+        self.arrange_method = method;
+        if let Some(method) = method {
+            self.arrange_windows(method);
+        }
+    }
+
+    /// Close every window, then the desktop.
+    fn close_all(&mut self) {
+        for windows in self.app_windows.clone().values() {
+            for window in windows {
+                if let Some(win) = self.window_mut(*window) {
+                    win.close_command();
+                }
+            }
+        }
+
+        self.close()
+    }
+
+    /// Add a menu to the app bar for every shortcut.
+    fn add_app_menus(&mut self) {
+        let shortcuts = self.shortcuts.clone();
         let mut app_menues = vec![Handle::<Menu>::None; shortcuts.len()];
         let mut app_menu_buttons = vec![Handle::<MenuButton>::None; shortcuts.len()];
         for (index, shortcut) in shortcuts.iter().enumerate() {
@@ -110,19 +516,150 @@ impl DesktopEvents for MyDesktop {
             app_menu_buttons[index] = self.appbar().add(MenuButton::with_handle(&shortcut.name, app_menues[index], 2 + index as u8, Side::Left));
         }
 
+        self.app_menues = app_menues;
+        self.app_menu_buttons = app_menu_buttons;
+    }
+
+    /// Start over with the shortcuts picked in the welcome window, if it was answered.
+    fn apply_onboarding(&mut self) {
+        let Some(onboarded) = onboarding::take_choice() else { return };
+
+        self.shortcuts = onboarded.shortcuts;
+        self.shortcut_dir = onboarded.shortcut_dir;
+        self.app_windows.clear();
+        self.add_app_menus();
+
+        if let Some(index) = onboarded.open {
+            let command = self.shortcuts[index].command.clone();
+            let args = self.shortcuts[index].args.clone();
+            if let Err(e) = self.open_shortcut(index, command, args) {
+                warn!("Failed to open a window of '{}': {:#}", self.shortcuts[index].name, e);
+            }
+        }
+    }
+
+    fn layout_snapshot(&mut self) -> DesktopLayout {
+        let mut indexes: Vec<usize> = self.app_windows.keys().copied().collect();
+        indexes.sort();
+
+        let mut windows = Vec::new();
+        for index in indexes {
+            let shortcut = self.shortcuts[index].name.clone();
+
+            for win_handle in self.app_windows[&index].clone() {
+                if let Some(window) = self.window_mut(win_handle) {
+                    let position = window.position();
+                    let size = window.size();
+
+                    windows.push(WindowLayout {
+                        shortcut: shortcut.clone(),
+                        command: window.command.clone(),
+                        args: window.args.clone(),
+                        cwd: window.pid.and_then(layout::process_cwd),
+                        x: position.x,
+                        y: position.y,
+                        width: size.width as u16,
+                        height: size.height as u16,
+                        visible: window.is_visible(),
+                    });
+                }
+            }
+        }
+
+        DesktopLayout {
+            shortcut_dir: self.shortcut_dir.clone(),
+            windows,
+        }
+    }
+
+    /// Write the layout snapshot if it changed since the last one.
+    fn save_layout(&mut self) {
+        let Some(layout_file) = self.layout_file.clone() else {
+            return;
+        };
+
+        let layout = self.layout_snapshot();
+        if self.saved_layout.as_ref() != Some(&layout) && layout::save(&layout_file, &layout).is_ok() {
+            self.saved_layout = Some(layout);
+        }
+    }
+}
+
+impl OnPaint for MyDesktop {
+    fn on_paint(&self, surface: &mut Surface, theme: &Theme) {
+        surface.clear(theme.desktop.character);
+    }
+}
+
+impl OnKeyPressed for MyDesktop {
+    fn on_key_pressed(&mut self, key: Key, _character: char) -> EventProcessStatus {
+        // Windows leave the keys of the palette and desktop actions to the
+        // desktop, which also gets every key on an empty desktop.
+        let Some(action) = actions::bound_to(&config::get().keybindings, key) else {
+            return EventProcessStatus::Ignored;
+        };
+
+        self.run_action(action);
+        EventProcessStatus::Processed
+    }
+}
+
+impl DesktopEvents for MyDesktop {
+    fn on_start(&mut self) {
+        let mut desktop_menu = Menu::new();
+
+        desktop_menu.add(Command::new("Play recording...", Key::None, Commands::PlayRecording));
+        desktop_menu.add(Command::new("Exit", Key::None, Commands::Exit));
+
+        let desktop_menu_button = self.appbar().add(MenuButton::new("Desktop", desktop_menu, 0, Side::Left));
+
+        let mut tilling_menu = Menu::new();
+
+        tilling_menu.add(SingleChoice::new("No arrangement", Key::None, Commands::NoArrange, true));
+        tilling_menu.add(SingleChoice::new("Cascade", Key::None, Commands::Cascade, false));
+        tilling_menu.add(SingleChoice::new("Vertical", Key::None, Commands::Vertical, false));
+        tilling_menu.add(SingleChoice::new("Horizontal", Key::None, Commands::Horizontal, false));
+        tilling_menu.add(SingleChoice::new("Grid", Key::None, Commands::Grid, false));
+
+        let arrange_menu_button = self.appbar().add(MenuButton::new("Tilling", tilling_menu, 1, Side::Left));
+
+        let separator = self.appbar().add(appbar::Separator::new(2, Side::Left));
+
         self.time_label = self.appbar().add(appbar::Label::new(&time_to_string(), 0, Side::Right));
+        self.battery_label = self.appbar().add(appbar::Label::new("", 1, Side::Right));
+        self.network_label = self.appbar().add(appbar::Label::new("", 2, Side::Right));
+        self.host_label = self.appbar().add(appbar::Label::new("", 3, Side::Right));
+        self.show_indicators(indicators::current());
 
         self.desktop_menu = desktop_menu_button;
         self.arrange_menu = arrange_menu_button;
         self.separator = separator;
-        self.app_menues = app_menues;
-        self.app_menu_buttons = app_menu_buttons;
+        self.add_app_menus();
 
         let timer = self.timer().expect("Failed to get timer");
-        timer.start(Duration::from_millis(2000));
+        timer.start(TICK);
+
+        // Before any window opens, so plugins see every `window_open`.
+        self.plugins = Plugins::load();
+        self.run_plugins(PluginEvent::Startup);
+
+        // A resumed session gets its windows back, so autostarting would open them twice.
+        if let Some(layout) = self.restore.take() {
+            self.restore_layout(layout);
+        } else if self.shortcuts.is_empty() {
+            self.add_window(WelcomeWindow::new(self.shortcut_dir.clone()));
+        } else {
+            self.schedule_autostart();
+            self.restart_timer();
+        }
     }
 
     fn on_update_window_count(&mut self, _count: usize) {
+        self.report_closed_windows();
+        self.apply_onboarding();
+        // Opening and closing windows is what a resumed session must not miss.
+        self.save_layout();
+
         let m = self.arrange_method;
 
         if let Some(method) = m {
@@ -133,32 +670,42 @@ impl DesktopEvents for MyDesktop {
 
 impl AppBarEvents for MyDesktop {
     fn on_update(&self, app_bar: &mut AppBar) {
+        let config = config::get();
+        let widgets = &config.widgets;
+
         app_bar.show(self.desktop_menu);
-        app_bar.show(self.arrange_menu);
+        if widgets.arrange_menu {
+            app_bar.show(self.arrange_menu);
+        }
         app_bar.show(self.separator);
 
         for app_menu in self.app_menu_buttons.iter() {
             app_bar.show(*app_menu);
         }
 
-        app_bar.show(self.time_label);
+        if widgets.clock {
+            app_bar.show(self.time_label);
+        }
+
+        // Indicators without a value, like the battery of a desktop computer, stay hidden.
+        let indicators = [
+            (widgets.battery && self.indicators.battery.is_some(), self.battery_label),
+            (widgets.network && self.indicators.network.is_some(), self.network_label),
+            (widgets.hostname && self.indicators.host.is_some(), self.host_label),
+        ];
+        for (shown, label) in indicators {
+            if shown {
+                app_bar.show(label);
+            }
+        }
     }
 }
 
 impl MenuEvents for MyDesktop {
     fn on_command(&mut self, menu: Handle<Menu>, item: Handle<Command>, command: Commands) {
         match command {
-            Commands::Exit => {
-                for windows in self.app_windows.clone().values() {
-                    for window in windows {
-                        if let Some(win) = self.window_mut(*window) {
-                            win.close_command();
-                        }
-                    }
-                }
-
-                self.close()
-            },
+            Commands::Exit => self.close_all(),
+            Commands::PlayRecording => self.play_recording(),
             Commands::OpenApp | Commands::CloseApp | Commands::AppVisibilityToggle | Commands::AppCommand => {
                 let mut app = None;
 
@@ -173,7 +720,7 @@ impl MenuEvents for MyDesktop {
                         Commands::OpenApp => {
                             let cmd = self.shortcuts[index].command.clone();
                             let args = self.shortcuts[index].args.clone();
-                            self.create_window(index, cmd, args).ok();
+                            self.open_shortcut(index, cmd, args).ok();
                         },
                         Commands::AppCommand => {
                             let shortcut = self.shortcuts[index].clone();
@@ -181,7 +728,7 @@ impl MenuEvents for MyDesktop {
 
                             for cmd in shortcut.taskbar.additional_commands {
                                 if item.caption() == cmd.name {
-                                    self.create_window(index, cmd.command, cmd.args).ok();
+                                    self.open_shortcut(index, cmd.command, cmd.args).ok();
                                     break;
                                 }
                             }
@@ -254,11 +801,46 @@ impl MenuEvents for MyDesktop {
 
 impl TimerEvents for MyDesktop {
     fn on_update(&mut self, _: u64) -> EventProcessStatus {
-        let time_label_handle = self.time_label;
-        let time_label = self.appbar().get_mut(time_label_handle).unwrap();
+        self.open_autostart_windows();
 
-        time_label.set_caption(&time_to_string());
+        // Woken up early for an autostart window, the rest waits for its tick.
+        let now = Instant::now();
+        if now < self.next_tick {
+            self.restart_timer();
+            return EventProcessStatus::Processed;
+        }
+        self.next_tick = now + TICK;
+
+        // Nobody looks at an idle desktop, its clock and timer hooks can wait.
+        let idle = idle::is_idle();
+        if !idle {
+            let time_label_handle = self.time_label;
+            let time_label = self.appbar().get_mut(time_label_handle).unwrap();
+
+            time_label.set_caption(&time_to_string());
+
+            let indicators = indicators::current();
+            if indicators != self.indicators {
+                self.show_indicators(indicators);
+            }
+        }
+
+        if let Some(summary) = self.config_watcher.poll() {
+            self.add_window(ReloadNotice::new(&summary));
+        }
+
+        self.open_requested_windows();
+        self.save_layout();
+        self.remember_geometries();
+
+        for (id, title) in plugins::take_bells() {
+            self.run_plugins(PluginEvent::Bell { id, title });
+        }
+        if !idle {
+            self.run_plugins(PluginEvent::Timer);
+        }
 
+        self.restart_timer();
         EventProcessStatus::Processed
     }
 }
\ No newline at end of file
@@ -0,0 +1,115 @@
+use anyhow::{anyhow, bail, Context};
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Variables taken over from attaching clients unless `--update-environment`
+/// says otherwise, like tmux's `update-environment`.
+///
+/// `TERM` and `COLORTERM` are left out: programs in the session draw for
+/// the session's terminal, not for the client's.
+pub const DEFAULT_UPDATE_ENVIRONMENT: [&str; 10] = [
+    "LANG",
+    "LANGUAGE",
+    "LC_*",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+    "SSH_AUTH_SOCK",
+    "SSH_AGENT_PID",
+    "SSH_CONNECTION",
+    "KRB5CCNAME",
+];
+
+/// `TERM` given to a child started without one, e.g. by a service manager.
+pub const DEFAULT_TERM: &str = "xterm-256color";
+
+/// Names the file the served child can source for the latest client environment.
+pub const ENV_FILE_ENV: &str = "DESKTOP_TUI_ENV_FILE";
+
+/// A variable set for the served child, written `NAME=VALUE` on the command line.
+#[derive(Clone, Debug)]
+pub struct EnvVar {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for EnvVar {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s.split_once('=').ok_or_else(|| anyhow!("expected NAME=VALUE"))?;
+
+        if !is_valid_name(name) {
+            bail!("'{}' is not a valid variable name", name);
+        }
+
+        Ok(EnvVar { name: name.to_owned(), value: value.to_owned() })
+    }
+}
+
+/// Whether `name` can be exported by a shell.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Whether `name` is selected by `pattern`, an exact name or a prefix followed by `*`.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    }
+}
+
+/// This process's variables selected by any of `patterns`.
+pub fn capture(patterns: &[String]) -> Vec<(String, String)> {
+    std::env::vars()
+        .filter(|(name, _)| patterns.iter().any(|pattern| matches(pattern, name)))
+        .collect()
+}
+
+/// Environment file of the server with this pid.
+///
+/// Like the layout snapshot it is not named after the session, so a rename
+/// does not move it from under the child.
+pub fn env_file_path(dir: &Path, server_pid: u32) -> PathBuf {
+    dir.join(format!("env-{}.sh", server_pid))
+}
+
+/// Write variables as `export` lines for a shell to source, and the removed
+/// ones as `unset` lines, atomically.
+pub fn save(path: &Path, vars: &BTreeMap<String, Option<String>>) -> anyhow::Result<()> {
+    let content: String = vars
+        .iter()
+        .map(|(name, value)| match value {
+            Some(value) => format!("export {}='{}'\n", name, value.replace('\'', "'\\''")),
+            None => format!("unset {}\n", name),
+        })
+        .collect();
+
+    let tmp_path = path.with_extension("sh.tmp");
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// `desktop-tui exec`: become `command` with `set` added to the environment
+/// and `unset` removed from it. Windows start programs through it to apply
+/// the variables taken over from clients.
+pub fn exec(set: &[EnvVar], unset: &[String], command: &[String]) -> anyhow::Result<()> {
+    let (program, args) = command.split_first().context("No command to run")?;
+    let mut cmd = std::process::Command::new(program);
+    cmd.args(args);
+    for name in unset {
+        cmd.env_remove(name);
+    }
+    cmd.envs(set.iter().map(|var| (&var.name, &var.value)));
+
+    // Only returns on failure.
+    let e = cmd.exec();
+    Err(e).with_context(|| format!("Failed to run {}", program))
+}
@@ -0,0 +1,208 @@
+use crate::clipboard::{self, Source};
+use crate::idle;
+use crate::indicators;
+use crate::protocol::WindowEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::FromRawFd;
+use std::os::unix::net::UnixStream as StdUnixStream;
+use std::sync::{Mutex, OnceLock};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::{mpsc, watch};
+
+/// Names the descriptor a served desktop talks to its server on.
+pub const EVENT_FD_ENV: &str = "DESKTOP_TUI_EVENT_FD";
+
+/// Descriptor the server hands the event socket to its desktop on.
+pub const EVENT_FD: i32 = 3;
+
+/// Something that happened inside the desktop the server cannot see in the
+/// PTY output, sent as one JSON object per line.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum DesktopEvent {
+    /// A window without focus rang the bell.
+    Bell { window: String },
+    /// A program copied to the clipboard with OSC 52.
+    Clipboard { data: Vec<u8> },
+    /// A window was opened, closed or retitled.
+    Window(WindowEvent),
+    /// Output of a mirrored window's program, as read from its PTY.
+    WindowOutput { id: u64, data: Vec<u8> },
+}
+
+/// Sent by the server to its desktop, one JSON object per line.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum DesktopCommand {
+    /// An attached client pasted, programs asking with OSC 52 get this.
+    SetClipboard { data: Vec<u8> },
+    /// A control-mode client asked for a window of this shortcut.
+    OpenWindow { shortcut: String },
+    /// An attaching client's environment, for programs started from now on,
+    /// `None` for the variables it does not have.
+    UpdateEnvironment { vars: Vec<(String, Option<String>)> },
+    /// Clients attached to this window alone, report its output. Its PTY
+    /// takes their size, or keeps the window's without one.
+    MirrorWindow { id: u64, size: Option<(u16, u16)> },
+    /// The last client attached to this window left.
+    UnmirrorWindow { id: u64 },
+    /// Keys typed by a client attached to this window alone.
+    WindowInput { id: u64, data: Vec<u8> },
+    /// Make the program of this window draw itself again.
+    RedrawWindow { id: u64 },
+    /// Clients now attached to the session, the desktop idles without any.
+    AttachedClients { count: usize },
+    /// The session goes by this name now.
+    Renamed { name: String },
+}
+
+/// What the clients attached to a single window want from it.
+#[derive(Default, Debug)]
+pub struct Mirror {
+    pub size: Option<(u16, u16)>,
+    pub input: Vec<Vec<u8>>,
+    pub redraw: bool,
+}
+
+/// Shortcuts clients asked to open, until the desktop gets to them.
+static WINDOW_REQUESTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Variables taken over from attaching clients.
+static ENVIRONMENT: Mutex<BTreeMap<String, Option<String>>> = Mutex::new(BTreeMap::new());
+
+/// Mirrored windows by id, until the window gets to their input.
+static MIRRORS: Mutex<BTreeMap<u64, Mirror>> = Mutex::new(BTreeMap::new());
+
+/// Desktop side of the event socket, when running inside a session.
+fn channel() -> Option<&'static Mutex<StdUnixStream>> {
+    static CHANNEL: OnceLock<Option<Mutex<StdUnixStream>>> = OnceLock::new();
+
+    CHANNEL
+        .get_or_init(|| {
+            let fd = std::env::var(EVENT_FD_ENV).ok()?.parse().ok()?;
+            let stream = unsafe { StdUnixStream::from_raw_fd(fd) };
+
+            // Programs run in windows must not get a socket to the server:
+            // close it on exec and drop the variable pointing at it.
+            // SAFETY: `connect` gets here first, at startup, before any thread
+            // of the desktop reads the environment.
+            unsafe {
+                libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                std::env::remove_var(EVENT_FD_ENV);
+            }
+
+            // Commands are rare and tiny, a plain thread is enough.
+            let reader = stream.try_clone().ok()?;
+            std::thread::spawn(move || {
+                for line in BufReader::new(reader).lines() {
+                    let Ok(line) = line else { break };
+                    match serde_json::from_str(&line) {
+                        Ok(DesktopCommand::SetClipboard { data }) => clipboard::record(data, Source::Client),
+                        Ok(DesktopCommand::OpenWindow { shortcut }) => WINDOW_REQUESTS.lock().unwrap().push(shortcut),
+                        Ok(DesktopCommand::UpdateEnvironment { vars }) => ENVIRONMENT.lock().unwrap().extend(vars),
+                        Ok(DesktopCommand::MirrorWindow { id, size }) => MIRRORS.lock().unwrap().entry(id).or_default().size = size,
+                        Ok(DesktopCommand::UnmirrorWindow { id }) => {
+                            MIRRORS.lock().unwrap().remove(&id);
+                        }
+                        Ok(DesktopCommand::WindowInput { id, data }) => {
+                            if let Some(mirror) = MIRRORS.lock().unwrap().get_mut(&id) {
+                                mirror.input.push(data);
+                            }
+                        }
+                        Ok(DesktopCommand::AttachedClients { count }) => idle::set_attached_clients(count),
+                        Ok(DesktopCommand::Renamed { name }) => indicators::set_session(&name),
+                        Ok(DesktopCommand::RedrawWindow { id }) => {
+                            if let Some(mirror) = MIRRORS.lock().unwrap().get_mut(&id) {
+                                mirror.redraw = true;
+                            }
+                        }
+                        Err(_) => {}
+                    }
+                }
+            });
+
+            Some(Mutex::new(stream))
+        })
+        .as_ref()
+}
+
+/// Start listening to the server, when running inside a session.
+pub fn connect() {
+    channel();
+}
+
+/// Tell the server about an event, when running inside a session.
+pub fn report(event: &DesktopEvent) {
+    if let Some(channel) = channel()
+        && let Ok(line) = serde_json::to_string(event)
+    {
+        let _ = writeln!(channel.lock().unwrap(), "{}", line);
+    }
+}
+
+/// Variables windows opened from now on should get on top of the desktop's
+/// own, `None` for those to remove from it.
+pub fn session_environment() -> Vec<(String, Option<String>)> {
+    ENVIRONMENT.lock().unwrap().iter().map(|(name, value)| (name.clone(), value.clone())).collect()
+}
+
+/// Whether clients are attached to this window alone, with their size and
+/// what they asked for since the last call.
+pub fn take_mirror(id: u64) -> Option<Mirror> {
+    let mut mirrors = MIRRORS.lock().unwrap();
+    let mirror = mirrors.get_mut(&id)?;
+    Some(Mirror {
+        size: mirror.size,
+        input: std::mem::take(&mut mirror.input),
+        redraw: std::mem::take(&mut mirror.redraw),
+    })
+}
+
+/// Shortcuts the server asked to open since the last call.
+pub fn take_window_requests() -> Vec<String> {
+    std::mem::take(&mut *WINDOW_REQUESTS.lock().unwrap())
+}
+
+/// Server side of the event socket: events decoded from the desktop, a
+/// sender for commands to it, and the number of attached clients to tell it.
+/// The event channel closes with the desktop.
+pub fn spawn_channel(stream: UnixStream) -> (mpsc::Receiver<DesktopEvent>, mpsc::Sender<DesktopCommand>, watch::Sender<usize>) {
+    let (reader, mut writer) = stream.into_split();
+    let (event_tx, event_rx) = mpsc::channel(16);
+    let (command_tx, mut command_rx) = mpsc::channel::<DesktopCommand>(16);
+    let (attached_tx, mut attached_rx) = watch::channel(0);
+
+    tokio::spawn(async move {
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            // Lines from a newer or older desktop may not decode, skip them.
+            if let Ok(event) = serde_json::from_str(&line)
+                && event_tx.send(event).await.is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        loop {
+            let command = tokio::select! {
+                command = command_rx.recv() => match command {
+                    Some(command) => command,
+                    None => break,
+                },
+                // Never dropped, unlike a command when the channel is full;
+                // counts changing in a burst are sent once.
+                Ok(()) = attached_rx.changed() => DesktopCommand::AttachedClients { count: *attached_rx.borrow_and_update() },
+            };
+            let Ok(mut line) = serde_json::to_string(&command) else { continue };
+            line.push('\n');
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    (event_rx, command_tx, attached_tx)
+}
@@ -0,0 +1,51 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Numbers the temporary files of this process's saves.
+static NEXT_SAVE: AtomicU64 = AtomicU64::new(1);
+
+/// Where and how large the last window of a shortcut was.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Geometry of every shortcut's last window, by shortcut name.
+pub type Geometries = BTreeMap<String, WindowGeometry>;
+
+/// Shared by every desktop, in or out of a session.
+pub fn geometry_path(dir: &Path) -> PathBuf {
+    dir.join("geometry.toml")
+}
+
+/// Remembered geometries, none when the file is missing or unreadable.
+pub fn load(path: &Path) -> Geometries {
+    fs::read_to_string(path).ok().and_then(|content| toml::from_str(&content).ok()).unwrap_or_default()
+}
+
+/// Write the geometries atomically so a reader never sees half of them.
+/// Every desktop shares the file, so each save goes through a temporary
+/// file of its own.
+pub fn save(path: &Path, geometries: &Geometries) -> anyhow::Result<()> {
+    let tmp_path = path.with_extension(format!("toml.{}-{}.tmp", std::process::id(), NEXT_SAVE.fetch_add(1, Ordering::Relaxed)));
+    let write = || -> anyhow::Result<()> {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&tmp_path)?;
+        file.write_all(toml::to_string(geometries)?.as_bytes())?;
+        Ok(())
+    };
+    let written = write().with_context(|| format!("Failed to write {:?}", tmp_path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    written?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
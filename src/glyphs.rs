@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+/// Replacements applied when the screen is flushed, for terminals missing glyphs.
+struct Substitutions {
+    /// From the config, tried first.
+    table: HashMap<char, char>,
+    /// Replace anything outside ASCII, with `ascii_fallback` or `?`.
+    ascii_only: bool,
+}
+
+static SUBSTITUTIONS: RwLock<Option<Substitutions>> = RwLock::new(None);
+
+/// Set while there is anything to substitute, sparing the lock otherwise.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Use the configured substitutions, on top of the built-in ASCII fallbacks if `ascii_only`.
+pub fn init(table: HashMap<char, char>, ascii_only: bool) {
+    ACTIVE.store(ascii_only || !table.is_empty(), Ordering::Relaxed);
+    *SUBSTITUTIONS.write().unwrap() = Some(Substitutions { table, ascii_only });
+}
+
+/// The character to draw in place of `c`.
+pub fn substitute(c: char) -> char {
+    if c.is_ascii() || !ACTIVE.load(Ordering::Relaxed) {
+        return c;
+    }
+    let substitutions = SUBSTITUTIONS.read().unwrap();
+    let Some(substitutions) = substitutions.as_ref() else { return c };
+    if let Some(&replacement) = substitutions.table.get(&c) {
+        return replacement;
+    }
+    match substitutions.ascii_only {
+        true => ascii_fallback(c).unwrap_or('?'),
+        false => c,
+    }
+}
+
+/// Closest ASCII look of box drawing, blocks, arrows and typographic marks.
+fn ascii_fallback(c: char) -> Option<char> {
+    Some(match c {
+        // Box drawing: lines, corners and junctions, light, heavy and double.
+        '─' | '━' | '═' | '╌' | '╍' | '┄' | '┅' | '┈' | '┉' | '╼' | '╾' => '-',
+        '│' | '┃' | '║' | '╎' | '╏' | '┆' | '┇' | '┊' | '┋' | '╽' | '╿' => '|',
+        '\u{250C}'..='\u{254B}' | '\u{2552}'..='\u{256C}' => '+',
+        '╭' | '╮' | '╯' | '╰' => '+',
+        '╱' => '/',
+        '╲' => '\\',
+        '╳' => 'X',
+        // Blocks and shades.
+        '█' | '▓' | '▉' | '▊' | '▋' | '▌' | '▍' | '▎' | '▏' | '▐' => '#',
+        '▒' => ':',
+        '░' => '.',
+        '▀' | '▔' => '"',
+        '▁' | '▂' | '▃' | '▄' | '▅' | '▆' | '▇' => '_',
+        '■' | '□' | '▪' | '▫' => '#',
+        // Arrows and triangles.
+        '←' | '◀' | '◂' | '◄' => '<',
+        '→' | '▶' | '▸' | '►' => '>',
+        '↑' | '▲' | '▴' => '^',
+        '↓' | '▼' | '▾' => 'v',
+        // Marks.
+        '•' | '●' | '○' | '◦' | '·' | '∙' => '*',
+        '✓' | '✔' => 'v',
+        '✗' | '✘' | '×' => 'x',
+        '…' => '.',
+        '‘' | '’' | '′' => '\'',
+        '“' | '”' | '″' => '"',
+        '–' | '—' | '−' => '-',
+        '\u{a0}' => ' ',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_substitutions_win_over_ascii_fallbacks() {
+        init(HashMap::from([('─', '=')]), true);
+        assert_eq!(substitute('─'), '=');
+        assert_eq!(substitute('│'), '|');
+        assert_eq!(substitute('漢'), '?');
+        assert_eq!(substitute('a'), 'a');
+
+        init(HashMap::new(), false);
+        assert_eq!(substitute('─'), '─');
+    }
+}
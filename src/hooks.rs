@@ -0,0 +1,98 @@
+use anyhow::anyhow;
+use std::process::Stdio;
+use std::str::FromStr;
+use tracing::warn;
+
+/// Session events a hook can be attached to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookEvent {
+    SessionCreated,
+    ClientAttached,
+    ClientDetached,
+    ChildExited,
+    BellInBackgroundWindow,
+}
+
+impl HookEvent {
+    const ALL: [HookEvent; 5] = [
+        HookEvent::SessionCreated,
+        HookEvent::ClientAttached,
+        HookEvent::ClientDetached,
+        HookEvent::ChildExited,
+        HookEvent::BellInBackgroundWindow,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::SessionCreated => "session-created",
+            HookEvent::ClientAttached => "client-attached",
+            HookEvent::ClientDetached => "client-detached",
+            HookEvent::ChildExited => "child-exited",
+            HookEvent::BellInBackgroundWindow => "bell-in-background-window",
+        }
+    }
+}
+
+/// A shell command run when an event happens, written `EVENT=COMMAND` on the command line.
+#[derive(Clone, Debug)]
+pub struct Hook {
+    pub event: HookEvent,
+    pub command: String,
+}
+
+impl FromStr for Hook {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, command) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected EVENT=COMMAND"))?;
+
+        let event = HookEvent::ALL
+            .into_iter()
+            .find(|event| event.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = HookEvent::ALL.iter().map(|event| event.name()).collect();
+                anyhow!("unknown event '{}', expected one of: {}", name, names.join(", "))
+            })?;
+
+        if command.trim().is_empty() {
+            return Err(anyhow!("empty command for event '{}'", name));
+        }
+
+        Ok(Hook { event, command: command.to_owned() })
+    }
+}
+
+/// Run every hook registered for `event` with `sh -c`, without waiting for them.
+///
+/// The event is described by `DESKTOP_TUI_EVENT` plus the given variables.
+/// Hooks inherit the server's stdout and stderr, so their output ends up in
+/// the session log.
+pub fn run(hooks: &[Hook], event: HookEvent, env: &[(&str, String)]) {
+    for hook in hooks.iter().filter(|hook| hook.event == event) {
+        let spawned = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(&hook.command)
+            .env("DESKTOP_TUI_EVENT", event.name())
+            .envs(env.iter().map(|(key, value)| (*key, value)))
+            .stdin(Stdio::null())
+            .spawn();
+
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                warn!("Failed to run {} hook: {}", event.name(), e);
+                continue;
+            }
+        };
+
+        tokio::spawn(async move {
+            if let Ok(status) = child.wait().await
+                && !status.success()
+            {
+                warn!("{} hook failed ({}).", event.name(), status);
+            }
+        });
+    }
+}
@@ -0,0 +1,58 @@
+use crate::config;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// How often windows read their program's output while someone is using the desktop.
+pub const ACTIVE_POLL: Duration = Duration::from_millis(25);
+/// For windows in the background of an idle desktop.
+pub const IDLE_POLL: Duration = Duration::from_millis(250);
+/// For quiet windows of a session no client is attached to.
+pub const DETACHED_POLL: Duration = Duration::from_millis(1000);
+
+/// Milliseconds from `start()` to the last input.
+static LAST_INPUT: AtomicU64 = AtomicU64::new(0);
+/// Set while the desktop runs in a session without attached clients.
+static DETACHED: AtomicBool = AtomicBool::new(false);
+
+fn start() -> Instant {
+    static START: OnceLock<Instant> = OnceLock::new();
+    *START.get_or_init(Instant::now)
+}
+
+/// Someone typed into the desktop.
+pub fn touch() {
+    LAST_INPUT.store(start().elapsed().as_millis() as u64, Ordering::Relaxed);
+}
+
+/// The server tells how many clients look at the desktop.
+pub fn set_attached_clients(count: usize) {
+    DETACHED.store(count == 0, Ordering::Relaxed);
+    if count > 0 {
+        touch();
+    }
+}
+
+pub fn is_detached() -> bool {
+    DETACHED.load(Ordering::Relaxed)
+}
+
+/// Whether nobody typed for `config.idle_after` seconds, or nobody is attached.
+pub fn is_idle() -> bool {
+    let since_input = (start().elapsed().as_millis() as u64).saturating_sub(LAST_INPUT.load(Ordering::Relaxed));
+    is_detached() || since_input >= config::get().idle_after * 1000
+}
+
+/// How often a window should read its program's output. Windows that just got
+/// some keep the fast pace so their output never piles up.
+pub fn poll_interval(focused: bool, busy: bool) -> Duration {
+    if busy {
+        ACTIVE_POLL
+    } else if is_detached() {
+        DETACHED_POLL
+    } else if is_idle() && !focused {
+        IDLE_POLL
+    } else {
+        ACTIVE_POLL
+    }
+}
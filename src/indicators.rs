@@ -0,0 +1,112 @@
+use crate::config;
+use crate::idle;
+use nix::ifaddrs::getifaddrs;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How often the background task reads the battery and network state.
+const REFRESH: Duration = Duration::from_secs(10);
+
+/// Latest app bar texts, written by the background task and read by the desktop's timer.
+static INDICATORS: Mutex<Indicators> = Mutex::new(Indicators { battery: None, network: None, host: None });
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Indicators {
+    /// e.g. `BAT 83%+`, `None` without a battery.
+    pub battery: Option<String>,
+    /// e.g. `eth0 192.168.1.10` or `wlan0 down`, `None` without a default route.
+    pub network: Option<String>,
+    /// `hostname@session`, or only the hostname outside a session.
+    pub host: Option<String>,
+}
+
+/// Start refreshing the indicators, those turned off in the config are skipped
+/// until a reload turns them on.
+pub fn start(session: Option<String>) {
+    INDICATORS.lock().unwrap().host = Some(host(session.as_deref()));
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REFRESH);
+        loop {
+            interval.tick().await;
+            // Nobody looks at an idle desktop.
+            if idle::is_idle() {
+                continue;
+            }
+            let config = config::get();
+            let widgets = &config.widgets;
+            let battery = if widgets.battery { battery() } else { None };
+            let network = if widgets.network { network() } else { None };
+
+            let mut indicators = INDICATORS.lock().unwrap();
+            indicators.battery = battery;
+            indicators.network = network;
+        }
+    });
+}
+
+/// Show the session's new name after a rename.
+pub fn set_session(session: &str) {
+    INDICATORS.lock().unwrap().host = Some(host(Some(session)));
+}
+
+fn host(session: Option<&str>) -> String {
+    let hostname = nix::unistd::gethostname().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    match session {
+        Some(session) => format!("{}@{}", hostname, session),
+        None => hostname,
+    }
+}
+
+pub fn current() -> Indicators {
+    INDICATORS.lock().unwrap().clone()
+}
+
+/// Charge of the first battery in sysfs, `+` while charging.
+fn battery() -> Option<String> {
+    let supplies = fs::read_dir("/sys/class/power_supply").ok()?;
+    for supply in supplies.flatten() {
+        let path = supply.path();
+        if read(&path.join("type")).as_deref() != Some("Battery") {
+            continue;
+        }
+        let Some(capacity) = read(&path.join("capacity")) else { continue };
+        let charging = read(&path.join("status")).as_deref() == Some("Charging");
+        return Some(format!("BAT {}%{}", capacity, if charging { "+" } else { "" }));
+    }
+    None
+}
+
+/// Interface of the default route, with its IPv4 address when up.
+fn network() -> Option<String> {
+    let interface = default_interface()?;
+    if read(&Path::new("/sys/class/net").join(&interface).join("operstate")).as_deref() == Some("down") {
+        return Some(format!("{} down", interface));
+    }
+
+    let address = getifaddrs()
+        .ok()?
+        .filter(|address| address.interface_name == interface)
+        .find_map(|address| address.address?.as_sockaddr_in().map(|address| address.ip()));
+
+    Some(match address {
+        Some(address) => format!("{} {}", interface, address),
+        None => format!("{} up", interface),
+    })
+}
+
+/// First interface with a default route in `/proc/net/route`.
+fn default_interface() -> Option<String> {
+    let routes = fs::read_to_string("/proc/net/route").ok()?;
+    routes.lines().skip(1).find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let interface = fields.next()?;
+        (fields.next()? == "00000000").then(|| interface.to_owned())
+    })
+}
+
+fn read(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|content| content.trim().to_owned())
+}
@@ -1,6 +1,14 @@
+use crate::actions::{self, Action};
+use crate::clipboard::{self, Choice, Source};
+use crate::config;
+use crate::events::{self, DesktopEvent};
+use crate::idle;
+use crate::processes::{self, send_signal};
 use crate::tui_window::CustomKeyboardControl;
+use appcui::dialogs;
 use appcui::input::{Key, KeyModifier};
-use appcui::prelude::{EventProcessStatus, KeyCode, OnKeyPressed};
+use appcui::prelude::{EventProcessStatus, KeyCode, MouseEvent, OnKeyPressed, OnMouseEvent};
+use tracing::{debug, trace};
 use virtual_terminal::Input;
 
 impl OnKeyPressed for CustomKeyboardControl {
@@ -8,20 +16,70 @@ impl OnKeyPressed for CustomKeyboardControl {
         if !self.has_focus() {
             return EventProcessStatus::Ignored;
         }
+        idle::touch();
+
+        match actions::bound_to(&config::get().keybindings, key) {
+            Some(action @ (Action::ClipboardManager | Action::Processes | Action::CloseWindow)) => self.run_action(action),
+            // The others need the window or the desktop, which gets the keys
+            // its windows leave alone.
+            Some(_) => return EventProcessStatus::Ignored,
+            None => self.send_key(key, character),
+        }
 
-        if key.modifier == KeyModifier::Ctrl && key.code == KeyCode::C {
-            self.tx.send_blocking(Input::Terminate).ok();
-            self.should_exit = true;
+        EventProcessStatus::Processed
+    }
+}
+
+impl OnMouseEvent for CustomKeyboardControl {
+    fn on_mouse_event(&mut self, _event: &MouseEvent) -> EventProcessStatus {
+        // Clicking or scrolling in a window is using the desktop too.
+        idle::touch();
+        EventProcessStatus::Ignored
+    }
+}
+
+impl CustomKeyboardControl {
+    fn send_key(&mut self, key: Key, character: char) {
+        // Only the size is logged: keys typed into windows include passwords.
+        if let Some(data) = to_escape_sequence_vec(key, character) {
+            trace!("Key sent as {} bytes.", data.len());
+            self.tx
+                .send_blocking(Input::Data(data))
+                .ok();
         }
-        else {
-            if let Some(data) = to_escape_sequence_vec(key, character) {
-                self.tx
-                    .send_blocking(Input::Data(data))
-                    .ok();
+    }
+
+    /// Run an action on the window's program, from its key or the command palette.
+    pub fn run_action(&mut self, action: Action) {
+        match action {
+            Action::ClipboardManager => match clipboard::manage(clipboard::history()) {
+                Some(Choice::Copy(data)) => {
+                    clipboard::record(data.clone(), Source::Manager);
+                    events::report(&DesktopEvent::Clipboard { data });
+                }
+                Some(Choice::Paste(data)) => {
+                    let paste = clipboard::paste_sequence(&data, self.bracketed_paste);
+                    self.tx.send_blocking(Input::Data(paste)).ok();
+                }
+                None => {}
+            },
+            Action::Processes => {
+                if let Some(pid) = self.pid
+                    && let Some((target, signal)) = processes::pick(pid)
+                {
+                    debug!("Sending {:?} to process {}.", signal, target);
+                    if let Err(e) = send_signal(target, signal) {
+                        dialogs::error("Could not signal the process", &format!("{:#}", e));
+                    }
+                }
             }
+            Action::CloseWindow => {
+                debug!("Close key pressed, terminating the window's program.");
+                self.tx.send_blocking(Input::Terminate).ok();
+                self.should_exit = true;
+            }
+            _ => {}
         }
-
-        EventProcessStatus::Processed
     }
 }
 
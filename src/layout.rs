@@ -0,0 +1,62 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Snapshot of a session's desktop, enough for `serve --resume` to rebuild it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DesktopLayout {
+    pub shortcut_dir: PathBuf,
+    #[serde(default)]
+    pub windows: Vec<WindowLayout>,
+}
+
+/// One open window and the program running in it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WindowLayout {
+    /// Name of the shortcut the window was opened from.
+    pub shortcut: String,
+    /// Program and arguments after file and folder placeholders were filled in.
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Working directory of the program when the snapshot was taken.
+    pub cwd: Option<PathBuf>,
+    pub x: i32,
+    pub y: i32,
+    pub width: u16,
+    pub height: u16,
+    pub visible: bool,
+}
+
+/// Layout kept after a server was terminated, read by `serve --resume`.
+pub fn layout_path(dir: &Path, session: &str) -> PathBuf {
+    dir.join(format!("{}.layout.toml", session))
+}
+
+/// Layout continuously written by the desktop of the server with this pid.
+///
+/// It is not named after the session so that renaming a session does not
+/// pull the file from under the running desktop.
+pub fn snapshot_path(dir: &Path, server_pid: u32) -> PathBuf {
+    dir.join(format!("layout-{}.toml", server_pid))
+}
+
+pub fn load(path: &Path) -> anyhow::Result<DesktopLayout> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read layout {:?}", path))?;
+    let layout = toml::from_str(&content).with_context(|| format!("Invalid layout {:?}", path))?;
+    Ok(layout)
+}
+
+/// Write the layout atomically so a reader never sees half of it.
+pub fn save(path: &Path, layout: &DesktopLayout) -> anyhow::Result<()> {
+    let tmp_path = path.with_extension("toml.tmp");
+    fs::write(&tmp_path, toml::to_string(layout)?)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Current working directory of a process, if it is still running.
+pub fn process_cwd(pid: u32) -> Option<PathBuf> {
+    fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+}
@@ -0,0 +1,30 @@
+use anyhow::Context;
+use std::fs::OpenOptions;
+use std::io::IsTerminal;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::level_filters::LevelFilter;
+
+/// Send log records at `level` and above to `file`, or to stderr without one.
+///
+/// The desktop draws on its stderr, so it only logs when given a file.
+pub fn init(level: LevelFilter, file: Option<&Path>, draws_on_stderr: bool) -> anyhow::Result<()> {
+    let builder = tracing_subscriber::fmt().with_max_level(level).with_target(false);
+
+    match file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .mode(0o600)
+                .open(path)
+                .with_context(|| format!("Failed to open log file {:?}", path))?;
+            builder.with_ansi(false).with_writer(Mutex::new(file)).init();
+        }
+        None if draws_on_stderr => {}
+        None => builder.with_ansi(std::io::stderr().is_terminal()).with_writer(std::io::stderr).init(),
+    }
+
+    Ok(())
+}
@@ -1,4 +1,5 @@
 mod terminal_emulation;
+mod actions;
 mod tui_window;
 mod keyboard;
 mod desktop;
@@ -8,54 +9,240 @@ mod args;
 mod server;
 mod client;
 mod protocol;
+mod transport;
+mod auth;
+mod replay;
+mod layout;
+mod daemon;
+mod events;
+mod palette;
+mod search_list;
+mod geometry;
+mod hooks;
+mod control;
+mod environment;
+mod activity;
+mod config;
+mod logging;
+mod completions;
+mod crash;
+mod onboarding;
+mod clipboard;
+mod plugins;
+mod idle;
+mod processes;
+mod pipe;
+mod recording;
+mod playback;
+mod indicators;
+mod accessibility;
+mod glyphs;
+mod reload;
 
 use std::path::PathBuf;
 use std::process::exit;
+use std::time::Duration;
 use crate::desktop::MyDesktop;
 use crate::shortcut::parse_shortcut_dir;
 use appcui::backend::Type;
 use appcui::prelude::{App, Theme};
-use appcui::system::Themes;
-use clap::Parser;
-use crate::args::{Args, Commands};
+use clap::{CommandFactory, Parser};
+use clap_complete::CompleteEnv;
+use crate::args::{Args, Commands, ConfigCommand};
+use crate::client::{AttachOptions, ConnectOptions, SshTarget};
+use crate::config::{Backend, Config, Overrides};
+use crate::daemon::Ready;
+use crate::server::ServeOptions;
+
+fn main() -> anyhow::Result<()> {
+    // Answers the shell when it asks for completions, then exits.
+    CompleteEnv::with_factory(Args::command).complete();
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
+    // Windows start programs through this, it must not touch the terminal or the config.
+    if let Some(Commands::Exec { set, unset, command }) = &args.command {
+        return environment::exec(set, unset, command);
+    }
+
+    let owns_terminal = matches!(args.command, None | Some(Commands::Run { .. }) | Some(Commands::Attach { .. }));
+    crash::install_panic_hook(owns_terminal);
+
+    // Flags override the environment, which overrides the config file. Only
+    // the desktop and the server need it all, a typo must not keep a user
+    // from listing, attaching to or killing their sessions.
+    let needs_config = matches!(args.command, None | Some(Commands::Run { .. }) | Some(Commands::Serve { .. }) | Some(Commands::Config { .. }));
+    let mut config = match needs_config {
+        true => config::load(args.config.as_deref())?,
+        false => config::load_or_default(args.config.as_deref()),
+    };
+    let overrides = Overrides { theme: args.theme, backend: args.backend, color_mode: args.color_mode, ascii_only: args.ascii_only };
+    overrides.apply(&mut config);
+
+    // Forking is only safe before the runtime starts its worker threads.
+    let ready = match &args.command {
+        Some(Commands::Serve { session, foreground: false, group: None, .. }) => {
+            let session = session.as_ref().unwrap_or(&config.session);
+            server::check_session_name(session)?;
+            Some(daemon::daemonize(session)?)
+        }
+        _ => None,
+    };
+
+    let draws_on_stderr = matches!(args.command, None | Some(Commands::Run { .. }));
+    logging::init(args.log_level, args.log_file.as_deref(), draws_on_stderr)?;
+
+    tokio::runtime::Runtime::new()?.block_on(run(args, config, ready))
+}
+
+async fn run(args: Args, config: Config, ready: Option<Ready>) -> anyhow::Result<()> {
+    let session_or_default = |session: Option<String>| session.unwrap_or_else(|| config.session.clone());
+    let dir_or_default = |dir: Option<PathBuf>| dir.unwrap_or_else(|| config.shortcut_dir.clone());
+
     match args.command {
         None => {
             // Backward compat: no subcommand given.
-            // Use shortcut_dir positional arg if provided, otherwise default to ".".
-            let dir = args.shortcut_dir.unwrap_or_else(|| PathBuf::from("."));
-            run_desktop(dir).await?;
+            // Use shortcut_dir positional arg if provided, otherwise the configured one.
+            let dir = dir_or_default(args.shortcut_dir);
+            run_desktop(dir, None, None, None, config).await?;
+        }
+        Some(Commands::Run { shortcut_dir, layout_file, restore, session }) => {
+            let dir = dir_or_default(shortcut_dir);
+            run_desktop(dir, layout_file, restore, session, config).await?;
+        }
+        Some(Commands::Serve { session, group: Some(group), .. }) => {
+            let leader = ConnectOptions { session: group, remote: None, tls_ca: None, token_file: None };
+            client::link_session(leader, session_or_default(session)).await?;
+        }
+        Some(Commands::Serve {
+            shortcut_dir,
+            session,
+            listen,
+            tls_cert,
+            tls_key,
+            force_size,
+            resume,
+            foreground: _,
+            hooks,
+            command,
+            env,
+            update_environment,
+            activity_log,
+            idle_timeout,
+            idle_action,
+            group: None,
+        }) => {
+            server::serve(ServeOptions {
+                shortcut_dir: dir_or_default(shortcut_dir),
+                session: session_or_default(session),
+                listen,
+                tls_cert,
+                tls_key,
+                force_size,
+                resume,
+                ready,
+                hooks,
+                command,
+                env,
+                update_environment,
+                activity_log,
+                idle_timeout: idle_timeout.map(|minutes| Duration::from_secs(minutes * 60)),
+                idle_action,
+                config_file: config.file.clone(),
+                overrides: config.overrides,
+                log_level: args.log_level,
+                log_file: args.log_file,
+            })
+            .await?;
         }
-        Some(Commands::Run { shortcut_dir }) => {
-            run_desktop(shortcut_dir).await?;
+        Some(Commands::Attach { session, remote, tls_ca, token_file, read_only, reconnect, detach_others, control, window }) => {
+            let ssh_target = session.as_deref().and_then(SshTarget::parse);
+            let connection = ConnectOptions { session: session_or_default(session), remote, tls_ca, token_file };
+            let options = AttachOptions { connection, read_only, reconnect, detach_others, control, window };
+            match ssh_target {
+                Some(target) => client::attach_over_ssh(target, options).await?,
+                None => client::attach(options).await?,
+            }
         }
-        Some(Commands::Serve { shortcut_dir, session }) => {
-            server::serve(shortcut_dir, session).await?;
+        Some(Commands::List { json, clean }) => {
+            client::list_sessions(json, clean).await?;
         }
-        Some(Commands::Attach { session }) => {
-            client::attach(session).await?;
+        Some(Commands::Kill { target }) => {
+            client::kill_session(target.resolve(&config)).await?;
         }
-        Some(Commands::List) => {
-            client::list_sessions()?;
+        Some(Commands::Rename { target, name }) => {
+            client::rename_session(target.resolve(&config), name).await?;
         }
+        Some(Commands::DetachClients { target }) => {
+            client::detach_clients(target.resolve(&config)).await?;
+        }
+        Some(Commands::SelectWindow { target, window }) => {
+            client::select_window(target.resolve(&config), window).await?;
+        }
+        Some(Commands::Send { target, keys, hex }) => {
+            client::send_keys(target.resolve(&config), &keys, hex).await?;
+        }
+        Some(Commands::Logs { session, follow }) => {
+            activity::show(&activity::activity_path(&server::session_dir()?, &session_or_default(session)), follow).await?;
+        }
+        Some(Commands::Record { target, output }) => {
+            recording::record(target.resolve(&config), &output).await?;
+        }
+        Some(Commands::Completions { shell }) => {
+            completions::print_completions(shell);
+        }
+        Some(Commands::Man) => {
+            completions::print_man()?;
+        }
+        Some(Commands::Config { command: ConfigCommand::Show }) => {
+            config::show(&config)?;
+        }
+        Some(Commands::Exec { .. }) => unreachable!("exec is handled before the runtime starts"),
     }
 
     exit(0);
 }
 
-async fn run_desktop(shortcut_dir: PathBuf) -> anyhow::Result<()> {
-    let desktop_shortcuts = parse_shortcut_dir(shortcut_dir)?;
-    let theme = Theme::new(Themes::Default);
-    let app = App::with_backend(Type::CrossTerm)
-        .desktop(MyDesktop::new(desktop_shortcuts))
-        .app_bar()
-        .theme(theme)
-        .color_schema(false)
-        .build()?;
+async fn run_desktop(shortcut_dir: PathBuf, layout_file: Option<PathBuf>, restore: Option<PathBuf>, session: Option<String>, config: Config) -> anyhow::Result<()> {
+    let desktop_shortcuts = parse_shortcut_dir(shortcut_dir.clone())?;
+    terminal_emulation::set_palette(config.palette()?);
+    accessibility::set_mode(config.color_mode);
+    glyphs::init(config.substitutions()?, config.ascii_only);
+    let (theme, backend) = (config.theme, config.backend);
+    config::init(config);
+    events::connect();
+    indicators::start(session);
+    let restore = restore.map(|path| layout::load(&path)).transpose()?;
+
+    // appcui may fail or panic halfway through setting up the terminal.
+    let _terminal = crash::TerminalGuard;
+    let build = |backend: Option<Type>| {
+        let mut desktop = MyDesktop::new(desktop_shortcuts.clone(), shortcut_dir.clone());
+        if let Some(layout_file) = &layout_file {
+            desktop = desktop.with_layout(layout_file.clone(), restore.clone());
+        }
+
+        let builder = match backend {
+            Some(backend) => App::with_backend(backend),
+            None => App::new(),
+        };
+        builder
+            .desktop(desktop)
+            .app_bar()
+            .theme(Theme::new(theme.theme()))
+            .color_schema(false)
+            .build()
+    };
+
+    // A backend that cannot start on this terminal falls back to the one appcui detects.
+    let app = match build(backend.kind()) {
+        Ok(app) => app,
+        Err(e) if backend != Backend::Auto => {
+            tracing::warn!("The {} backend failed to start ({}), detecting one instead.", backend, e);
+            build(None)?
+        }
+        Err(e) => return Err(e.into()),
+    };
     app.run();
     Ok(())
 }
@@ -0,0 +1,137 @@
+use anyhow::Context;
+use crate::shortcut::{parse_shortcut_dir, Shortcut, TaskbarCommand, TaskbarOptions, TerminalOptions, WindowOptions};
+use appcui::dialogs::{self, Location, SelectFolderDialogFlags};
+use appcui::prelude::*;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// What the user picked in the welcome window, for the desktop to act on.
+static CHOICE: Mutex<Option<Onboarded>> = Mutex::new(None);
+
+/// Shortcuts to start the desktop over with.
+pub struct Onboarded {
+    pub shortcut_dir: PathBuf,
+    pub shortcuts: Vec<Shortcut>,
+    /// Index of a shortcut to start right away.
+    pub open: Option<usize>,
+}
+
+pub fn take_choice() -> Option<Onboarded> {
+    CHOICE.lock().unwrap().take()
+}
+
+/// Shown instead of a blank desktop when the shortcut directory has no shortcut.
+#[Window(events = ButtonEvents)]
+pub struct WelcomeWindow {
+    shortcut_dir: PathBuf,
+    examples: Handle<Button>,
+    terminal: Handle<Button>,
+    other_dir: Handle<Button>,
+}
+
+impl WelcomeWindow {
+    pub fn new(shortcut_dir: PathBuf) -> Self {
+        let mut win = Self {
+            base: Window::new(
+                "Welcome to desktop-tui",
+                LayoutBuilder::new().alignment(Alignment::Center).width(60).height(13).build(),
+                window::Flags::None,
+            ),
+            shortcut_dir,
+            examples: Handle::None,
+            terminal: Handle::None,
+            other_dir: Handle::None,
+        };
+
+        let text = format!(
+            "No shortcut found in {:?}.\nShortcuts are TOML files describing the programs\nthe desktop can open, see the README.",
+            win.shortcut_dir
+        );
+        win.add(Label::new(&text, LayoutBuilder::new().x(2).y(1).width(54).height(3).build()));
+
+        let button = |caption: &str, y: i32| {
+            Button::new(caption, LayoutBuilder::new().x(2).y(y).width(54).build(), button::Type::Normal)
+        };
+        win.examples = win.add(button("&Create example shortcuts here", 5));
+        win.terminal = win.add(button("Open a &terminal", 7));
+        win.other_dir = win.add(button("&Pick another directory...", 9));
+        win
+    }
+
+    fn finish(&mut self, onboarded: Onboarded) {
+        *CHOICE.lock().unwrap() = Some(onboarded);
+        self.close();
+    }
+}
+
+impl ButtonEvents for WelcomeWindow {
+    fn on_pressed(&mut self, handle: Handle<Button>) -> EventProcessStatus {
+        if handle == self.examples {
+            match write_examples(&self.shortcut_dir).and_then(|_| parse_shortcut_dir(self.shortcut_dir.clone())) {
+                Ok(shortcuts) => self.finish(Onboarded { shortcut_dir: self.shortcut_dir.clone(), shortcuts, open: None }),
+                Err(e) => dialogs::error("Could not create the shortcuts", &format!("{:#}", e)),
+            }
+        } else if handle == self.terminal {
+            let shortcuts = vec![terminal_shortcut()];
+            self.finish(Onboarded { shortcut_dir: self.shortcut_dir.clone(), shortcuts, open: Some(0) });
+        } else if handle == self.other_dir {
+            let Some(dir) = dialogs::select_folder("Shortcut directory", Location::Path(self.shortcut_dir.as_path()), SelectFolderDialogFlags::Icons) else {
+                return EventProcessStatus::Processed;
+            };
+
+            match parse_shortcut_dir(dir.clone()) {
+                Ok(shortcuts) if !shortcuts.is_empty() => self.finish(Onboarded { shortcut_dir: dir, shortcuts, open: None }),
+                Ok(_) => dialogs::error("No shortcuts", &format!("{:?} has no shortcut either.", dir)),
+                Err(e) => dialogs::error("Invalid shortcuts", &format!("{:#}", e)),
+            }
+        } else {
+            return EventProcessStatus::Ignored;
+        }
+
+        EventProcessStatus::Processed
+    }
+}
+
+fn shortcut(name: &str, command: &str, position: u32, additional_commands: Vec<TaskbarCommand>) -> Shortcut {
+    Shortcut {
+        name: name.to_owned(),
+        command: command.to_owned(),
+        args: Vec::new(),
+        taskbar: TaskbarOptions { position: Some(position), additional_commands },
+        window: WindowOptions { resizable: true, close_button: true, fixed_position: false, size: None },
+        terminal: TerminalOptions { padding: Some((0, 0)), background_color: None },
+        autostart: None,
+    }
+}
+
+/// The user's shell.
+fn terminal_shortcut() -> Shortcut {
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| String::from("sh"));
+    shortcut("Terminal", &shell, 0, Vec::new())
+}
+
+/// Write a few shortcuts to start from, leaving existing files alone.
+fn write_examples(dir: &Path) -> anyhow::Result<()> {
+    let open_file = TaskbarCommand { name: String::from("Open file"), command: String::from("vi"), args: vec![String::from("<FILE_PATH>")] };
+    let examples = [
+        ("terminal.toml", terminal_shortcut()),
+        ("monitor.toml", shortcut("Monitor", "top", 1, Vec::new())),
+        ("editor.toml", shortcut("Editor", "vi", 2, vec![open_file])),
+    ];
+
+    std::fs::create_dir_all(dir)?;
+    for (file_name, example) in examples {
+        let content = toml::to_string(&example)?;
+        // A shortcut of the user's with the same name is left alone.
+        let path = dir.join(file_name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => file.write_all(content.as_bytes())?,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to create {:?}", path)),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use crate::actions::{Action, ACTIONS};
+use crate::config;
+use crate::search_list::{Item, SearchList};
+
+/// What was picked in the command palette.
+#[derive(Clone)]
+pub enum Choice {
+    Action(Action),
+    /// Open a window of the shortcut with this name.
+    Open(String),
+}
+
+/// Search every action and shortcut, with the key bound to it, to run one.
+/// Actions on the focused window are only offered `in_window`.
+pub fn pick(shortcuts: Vec<String>, in_window: bool) -> Option<Choice> {
+    let config = config::get();
+    let keybindings = &config.keybindings;
+
+    let mut choices: Vec<(String, Choice)> = ACTIONS
+        .into_iter()
+        .filter(|&action| action != Action::CommandPalette && (in_window || !action.acts_on_window()))
+        .map(|action| {
+            let key = action.binding(keybindings).map(|binding| binding.to_string()).unwrap_or_default();
+            (format!("{:<48}{}", action.name(), key), Choice::Action(action))
+        })
+        .collect();
+    choices.extend(shortcuts.into_iter().map(|name| (format!("Open: {}", name), Choice::Open(name))));
+
+    let items = choices.iter().map(|(line, _)| Item::new(line.clone())).collect();
+    let title = format!("Command palette ({})", keybindings.command_palette);
+    let (_, index) = SearchList::new(&title, items, &["&Run"]).show()?;
+    Some(choices.swap_remove(index).1)
+}
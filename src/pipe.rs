@@ -0,0 +1,86 @@
+use appcui::prelude::*;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Numbers the files holding piped text.
+static NEXT_PIPE: AtomicU64 = AtomicU64::new(1);
+
+/// A command to run in a new window, reading `input` on its stdin.
+pub struct PipeRequest {
+    pub command: String,
+    pub input: Vec<u8>,
+}
+
+impl PipeRequest {
+    /// Program and arguments running the command with the input on its stdin,
+    /// while the window's PTY stays its terminal.
+    ///
+    /// The input is written to a private file the shell opens as stdin and
+    /// removes before running the command.
+    pub fn program(&self) -> anyhow::Result<(String, Vec<String>)> {
+        let path = std::env::temp_dir().join(format!(
+            "desktop-tui-pipe-{}-{}",
+            std::process::id(),
+            NEXT_PIPE.fetch_add(1, Ordering::Relaxed)
+        ));
+        write_private(&path, &self.input)?;
+
+        let script = format!("exec < \"$1\"; rm -f \"$1\"; {}", self.command);
+        Ok((String::from("sh"), vec![String::from("-c"), script, String::from("desktop-tui-pipe"), path.display().to_string()]))
+    }
+}
+
+fn write_private(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+    file.write_all(data)?;
+    Ok(())
+}
+
+/// Asks for the command to pipe a window's screen into.
+#[ModalWindow(events = ButtonEvents, response = String)]
+pub struct PipeDialog {
+    command: Handle<TextField>,
+    run: Handle<Button>,
+    cancel: Handle<Button>,
+}
+
+impl PipeDialog {
+    pub fn new(title: &str) -> Self {
+        let mut win = Self {
+            base: ModalWindow::new(
+                &format!("Pipe {}", title),
+                LayoutBuilder::new().alignment(Alignment::Center).width(60).height(9).build(),
+                window::Flags::None,
+            ),
+            command: Handle::None,
+            run: Handle::None,
+            cancel: Handle::None,
+        };
+
+        win.add(Label::new("Send the screen to the stdin of (e.g. `grep foo`, `less`):", LayoutBuilder::new().x(1).y(1).width(56).build()));
+        win.command = win.add(TextField::new("less", LayoutBuilder::new().x(1).y(2).width(56).build(), textfield::Flags::None));
+        win.run = win.add(Button::new("&Run", LayoutBuilder::new().x(14).y(4).width(14).build(), button::Type::Normal));
+        win.cancel = win.add(Button::new("Cancel", LayoutBuilder::new().x(30).y(4).width(14).build(), button::Type::Normal));
+        win
+    }
+}
+
+impl ButtonEvents for PipeDialog {
+    fn on_pressed(&mut self, handle: Handle<Button>) -> EventProcessStatus {
+        if handle == self.cancel {
+            self.exit();
+        } else if handle == self.run {
+            let command = self.control(self.command).map(|field| field.text().trim().to_owned()).unwrap_or_default();
+            if command.is_empty() {
+                return EventProcessStatus::Processed;
+            }
+            self.exit_with(command);
+        } else {
+            return EventProcessStatus::Ignored;
+        }
+        EventProcessStatus::Processed
+    }
+}
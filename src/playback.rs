@@ -0,0 +1,174 @@
+use crate::recording::Cast;
+use crate::terminal_emulation::TerminalParser;
+use appcui::graphics::{CharFlags, Character, Color, Size, Surface};
+use appcui::prelude::*;
+use std::time::{Duration, Instant};
+
+/// How often the playback moves on.
+const FRAME: Duration = Duration::from_millis(25);
+
+/// Playback speeds the Slower and Faster buttons step through.
+const SPEEDS: [f64; 7] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0];
+
+/// Plays an asciicast recording through the terminal parser.
+#[Window(events = ButtonEvents + TimerEvents)]
+pub struct PlaybackWindow {
+    cast: Cast,
+    parser: TerminalParser,
+    /// Index of the next output event to play.
+    next: usize,
+    /// Seconds of the recording played so far.
+    position: f64,
+    speed: usize,
+    paused: bool,
+    last_frame: Instant,
+    canvas: Handle<Canvas>,
+    status: Handle<Label>,
+    pause: Handle<Button>,
+    slower: Handle<Button>,
+    faster: Handle<Button>,
+    restart: Handle<Button>,
+}
+
+impl PlaybackWindow {
+    pub fn new(title: &str, cast: Cast) -> Self {
+        let width = cast.header.width as u32;
+        let height = cast.header.height as u32;
+
+        let mut win = Self {
+            base: Window::new(
+                title,
+                LayoutBuilder::new().alignment(Alignment::Center).width(width.max(60) + 2).height(height + 4).build(),
+                window::Flags::Sizeable,
+            ),
+            parser: TerminalParser::new(width, height, Color::RGB(0, 0, 0)),
+            cast,
+            next: 0,
+            position: 0.0,
+            speed: 2,
+            paused: false,
+            last_frame: Instant::now(),
+            canvas: Handle::None,
+            status: Handle::None,
+            pause: Handle::None,
+            slower: Handle::None,
+            faster: Handle::None,
+            restart: Handle::None,
+        };
+
+        win.canvas = win.add(Canvas::new(
+            Size::new(width, height),
+            LayoutBuilder::new().x(0).y(0).width(width).height(height).build(),
+            canvas::Flags::None,
+        ));
+
+        let y = height as i32 + 1;
+        let button = |caption: &str, x: i32| Button::new(caption, LayoutBuilder::new().x(x).y(y).width(10).build(), button::Type::Normal);
+        win.pause = win.add(button("&Pause", 0));
+        win.slower = win.add(button("&Slower", 11));
+        win.faster = win.add(button("&Faster", 22));
+        win.restart = win.add(button("&Restart", 33));
+        win.status = win.add(Label::new("", LayoutBuilder::new().x(44).y(y).width(16).build()));
+
+        if let Some(timer) = win.timer() {
+            timer.start(FRAME);
+        }
+        win.update_status();
+        win
+    }
+
+    fn duration(&self) -> f64 {
+        self.cast.output.last().map(|(time, _)| *time).unwrap_or(0.0)
+    }
+
+    fn update_status(&mut self) {
+        let state = if self.next >= self.cast.output.len() {
+            "done"
+        } else if self.paused {
+            "paused"
+        } else {
+            ""
+        };
+        let text = format!("{:.0}/{:.0}s x{} {}", self.position, self.duration(), SPEEDS[self.speed], state);
+
+        let status = self.status;
+        if let Some(status) = self.control_mut(status) {
+            status.set_caption(&text);
+        }
+    }
+
+    /// Feed the parser everything written up to the current position.
+    fn play(&mut self) {
+        let mut data = Vec::new();
+        while let Some((time, output)) = self.cast.output.get(self.next)
+            && *time <= self.position
+        {
+            data.extend_from_slice(output);
+            self.next += 1;
+        }
+        if data.is_empty() {
+            return;
+        }
+
+        let canvas = self.canvas;
+        let Some(cv) = self.control_mut(canvas) else { return };
+        let surface = cv.drawing_surface_mut();
+        let mut buffer = Vec::new();
+        surface.serialize_to_buffer(&mut buffer);
+        let old_surface = Surface::from_buffer(&buffer).unwrap();
+
+        let new_surface = self.parser.parse_to_surface(&data, old_surface);
+        if let Some(cv) = self.control_mut(canvas) {
+            *cv.drawing_surface_mut() = new_surface;
+        }
+    }
+
+    fn rewind(&mut self) {
+        self.parser.reset();
+        self.next = 0;
+        self.position = 0.0;
+        let canvas = self.canvas;
+        if let Some(cv) = self.control_mut(canvas) {
+            cv.drawing_surface_mut().clear(Character::new(' ', Color::Transparent, Color::RGB(0, 0, 0), CharFlags::None));
+        }
+    }
+}
+
+impl TimerEvents for PlaybackWindow {
+    fn on_update(&mut self, _: u64) -> EventProcessStatus {
+        let elapsed = self.last_frame.elapsed().as_secs_f64();
+        self.last_frame = Instant::now();
+
+        if self.paused || self.next >= self.cast.output.len() {
+            return EventProcessStatus::Ignored;
+        }
+
+        self.position += elapsed * SPEEDS[self.speed];
+        self.play();
+        self.update_status();
+        EventProcessStatus::Processed
+    }
+}
+
+impl ButtonEvents for PlaybackWindow {
+    fn on_pressed(&mut self, handle: Handle<Button>) -> EventProcessStatus {
+        if handle == self.pause {
+            self.paused = !self.paused;
+            let caption = if self.paused { "&Play" } else { "&Pause" };
+            if let Some(button) = self.control_mut(handle) {
+                button.set_caption(caption);
+            }
+        } else if handle == self.slower {
+            self.speed = self.speed.saturating_sub(1);
+        } else if handle == self.faster {
+            self.speed = (self.speed + 1).min(SPEEDS.len() - 1);
+        } else if handle == self.restart {
+            self.rewind();
+        } else {
+            return EventProcessStatus::Ignored;
+        }
+
+        self.update_status();
+        EventProcessStatus::Processed
+    }
+}
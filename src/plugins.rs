@@ -0,0 +1,268 @@
+use crate::config;
+use appcui::prelude::*;
+use mlua::{Function, HookTriggers, Lua, Table, VmState};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How long a script or callback may run, the desktop waits for it.
+const RUN_TIMEOUT: Duration = Duration::from_millis(500);
+/// Lua instructions between two looks at the clock.
+const HOOK_INSTRUCTIONS: u32 = 10_000;
+
+/// Bells rung in windows since the desktop last ran the `bell` hooks, by window id and title.
+static BELLS: Mutex<Vec<(u64, String)>> = Mutex::new(Vec::new());
+
+/// Something a hook ran into, passed to the callbacks registered for it.
+pub enum PluginEvent {
+    Startup,
+    WindowOpen { id: u64, title: String },
+    Bell { id: u64, title: String },
+    /// Fired with the desktop's clock, about every 2 seconds while it is not idle.
+    Timer,
+}
+
+impl PluginEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            PluginEvent::Startup => "startup",
+            PluginEvent::WindowOpen { .. } => "window_open",
+            PluginEvent::Bell { .. } => "bell",
+            PluginEvent::Timer => "timer",
+        }
+    }
+}
+
+/// What scripts asked the desktop to do, carried out once their callbacks return.
+pub enum Action {
+    CreateWidget { id: u64, title: String, text: String },
+    SetWidgetText { id: u64, text: String },
+    SendKeys { window: u64, keys: String },
+}
+
+/// State the Lua API shares with the desktop.
+#[derive(Default)]
+struct Shared {
+    callbacks: HashMap<String, Vec<Function>>,
+    actions: Vec<Action>,
+    /// Open windows by id and title, as of the event being handled.
+    windows: Vec<(u64, String)>,
+    next_widget: u64,
+}
+
+/// Lua scripts from `~/.config/desktop-tui/plugins/`, all run in one Lua state
+/// through the `desktop` table:
+///
+/// - `desktop.on(event, function)`: call `function` on `startup`, `window_open(id, title)`,
+///   `bell(id, title)` or `timer()`
+/// - `desktop.widget(title, text)`: open a window showing `text`, returns its id
+/// - `desktop.set_text(id, text)`: change the text of a widget window
+/// - `desktop.send_keys(window_id, keys)`: type into a window
+/// - `desktop.windows()`: the open windows, as `{ id = ..., title = ... }` tables
+/// - `desktop.log(message)`: write to the desktop's log
+pub struct Plugins {
+    lua: Lua,
+    shared: Rc<RefCell<Shared>>,
+    /// When the script or callback running now is stopped.
+    deadline: Rc<Cell<Instant>>,
+}
+
+impl Plugins {
+    /// Run every script of the plugins directory. A script that fails is
+    /// logged and left out, `None` when there is none.
+    pub fn load() -> Option<Self> {
+        let dir = config::config_dir()?.join("plugins");
+        let mut scripts: Vec<_> = fs::read_dir(&dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "lua"))
+            .collect();
+        if scripts.is_empty() {
+            return None;
+        }
+        scripts.sort();
+
+        let plugins = Plugins {
+            lua: Lua::new(),
+            shared: Rc::new(RefCell::new(Shared::default())),
+            deadline: Rc::new(Cell::new(Instant::now())),
+        };
+        plugins.limit_run_time();
+        if let Err(e) = plugins.register_api() {
+            warn!("Failed to set up the plugin API: {}", e);
+            return None;
+        }
+
+        for script in scripts {
+            match plugins.run_script(&script) {
+                Ok(()) => info!("Loaded plugin {:?}.", script),
+                Err(e) => warn!("Plugin {:?} failed: {}", script, e),
+            }
+        }
+
+        Some(plugins)
+    }
+
+    /// Stop scripts and callbacks running past their deadline, like one
+    /// stuck in a loop, which would otherwise freeze the desktop.
+    fn limit_run_time(&self) {
+        let deadline = Rc::clone(&self.deadline);
+        self.lua.set_hook(HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTIONS), move |_, _| {
+            if Instant::now() > deadline.get() {
+                return Err(mlua::Error::runtime(format!("stopped after running for over {:?}", RUN_TIMEOUT)));
+            }
+            Ok(VmState::Continue)
+        });
+    }
+
+    fn run_script(&self, path: &Path) -> mlua::Result<()> {
+        let code = fs::read_to_string(path).map_err(mlua::Error::external)?;
+        self.deadline.set(Instant::now() + RUN_TIMEOUT);
+        self.lua.load(code).set_name(path.display().to_string()).exec()
+    }
+
+    fn register_api(&self) -> mlua::Result<()> {
+        let lua = &self.lua;
+        let desktop = lua.create_table()?;
+
+        let shared = Rc::clone(&self.shared);
+        desktop.set(
+            "on",
+            lua.create_function(move |_, (event, callback): (String, Function)| {
+                if !["startup", "window_open", "bell", "timer"].contains(&event.as_str()) {
+                    return Err(mlua::Error::runtime(format!("unknown event '{}'", event)));
+                }
+                shared.borrow_mut().callbacks.entry(event).or_default().push(callback);
+                Ok(())
+            })?,
+        )?;
+
+        let shared = Rc::clone(&self.shared);
+        desktop.set(
+            "widget",
+            lua.create_function(move |_, (title, text): (String, String)| {
+                let mut shared = shared.borrow_mut();
+                shared.next_widget += 1;
+                let id = shared.next_widget;
+                shared.actions.push(Action::CreateWidget { id, title, text });
+                Ok(id)
+            })?,
+        )?;
+
+        let shared = Rc::clone(&self.shared);
+        desktop.set(
+            "set_text",
+            lua.create_function(move |_, (id, text): (u64, String)| {
+                shared.borrow_mut().actions.push(Action::SetWidgetText { id, text });
+                Ok(())
+            })?,
+        )?;
+
+        let shared = Rc::clone(&self.shared);
+        desktop.set(
+            "send_keys",
+            lua.create_function(move |_, (window, keys): (u64, String)| {
+                shared.borrow_mut().actions.push(Action::SendKeys { window, keys });
+                Ok(())
+            })?,
+        )?;
+
+        let shared = Rc::clone(&self.shared);
+        desktop.set(
+            "windows",
+            lua.create_function(move |lua, ()| {
+                let windows = lua.create_table()?;
+                for (index, (id, title)) in shared.borrow().windows.iter().enumerate() {
+                    let window: Table = lua.create_table()?;
+                    window.set("id", *id)?;
+                    window.set("title", title.as_str())?;
+                    windows.set(index + 1, window)?;
+                }
+                Ok(windows)
+            })?,
+        )?;
+
+        desktop.set(
+            "log",
+            lua.create_function(|_, message: String| {
+                info!("[plugin] {}", message);
+                Ok(())
+            })?,
+        )?;
+
+        lua.globals().set("desktop", desktop)
+    }
+
+    /// Call the callbacks registered for `event`, given the open windows, and
+    /// return what they asked for.
+    pub fn emit(&self, event: PluginEvent, windows: Vec<(u64, String)>) -> Vec<Action> {
+        // Callbacks use the API, so nothing may stay borrowed while they run.
+        let callbacks = {
+            let mut shared = self.shared.borrow_mut();
+            shared.windows = windows;
+            shared.callbacks.get(event.name()).cloned().unwrap_or_default()
+        };
+
+        for callback in callbacks {
+            self.deadline.set(Instant::now() + RUN_TIMEOUT);
+            let result = match &event {
+                PluginEvent::Startup | PluginEvent::Timer => callback.call::<()>(()),
+                PluginEvent::WindowOpen { id, title } | PluginEvent::Bell { id, title } => {
+                    callback.call::<()>((*id, title.as_str()))
+                }
+            };
+            if let Err(e) = result {
+                warn!("Plugin {} callback failed: {}", event.name(), e);
+            }
+        }
+
+        std::mem::take(&mut self.shared.borrow_mut().actions)
+    }
+}
+
+/// Note a bell for the `bell` hooks.
+pub fn ring(id: u64, title: &str) {
+    BELLS.lock().unwrap().push((id, title.to_owned()));
+}
+
+/// Bells rung since the last call.
+pub fn take_bells() -> Vec<(u64, String)> {
+    std::mem::take(&mut *BELLS.lock().unwrap())
+}
+
+/// A window made by a script, showing text it can change.
+#[Window()]
+pub struct PluginWidget {
+    label: Handle<Label>,
+}
+
+impl PluginWidget {
+    pub fn new(title: &str, text: &str) -> Self {
+        let width = text.lines().map(|line| line.chars().count()).max().unwrap_or(0).max(title.chars().count()) as u32 + 4;
+        let height = text.lines().count().max(1) as u32 + 2;
+
+        let mut win = Self {
+            base: Window::new(
+                title,
+                LayoutBuilder::new().alignment(Alignment::Center).width(width).height(height).build(),
+                window::Flags::Sizeable,
+            ),
+            label: Handle::None,
+        };
+        win.label = win.add(Label::new(text, Layout::fill()));
+        win
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        let label = self.label;
+        if let Some(label) = self.control_mut(label) {
+            label.set_caption(text);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::search_list::{Item, SearchList};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::collections::HashMap;
+use std::fs;
+
+/// A process as seen in `/proc`.
+#[derive(Clone, Debug)]
+pub struct Process {
+    pub pid: u32,
+    pub parent: u32,
+    pub name: String,
+    /// Process group in the foreground of its terminal.
+    pub foreground_group: i32,
+    pub command_line: String,
+}
+
+/// Read `/proc/<pid>/stat` and the command line.
+pub fn process(pid: u32) -> Option<Process> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The name is in parentheses and may hold spaces or parentheses itself.
+    let name = stat.get(stat.find('(')? + 1..stat.rfind(')')?)?.to_owned();
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let parent = fields.get(1)?.parse().ok()?;
+    let foreground_group = fields.get(5)?.parse().ok()?;
+
+    let command_line = fs::read(format!("/proc/{}/cmdline", pid))
+        .map(|bytes| String::from_utf8_lossy(&bytes).split('\0').filter(|arg| !arg.is_empty()).collect::<Vec<_>>().join(" "))
+        .unwrap_or_default();
+
+    Some(Process { pid, parent, name, foreground_group, command_line })
+}
+
+/// `root` and its descendants, depth first, with their depth in the tree.
+pub fn process_tree(root: u32) -> Vec<(usize, Process)> {
+    let mut children: HashMap<u32, Vec<Process>> = HashMap::new();
+    let mut root_process = None;
+
+    for entry in fs::read_dir("/proc").into_iter().flatten().flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse().ok()) else {
+            continue;
+        };
+        let Some(process) = process(pid) else { continue };
+
+        if pid == root {
+            root_process = Some(process);
+        } else {
+            children.entry(process.parent).or_default().push(process);
+        }
+    }
+
+    let mut tree = Vec::new();
+    let mut stack: Vec<(usize, Process)> = root_process.into_iter().map(|process| (0, process)).collect();
+    while let Some((depth, process)) = stack.pop() {
+        if let Some(mut below) = children.remove(&process.pid) {
+            below.sort_by_key(|child| std::cmp::Reverse(child.pid));
+            stack.extend(below.into_iter().map(|child| (depth + 1, child)));
+        }
+        tree.push((depth, process));
+    }
+
+    tree
+}
+
+/// Name of the process in the foreground of the window running `pid`, when it
+/// is not `pid` itself.
+pub fn foreground_name(pid: u32) -> Option<String> {
+    let group = process(pid)?.foreground_group;
+    if group <= 0 || group as u32 == pid {
+        return None;
+    }
+    process(group as u32).map(|leader| leader.name)
+}
+
+/// Signals offered for the picked process, in button order.
+const SIGNALS: [Signal; 3] = [Signal::SIGINT, Signal::SIGTERM, Signal::SIGKILL];
+
+/// Lists the processes of a window to signal one of them.
+pub fn pick(root: u32) -> Option<(u32, Signal)> {
+    // Refresh lists the processes running now in a new dialog.
+    loop {
+        let tree = process_tree(root);
+        let items = tree
+            .iter()
+            .map(|(depth, process)| {
+                let command = if process.command_line.is_empty() { &process.name } else { &process.command_line };
+                Item::new(format!("{:>7} {}{}", process.pid, "  ".repeat(*depth), command))
+            })
+            .collect();
+
+        let (button, index) = SearchList::new("Processes", items, &["&Interrupt", "&Terminate", "&Kill", "&Refresh"]).show()?;
+        if let Some(&signal) = SIGNALS.get(button) {
+            return Some((tree[index].1.pid, signal));
+        }
+    }
+}
+
+/// Send `signal` to `pid`, as picked in the processes window.
+pub fn send_signal(pid: u32, signal: Signal) -> anyhow::Result<()> {
+    kill(Pid::from_raw(pid as i32), signal)?;
+    Ok(())
+}
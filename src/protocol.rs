@@ -1,36 +1,626 @@
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Version of the wire protocol spoken by this build.
+///
+/// 2: clients send `Attach` or `Control` after authenticating, version 1
+///    clients are attached implicitly.
+/// 3: clients may send `Query` instead, answered with `Info`.
+/// 4: clients attach with `AttachAs` to identify themselves, `Info` is
+///    followed by `Clients`.
+/// 5: attached clients may send `WatchWindows` and `OpenWindow`.
+/// 6: the server may send `RequestEnvironment` to attached clients, answered
+///    with `Environment`.
+/// 7: messages after the handshake are sent in checked frames.
+/// 8: clients may send `AttachWindow` instead of `AttachAs`.
+/// 9: `Control` may carry `Link` and `SelectWindow`.
+pub const PROTOCOL_VERSION: u32 = 9;
+
+/// Oldest peer protocol version this build can still talk to.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Client may attach without sending input.
+pub const FEATURE_READ_ONLY: &str = "read-only";
+
+/// Server pings the client periodically and expects a `Pong` back.
+pub const FEATURE_HEARTBEAT: &str = "heartbeat";
+
+/// `Data` payloads above the threshold may be sent as `CompressedData`.
+pub const FEATURE_ZSTD: &str = "zstd";
+
+/// Either side may send `Clipboard` to share copied text.
+pub const FEATURE_CLIPBOARD: &str = "clipboard";
+
+/// Payloads smaller than this are not worth compressing.
+pub const COMPRESSION_THRESHOLD: usize = 512;
+
+/// zstd level: output bursts favour speed over ratio.
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// First byte of a checked frame. A legacy frame starts with the high byte
+/// of its length, which is 0 for any frame below `MAX_FRAME_SIZE`, so
+/// `decode` can tell both apart.
+const FRAME_MAGIC: u8 = 0xD7;
+
+/// Frames claiming to be larger are treated as damaged rather than allocated.
+const MAX_FRAME_SIZE: usize = 8 * 1024 * 1024;
+
+/// How often the server pings clients that negotiated heartbeats.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Silence after which a peer is considered dead.
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(15);
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Message {
+    /// Greeting exchanged first on every connection: the client sends its
+    /// version and features, the server answers with the negotiated ones.
+    ///
+    /// Must stay the first variant with unchanged fields so that any two
+    /// versions can always decode each other's greeting.
+    Hello { version: u32, features: Vec<String> },
     /// Terminal I/O data
     Data(Vec<u8>),
     /// Terminal resize notification
     Resize { cols: u16, rows: u16 },
     /// Client wants to detach
     Detach,
-    /// Shutdown the session
+    /// Shutdown the session (client to server), or the session is shutting
+    /// down and the connection will close (server to client)
     Shutdown,
+    /// Sent by a client after `Hello`, carrying the session token and
+    /// whether its input should be ignored
+    Auth { token: String, read_only: bool },
+    /// Server accepted the client's credentials
+    Authenticated,
+    /// Server refused the client, the connection is closed afterwards
+    Rejected { reason: String },
+    /// Liveness probe, answered with `Pong`
+    Ping,
+    /// Answer to `Ping`
+    Pong,
+    /// Terminal data compressed with zstd, only sent when negotiated
+    CompressedData(Vec<u8>),
+    /// Sent by a client after `Authenticated` to start streaming the session
+    Attach,
+    /// Sent by a client after `Authenticated` instead of `Attach` to run a
+    /// one-shot management request
+    Control(ControlRequest),
+    /// Server carried out a `Control` request
+    Done,
+    /// Sent by a client after `Authenticated` to ask for session metadata
+    Query,
+    /// Answer to `Query`, the connection is closed afterwards
+    Info(SessionInfo),
+    /// Text copied in the desktop (server to client) or pasted by the user
+    /// (client to server), only sent when negotiated
+    Clipboard(Vec<u8>),
+    /// `Attach` telling the server who is attaching, optionally detaching
+    /// every other client first
+    AttachAs { identity: ClientIdentity, detach_others: bool },
+    /// Clients attached to the session, sent after `Info`
+    Clients(Vec<AttachedClient>),
+    /// Sent by an attached client to be told about windows with `Window`,
+    /// starting with those already open
+    WatchWindows,
+    /// A window opened, closed or was retitled in the desktop, only sent
+    /// after `WatchWindows`
+    Window(WindowEvent),
+    /// Sent by an attached client to open a window of the named shortcut
+    OpenWindow { shortcut: String },
+    /// Server asks for the client's environment variables matching these
+    /// names, a trailing `*` matching any suffix
+    RequestEnvironment(Vec<String>),
+    /// Answer to `RequestEnvironment`, as name and value pairs
+    Environment(Vec<(String, String)>),
+    /// Client -> Server: attach like `AttachAs`, but to a single desktop
+    /// window given by id or title, answered with `Rejected` if there is none
+    AttachWindow { identity: ClientIdentity, window: String },
+}
+
+/// Change to the desktop's windows, which are numbered in opening order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum WindowEvent {
+    Opened { id: u64, shortcut: String, title: String },
+    /// The program in the window set its title with OSC 0 or 2.
+    TitleChanged { id: u64, title: String },
+    Closed { id: u64 },
+}
+
+/// Where a client runs, reported when attaching.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientIdentity {
+    pub hostname: String,
+    pub pid: u32,
+    pub tty: Option<String>,
+}
+
+impl std::fmt::Display for ClientIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pid {} on {}", self.pid, self.hostname)?;
+        if let Some(tty) = &self.tty {
+            write!(f, " ({})", tty)?;
+        }
+        Ok(())
+    }
+}
+
+/// A client currently attached to a session.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AttachedClient {
+    pub id: u64,
+    /// Transport the client connected over, e.g. `unix` or a TCP address.
+    pub peer: String,
+    /// Unknown for clients older than protocol version 4.
+    pub identity: Option<ClientIdentity>,
+    pub read_only: bool,
+    pub attached_secs: u64,
+}
+
+/// Metadata describing a running session.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionInfo {
+    pub name: String,
+    pub child_pid: i32,
+    pub uptime_secs: u64,
+    pub clients: u32,
+    pub cols: u16,
+    pub rows: u16,
+    pub shortcut_dir: String,
+}
+
+/// Management requests, answered with `Done` or `Rejected`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ControlRequest {
+    /// Terminate the session's child (SIGTERM, then SIGKILL after a grace period)
+    Kill,
+    /// Give the session a new name
+    Rename { name: String },
+    /// Detach every attached client
+    DetachClients,
+    /// Write bytes to the session as if typed by an attached client
+    SendKeys { data: Vec<u8> },
+    /// Serve the session under another name too, with its own clients and
+    /// current window, like a tmux session group
+    Link { name: String },
+    /// Show clients of this session name one window, or the whole desktop
+    SelectWindow { window: Option<String> },
+}
+
+impl ControlRequest {
+    /// Oldest server version that understands the request.
+    pub fn min_version(&self) -> u32 {
+        match self {
+            ControlRequest::Link { .. } | ControlRequest::SelectWindow { .. } => 9,
+            _ => 2,
+        }
+    }
+}
+
+/// Version and features both peers agreed on during the `Hello` exchange.
+#[derive(Debug, Clone)]
+pub struct Negotiated {
+    pub version: u32,
+    pub features: Vec<String>,
+}
+
+impl Negotiated {
+    pub fn has(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Framing for messages after the handshake.
+    pub fn framing(&self) -> Framing {
+        match self.version >= 7 {
+            true => Framing::Checked,
+            false => Framing::Legacy,
+        }
+    }
+}
+
+/// How messages are wrapped on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// Big-endian `u32` length, then the payload. Spoken by versions before 7
+    /// and during the handshake, so that every version can read a `Hello`
+    /// or `Rejected`.
+    Legacy,
+    /// `FRAME_MAGIC`, big-endian `u32` length and CRC32 of the payload, then
+    /// the payload. Damage is detected and the stream can be resynchronized.
+    Checked,
+}
+
+/// Why no message could be read.
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// The peer closed the connection, possibly in the middle of a frame.
+    Closed,
+    Io(std::io::Error),
+    /// A checked frame failed its checks, the frames after it can still be read.
+    CorruptFrame(String),
+    /// The stream is not at a frame boundary, legacy framing cannot recover.
+    Desynchronized(String),
+    /// The frame is intact but holds nothing this build understands, the
+    /// next frame can be read as usual.
+    UnknownMessage(String),
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::Closed => write!(f, "connection closed by peer"),
+            ProtocolError::Io(e) => write!(f, "{}", e),
+            ProtocolError::CorruptFrame(reason) => write!(f, "corrupt frame: {}", reason),
+            ProtocolError::Desynchronized(reason) => write!(f, "lost track of frames: {}", reason),
+            ProtocolError::UnknownMessage(reason) => write!(f, "malformed or unsupported protocol message: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<std::io::Error> for ProtocolError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => ProtocolError::Closed,
+            _ => ProtocolError::Io(e),
+        }
+    }
+}
+
+/// Features supported by this build.
+pub fn supported_features() -> Vec<String> {
+    vec![
+        FEATURE_READ_ONLY.to_owned(),
+        FEATURE_HEARTBEAT.to_owned(),
+        FEATURE_ZSTD.to_owned(),
+        FEATURE_CLIPBOARD.to_owned(),
+    ]
+}
+
+/// Greeting advertising this build's version and features.
+pub fn hello() -> Message {
+    Message::Hello {
+        version: PROTOCOL_VERSION,
+        features: supported_features(),
+    }
+}
+
+/// Pick the version to speak with a peer: the older of both, provided this
+/// build still supports it.
+pub fn negotiate_version(peer_version: u32) -> Result<u32, String> {
+    let version = peer_version.min(PROTOCOL_VERSION);
+
+    if version < MIN_PROTOCOL_VERSION {
+        return Err(format!(
+            "protocol version {} is not supported (this build speaks {} to {}), please upgrade",
+            peer_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        ));
+    }
+
+    Ok(version)
+}
+
+/// Features supported by both this build and the peer.
+pub fn common_features(peer_features: &[String]) -> Vec<String> {
+    supported_features()
+        .into_iter()
+        .filter(|feature| peer_features.contains(feature))
+        .collect()
+}
+
+/// Wrap terminal data, compressing it when allowed, large enough and
+/// actually smaller once compressed.
+pub fn data_message(bytes: Vec<u8>, compress: bool) -> Message {
+    if compress && bytes.len() >= COMPRESSION_THRESHOLD
+        && let Ok(compressed) = zstd::bulk::compress(&bytes, COMPRESSION_LEVEL)
+        && compressed.len() < bytes.len()
+    {
+        return Message::CompressedData(compressed);
+    }
+
+    Message::Data(bytes)
+}
+
+/// Decompress a `CompressedData` payload, no larger than a frame may be: a
+/// few bytes of zstd can claim gigabytes.
+pub fn decompress(compressed: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let decoder = zstd::stream::read::Decoder::new(compressed).context("corrupt compressed data")?;
+    let mut bytes = Vec::new();
+    // One byte over the limit tells a payload at the limit from a larger one.
+    decoder.take(MAX_FRAME_SIZE as u64 + 1).read_to_end(&mut bytes).context("corrupt compressed data")?;
+    if bytes.len() > MAX_FRAME_SIZE {
+        anyhow::bail!("compressed data over the {} byte limit", MAX_FRAME_SIZE);
+    }
+    Ok(bytes)
 }
 
-/// Encode a message with length-prefix framing
-pub fn encode(msg: &Message) -> anyhow::Result<Vec<u8>> {
+/// Encode a message into a frame
+pub fn encode(msg: &Message, framing: Framing) -> anyhow::Result<Vec<u8>> {
     let payload = bincode::serialize(msg)?;
     let len = (payload.len() as u32).to_be_bytes();
-    let mut buf = Vec::with_capacity(4 + payload.len());
-    buf.extend_from_slice(&len);
+    let mut buf = Vec::with_capacity(9 + payload.len());
+    if framing == Framing::Checked {
+        buf.push(FRAME_MAGIC);
+        buf.extend_from_slice(&len);
+        buf.extend_from_slice(&crc32fast::hash(&payload).to_be_bytes());
+    } else {
+        buf.extend_from_slice(&len);
+    }
     buf.extend_from_slice(&payload);
     Ok(buf)
 }
 
-/// Read a length-prefixed message from a reader
-pub async fn decode(reader: &mut (impl tokio::io::AsyncReadExt + Unpin)) -> anyhow::Result<Message> {
-    let mut len_buf = [0u8; 4];
-    reader.read_exact(&mut len_buf).await?;
-    let len = u32::from_be_bytes(len_buf) as usize;
+/// Read a message from a reader, in whichever framing it was sent
+pub async fn decode(reader: &mut (impl AsyncReadExt + Unpin)) -> Result<Message, ProtocolError> {
+    match reader.read_u8().await? {
+        FRAME_MAGIC => decode_checked(reader).await,
+        0 => {
+            let mut len_buf = [0u8; 3];
+            reader.read_exact(&mut len_buf).await?;
+            let len = u32::from_be_bytes([0, len_buf[0], len_buf[1], len_buf[2]]) as usize;
+            if len > MAX_FRAME_SIZE {
+                return Err(ProtocolError::Desynchronized(format!("frame of {} bytes", len)));
+            }
+
+            let mut payload = vec![0u8; len];
+            reader.read_exact(&mut payload).await?;
+            deserialize(&payload)
+        }
+        other => Err(ProtocolError::Desynchronized(format!("unexpected byte {:#04x} at frame start", other))),
+    }
+}
+
+/// Read the rest of a checked frame, after its magic byte.
+async fn decode_checked(reader: &mut (impl AsyncReadExt + Unpin)) -> Result<Message, ProtocolError> {
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header).await?;
+    let (len, crc) = frame_header(&header);
+    if len > MAX_FRAME_SIZE {
+        return Err(ProtocolError::CorruptFrame(format!("frame of {} bytes", len)));
+    }
 
     let mut payload = vec![0u8; len];
     reader.read_exact(&mut payload).await?;
+    if crc32fast::hash(&payload) != crc {
+        return Err(ProtocolError::CorruptFrame(String::from("checksum mismatch")));
+    }
+
+    deserialize(&payload)
+}
+
+/// Payload length and CRC32 from the 8 header bytes following a magic byte.
+fn frame_header(header: &[u8]) -> (usize, u32) {
+    let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let crc = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+    (len, crc)
+}
+
+/// Bytes read from the stream at once by `FrameReader`.
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Where `bytes`, starting with a magic byte, stands as a checked frame.
+enum FrameCheck {
+    /// An intact frame of this many bytes, header included.
+    Intact(usize),
+    Damaged(String),
+    /// More bytes are needed to tell.
+    Incomplete,
+}
+
+fn check_frame(bytes: &[u8]) -> FrameCheck {
+    if bytes.len() < 9 {
+        return FrameCheck::Incomplete;
+    }
+    let (len, crc) = frame_header(&bytes[1..9]);
+    if len > MAX_FRAME_SIZE {
+        return FrameCheck::Damaged(format!("frame of {} bytes", len));
+    }
+    match bytes.get(9..9 + len) {
+        None => FrameCheck::Incomplete,
+        Some(payload) if crc32fast::hash(payload) != crc => FrameCheck::Damaged(String::from("checksum mismatch")),
+        Some(_) => FrameCheck::Intact(9 + len),
+    }
+}
+
+/// Reads a stream of frames. Checked frames are buffered until the frame
+/// holding them checks out: a damaged frame is rescanned for the next magic
+/// byte from right after its own, whatever length it claims, so a damaged
+/// length field loses no frame after it.
+struct FrameReader<R> {
+    reader: R,
+    framing: Framing,
+    buffer: Vec<u8>,
+    /// Offsets in `buffer` of later magic bytes that may start an intact
+    /// frame, while the one at its start is incomplete.
+    candidates: Vec<usize>,
+    /// How far `buffer` was searched for candidates.
+    scanned: usize,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    fn new(reader: R, framing: Framing) -> Self {
+        Self { reader, framing, buffer: Vec::new(), candidates: Vec::new(), scanned: 1 }
+    }
+
+    /// The next message. After an error in checked framing, the next call
+    /// goes on with the frame after the damage.
+    async fn next(&mut self) -> Result<Message, ProtocolError> {
+        if self.framing == Framing::Legacy {
+            return decode(&mut self.reader).await;
+        }
+        loop {
+            if let Some(&first) = self.buffer.first()
+                && first != FRAME_MAGIC
+            {
+                self.skip_to_magic(1);
+                return Err(ProtocolError::Desynchronized(format!("unexpected byte {:#04x} at frame start", first)));
+            }
+
+            if !self.buffer.is_empty() {
+                match check_frame(&self.buffer) {
+                    FrameCheck::Intact(len) => {
+                        let message = deserialize(&self.buffer[9..len]);
+                        self.consume(len);
+                        return message;
+                    }
+                    FrameCheck::Damaged(reason) => {
+                        self.skip_to_magic(1);
+                        return Err(ProtocolError::CorruptFrame(reason));
+                    }
+                    // A length too large for what follows would otherwise
+                    // hold every later frame back until it is all read.
+                    FrameCheck::Incomplete if self.intact_candidate() => {
+                        self.skip_to_magic(1);
+                        return Err(ProtocolError::CorruptFrame(String::from("frame cut short by the next one")));
+                    }
+                    FrameCheck::Incomplete => {}
+                }
+            }
+
+            self.buffer.reserve(READ_CHUNK);
+            if self.reader.read_buf(&mut self.buffer).await? == 0 {
+                return Err(ProtocolError::Closed);
+            }
+        }
+    }
+
+    /// Whether a later magic byte in the buffer starts an intact frame.
+    fn intact_candidate(&mut self) -> bool {
+        for offset in self.scanned..self.buffer.len() {
+            if self.buffer[offset] == FRAME_MAGIC {
+                self.candidates.push(offset);
+            }
+        }
+        self.scanned = self.buffer.len();
+
+        let buffer = &self.buffer;
+        let mut found = false;
+        self.candidates.retain(|&offset| match check_frame(&buffer[offset..]) {
+            FrameCheck::Intact(_) => {
+                found = true;
+                true
+            }
+            FrameCheck::Damaged(_) => false,
+            FrameCheck::Incomplete => true,
+        });
+        found
+    }
+
+    /// Drop the bytes before the first magic byte at or after `from`.
+    fn skip_to_magic(&mut self, from: usize) {
+        let start = self.buffer.iter().skip(from).position(|&byte| byte == FRAME_MAGIC).map_or(self.buffer.len(), |at| from + at);
+        self.consume(start);
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.buffer.drain(..len);
+        self.candidates.clear();
+        self.scanned = 1;
+    }
+}
+
+fn deserialize(payload: &[u8]) -> Result<Message, ProtocolError> {
+    bincode::deserialize(payload).map_err(|e| ProtocolError::UnknownMessage(e.to_string()))
+}
+
+/// Decode messages on a dedicated task and hand them over through a channel.
+///
+/// `decode` is not cancel-safe: using it directly in a `select!` loses a
+/// partially read frame whenever another branch wins. Messages this build
+/// does not know are skipped, damaged checked frames are resynchronized
+/// past. The channel closes when the peer disconnects or a legacy stream
+/// loses track of its frames, the task then returns the reason.
+pub fn spawn_reader<R>(reader: R, framing: Framing) -> (mpsc::Receiver<Message>, JoinHandle<Option<ProtocolError>>)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(64);
+    let task = tokio::spawn(async move {
+        let mut frames = FrameReader::new(reader, framing);
+        loop {
+            match frames.next().await {
+                Ok(msg) => {
+                    if tx.send(msg).await.is_err() {
+                        return None;
+                    }
+                }
+                Err(ProtocolError::UnknownMessage(_)) => {}
+                Err(ProtocolError::CorruptFrame(_) | ProtocolError::Desynchronized(_)) if framing == Framing::Checked => {}
+                Err(e) => return Some(e),
+            }
+        }
+    });
+    (rx, task)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(message: Message) -> Vec<u8> {
+        match message {
+            Message::Data(bytes) => bytes,
+            other => panic!("expected data, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_round_trip_in_both_framings() {
+        for framing in [Framing::Legacy, Framing::Checked] {
+            let frame = encode(&Message::Data(b"hello".to_vec()), framing).unwrap();
+            assert_eq!(data(decode(&mut frame.as_slice()).await.unwrap()), b"hello");
+        }
+    }
+
+    #[tokio::test]
+    async fn damaged_checked_frame_is_refused() {
+        let mut frame = encode(&Message::Data(b"hello".to_vec()), Framing::Checked).unwrap();
+        let last = frame.len() - 1;
+        frame[last] ^= 0xFF;
+        assert!(matches!(decode(&mut frame.as_slice()).await, Err(ProtocolError::CorruptFrame(_))));
+    }
+
+    #[tokio::test]
+    async fn oversized_frame_is_not_allocated() {
+        let mut reader: &[u8] = &[0x00, 0x90, 0x00, 0x00];
+        assert!(matches!(decode(&mut reader).await, Err(ProtocolError::Desynchronized(_))));
+    }
+
+    #[tokio::test]
+    async fn checked_stream_skips_to_the_next_frame() {
+        let mut stream = vec![0x01, 0x02, 0x03];
+        stream.extend(encode(&Message::Data(b"after".to_vec()), Framing::Checked).unwrap());
+        let mut frames = FrameReader::new(stream.as_slice(), Framing::Checked);
+
+        assert!(matches!(frames.next().await, Err(ProtocolError::Desynchronized(_))));
+        assert_eq!(data(frames.next().await.unwrap()), b"after");
+    }
+
+    #[tokio::test]
+    async fn damaged_length_loses_no_later_frame() {
+        // Claiming less than the payload, then more than the whole stream.
+        for len in [2u32, 1000] {
+            let mut stream = encode(&Message::Data(b"damaged".to_vec()), Framing::Checked).unwrap();
+            stream[1..5].copy_from_slice(&len.to_be_bytes());
+            stream.extend(encode(&Message::Data(b"after".to_vec()), Framing::Checked).unwrap());
+            let mut frames = FrameReader::new(stream.as_slice(), Framing::Checked);
 
-    let msg = bincode::deserialize(&payload)?;
-    Ok(msg)
+            assert!(matches!(frames.next().await, Err(ProtocolError::CorruptFrame(_))));
+            let message = loop {
+                match frames.next().await {
+                    Ok(message) => break message,
+                    Err(ProtocolError::CorruptFrame(_) | ProtocolError::Desynchronized(_)) => {}
+                    Err(e) => panic!("lost the frame after a damaged length of {}: {}", len, e),
+                }
+            };
+            assert_eq!(data(message), b"after");
+            assert!(matches!(frames.next().await, Err(ProtocolError::Closed)));
+        }
+    }
 }
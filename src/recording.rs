@@ -0,0 +1,168 @@
+use crate::client::{self, AttachOptions, ConnectOptions};
+use crate::protocol::{self, Message};
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Largest width and height a recording may claim, its screen is allocated
+/// from them.
+const MAX_CAST_SIZE: u16 = 1000;
+
+/// First line of an asciicast v2 file.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Header {
+    pub version: u32,
+    pub width: u16,
+    pub height: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// A parsed recording: its size and the output it holds, with the seconds
+/// since the start at which each piece was written.
+pub struct Cast {
+    pub header: Header,
+    pub output: Vec<(f64, Vec<u8>)>,
+}
+
+/// Read an asciicast v2 file, keeping only output events.
+pub fn load(path: &Path) -> anyhow::Result<Cast> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+
+    let header: Header = serde_json::from_str(lines.next().context("The recording is empty")?)
+        .context("The recording does not start with an asciicast header")?;
+    if header.version != 2 {
+        bail!("Unsupported asciicast version {}, only version 2 is", header.version);
+    }
+    if !(1..=MAX_CAST_SIZE).contains(&header.width) || !(1..=MAX_CAST_SIZE).contains(&header.height) {
+        bail!("Unsupported recording size {}x{}, expected 1 to {} on each side", header.width, header.height, MAX_CAST_SIZE);
+    }
+
+    let mut output = Vec::new();
+    for (number, line) in lines.enumerate() {
+        let (time, kind, data): (f64, String, String) =
+            serde_json::from_str(line).with_context(|| format!("Invalid event on line {}", number + 2))?;
+        if kind == "o" {
+            output.push((time, data.into_bytes()));
+        }
+    }
+
+    Ok(Cast { header, output })
+}
+
+/// `desktop-tui record`: watch a session read-only and write what it shows
+/// to `path` as asciicast v2, until it ends or Ctrl+C.
+///
+/// The recorder reports no size, so it never shrinks the session.
+pub async fn record(connection: ConnectOptions, path: &Path) -> anyhow::Result<()> {
+    let (info, _) = client::query(&connection).await?;
+    let session = connection.session.clone();
+
+    let options = AttachOptions { connection, read_only: true, reconnect: false, detach_others: false, control: false, window: None };
+    let (stream, negotiated) = client::open(&options, false).await?;
+    let framing = negotiated.framing();
+    let (reader, mut writer) = tokio::io::split(stream);
+    let (mut server_rx, reader_task) = protocol::spawn_reader(reader, framing);
+
+    let mut file = BufWriter::new(File::create(path).with_context(|| format!("Failed to create {:?}", path))?);
+    let header = Header {
+        version: 2,
+        width: info.cols,
+        height: info.rows,
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|time| time.as_secs()),
+        title: Some(session.clone()),
+    };
+    writeln!(file, "{}", serde_json::to_string(&header)?)?;
+    eprintln!("[record] Recording session '{}' to {:?} (Ctrl+C to stop).", session, path);
+
+    let start = Instant::now();
+    // Bytes of a UTF-8 character split across messages, written with the rest of it.
+    let mut pending = Vec::new();
+    // Listening from now on, a Ctrl+C between two messages is not missed.
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        let message = tokio::select! {
+            message = server_rx.recv() => message,
+            _ = &mut ctrl_c => break,
+        };
+
+        let data = match message {
+            Some(Message::Data(bytes)) => bytes,
+            Some(Message::CompressedData(compressed)) => protocol::decompress(&compressed)?,
+            Some(Message::Ping) => {
+                client::send(&mut writer, &Message::Pong, framing).await?;
+                continue;
+            }
+            Some(Message::Detach) | Some(Message::Shutdown) | None => break,
+            Some(_) => continue,
+        };
+
+        pending.extend(data);
+        let complete = match std::str::from_utf8(&pending) {
+            Ok(_) => pending.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => pending.len(),
+        };
+        let text = String::from_utf8_lossy(&pending[..complete]).into_owned();
+        pending.drain(..complete);
+
+        if !text.is_empty() {
+            let event = (start.elapsed().as_secs_f64(), "o", text);
+            writeln!(file, "{}", serde_json::to_string(&event)?)?;
+            file.flush()?;
+        }
+    }
+
+    reader_task.abort();
+    file.flush()?;
+    eprintln!("[record] Stopped after {:.1}s.", start.elapsed().as_secs_f64());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Load `content` as a recording from a file of its own.
+    fn load_str(name: &str, content: &str) -> anyhow::Result<Cast> {
+        let path = std::env::temp_dir().join(format!("desktop-tui-test-{}-{}.cast", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        let cast = load(&path);
+        let _ = fs::remove_file(&path);
+        cast
+    }
+
+    #[test]
+    fn output_events_are_kept_in_order() {
+        let cast = load_str(
+            "valid",
+            "{\"version\": 2, \"width\": 80, \"height\": 24}\n[0.5, \"o\", \"hello\"]\n[0.7, \"i\", \"typed\"]\n\n[1.25, \"o\", \"\\u001b[2J\"]\n",
+        )
+        .unwrap();
+        assert_eq!((cast.header.width, cast.header.height), (80, 24));
+        assert_eq!(cast.output, vec![(0.5, b"hello".to_vec()), (1.25, b"\x1b[2J".to_vec())]);
+    }
+
+    #[test]
+    fn unusable_recordings_are_refused() {
+        let invalid = [
+            ("empty", ""),
+            ("version", "{\"version\": 1, \"width\": 80, \"height\": 24}\n"),
+            ("zero", "{\"version\": 2, \"width\": 0, \"height\": 24}\n"),
+            ("huge", "{\"version\": 2, \"width\": 80, \"height\": 60000}\n"),
+            ("event", "{\"version\": 2, \"width\": 80, \"height\": 24}\n[\"o\", 0.5]\n"),
+        ];
+        for (name, content) in invalid {
+            assert!(load_str(name, content).is_err(), "the {} recording was accepted", name);
+        }
+    }
+}
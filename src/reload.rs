@@ -0,0 +1,131 @@
+use crate::accessibility;
+use crate::config::{self, Config};
+use crate::glyphs;
+use crate::terminal_emulation;
+use appcui::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+/// Notices the config file changing, polled by the desktop's timer.
+pub struct ConfigWatcher {
+    /// The file read at start, or the default one, which may not exist yet.
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    /// Watch the file the current configuration was read from.
+    pub fn watch_current() -> Self {
+        let path = config::get().file.clone().or_else(config::default_path);
+        let modified = path.as_deref().and_then(modified);
+        Self { path, modified }
+    }
+
+    /// Reload the configuration if the file changed since the last call, with
+    /// a summary for the user.
+    pub fn poll(&mut self) -> Option<String> {
+        let path = self.path.clone()?;
+        let modified = modified(&path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        let overrides = config::get().overrides;
+        let reloaded = config::load(Some(&path)).map(|mut config| {
+            overrides.apply(&mut config);
+            config
+        });
+        let summary = match reloaded.and_then(apply) {
+            Ok(summary) => summary,
+            Err(e) => {
+                warn!("Kept the previous configuration: {:#}", e);
+                format!("{:?} has an error, the previous configuration is kept:\n{:#}", path, e)
+            }
+        };
+        Some(summary)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Use `new` from now on, telling what changed live and what waits for a restart.
+fn apply(new: Config) -> anyhow::Result<String> {
+    let old = config::get();
+    let palette = new.palette()?;
+    let substitutions = new.substitutions()?;
+
+    let mut applied = Vec::new();
+    let mut restart = Vec::new();
+    let mut changed = |name: &'static str, differs: bool, live: bool| match (differs, live) {
+        (false, _) => {}
+        (true, true) => applied.push(name),
+        (true, false) => restart.push(name),
+    };
+    changed("keybindings", old.keybindings != new.keybindings, true);
+    changed("widgets", old.widgets != new.widgets, true);
+    changed("palette", old.palette != new.palette, true);
+    changed("color mode", old.color_mode != new.color_mode, true);
+    changed("substitutions", old.substitutions != new.substitutions || old.ascii_only != new.ascii_only, true);
+    changed("idle delay", old.idle_after != new.idle_after, true);
+    changed("clipboard history", old.clipboard_history != new.clipboard_history, true);
+    changed("window geometry", old.remember_geometry != new.remember_geometry, true);
+    changed("theme", old.theme != new.theme, false);
+    changed("backend", old.backend != new.backend, false);
+    changed("shortcut directory", old.shortcut_dir != new.shortcut_dir, false);
+
+    terminal_emulation::set_palette(palette);
+    accessibility::set_mode(new.color_mode);
+    glyphs::init(substitutions, new.ascii_only);
+    config::init(new);
+
+    info!("Reloaded the configuration: {:?} applied, {:?} need a restart.", applied, restart);
+
+    let mut summary = match applied.is_empty() {
+        true => String::from("Nothing to apply."),
+        false => format!("Applied: {}.", applied.join(", ")),
+    };
+    if !restart.is_empty() {
+        summary.push_str(&format!("\nRestart the desktop for: {}.", restart.join(", ")));
+    }
+    Ok(summary)
+}
+
+/// Tells what a configuration reload did.
+#[Window(events = ButtonEvents)]
+pub struct ReloadNotice {
+    ok: Handle<Button>,
+}
+
+impl ReloadNotice {
+    pub fn new(summary: &str) -> Self {
+        let width = summary.lines().map(|line| line.chars().count()).max().unwrap_or(0).clamp(30, 100) as u32 + 4;
+        let height = summary.lines().count() as u32 + 5;
+
+        let mut win = Self {
+            base: Window::new(
+                "Configuration reloaded",
+                LayoutBuilder::new().alignment(Alignment::TopRight).width(width).height(height).build(),
+                window::Flags::None,
+            ),
+            ok: Handle::None,
+        };
+        win.add(Label::new(summary, LayoutBuilder::new().x(1).y(0).width(width - 4).height(height - 4).build()));
+        win.ok = win.add(Button::new("&OK", LayoutBuilder::new().x(width as i32 / 2 - 6).y(height as i32 - 4).width(10).build(), button::Type::Normal));
+        win
+    }
+}
+
+impl ButtonEvents for ReloadNotice {
+    fn on_pressed(&mut self, handle: Handle<Button>) -> EventProcessStatus {
+        if handle != self.ok {
+            return EventProcessStatus::Ignored;
+        }
+        self.close();
+        EventProcessStatus::Processed
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::VecDeque;
+
+/// Default number of PTY output bytes kept for replay to newly attached clients.
+pub const DEFAULT_CAPACITY: usize = 256 * 1024;
+
+/// Sequences after which everything previously written is no longer visible.
+const SCREEN_RESETS: [&[u8]; 3] = [
+    b"\x1b[2J",     // Erase display
+    b"\x1b[?1049h", // Enter alternate screen
+    b"\x1bc",       // RIS: full reset
+];
+
+/// Bounded buffer of raw PTY output replayed when a client attaches mid-session.
+///
+/// Output before the last full screen reset is dropped since it cannot be
+/// visible anymore, which keeps the replay short for full-screen programs.
+pub struct ReplayBuffer {
+    capacity: usize,
+    bytes: VecDeque<u8>,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            bytes: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        let data = match last_screen_reset(data) {
+            Some(start) => {
+                self.bytes.clear();
+                &data[start..]
+            }
+            None => data,
+        };
+
+        let data = &data[data.len().saturating_sub(self.capacity)..];
+        let overflow = (self.bytes.len() + data.len()).saturating_sub(self.capacity);
+        self.bytes.drain(..overflow);
+        self.bytes.extend(data);
+
+        if overflow > 0 {
+            self.skip_to_escape();
+        }
+    }
+
+    /// Copy the buffered output, oldest first.
+    pub fn snapshot(&self) -> Vec<u8> {
+        self.bytes.iter().copied().collect()
+    }
+
+    /// After trimming the front, the buffer may start in the middle of an
+    /// escape sequence: drop bytes up to the next ESC so replay starts clean.
+    fn skip_to_escape(&mut self) {
+        let start = self.bytes.iter().position(|&b| b == 0x1b).unwrap_or(self.bytes.len());
+        self.bytes.drain(..start);
+    }
+}
+
+fn last_screen_reset(data: &[u8]) -> Option<usize> {
+    SCREEN_RESETS
+        .iter()
+        .filter_map(|seq| {
+            data.windows(seq.len())
+                .rposition(|window| window == *seq)
+        })
+        .max()
+}
@@ -0,0 +1,122 @@
+use appcui::prelude::*;
+
+const WIDTH: u32 = 72;
+const HEIGHT: u32 = 20;
+
+/// A line of a `SearchList`, with the text the search looks in.
+pub struct Item {
+    pub line: String,
+    pub text: String,
+}
+
+impl Item {
+    /// An item searched by what it shows.
+    pub fn new(line: String) -> Self {
+        Self { text: line.clone(), line }
+    }
+}
+
+/// Search a list and pick an item with one of the buttons, the command
+/// palette, clipboard history and processes are all one. Answers the index
+/// of the button pressed and that of the picked item.
+#[ModalWindow(events = ButtonEvents+TextFieldEvents, response = (usize, usize))]
+pub struct SearchList {
+    items: Vec<Item>,
+    /// Indexes in `items` of the listed items, in list order.
+    shown: Vec<usize>,
+    search: Handle<TextField>,
+    list: Handle<ListBox>,
+    buttons: Vec<Handle<Button>>,
+    cancel: Handle<Button>,
+}
+
+impl SearchList {
+    pub fn new(title: &str, items: Vec<Item>, buttons: &[&str]) -> Self {
+        let mut win = Self {
+            base: ModalWindow::new(
+                title,
+                LayoutBuilder::new().alignment(Alignment::Center).width(WIDTH).height(HEIGHT).build(),
+                window::Flags::None,
+            ),
+            items,
+            shown: Vec::new(),
+            search: Handle::None,
+            list: Handle::None,
+            buttons: Vec::new(),
+            cancel: Handle::None,
+        };
+
+        win.add(Label::new("&Search:", LayoutBuilder::new().x(1).y(1).width(8).build()));
+        win.search = win.add(TextField::new("", LayoutBuilder::new().x(10).y(1).width(58).build(), textfield::Flags::None));
+        win.list = win.add(ListBox::new(LayoutBuilder::new().x(1).y(3).width(68).height(12).build(), listbox::Flags::ScrollBars));
+
+        // Centered in a row, narrower when there are many.
+        let count = buttons.len() as i32 + 1;
+        let (width, gap) = if count > 3 { (12, 2) } else { (14, 3) };
+        let start = (WIDTH as i32 - (count * width + (count - 1) * gap)) / 2;
+        let button = |caption: &str, index: i32| {
+            Button::new(caption, LayoutBuilder::new().x(start + index * (width + gap)).y(16).width(width as u32).build(), button::Type::Normal)
+        };
+        for (index, caption) in buttons.iter().enumerate() {
+            let handle = win.add(button(caption, index as i32));
+            win.buttons.push(handle);
+        }
+        win.cancel = win.add(button("Cancel", count - 1));
+
+        win.refresh("");
+        win
+    }
+
+    /// List the items holding every word of `filter`, ignoring case.
+    fn refresh(&mut self, filter: &str) {
+        let filter = filter.to_lowercase();
+        let words: Vec<&str> = filter.split_whitespace().collect();
+        self.shown = (0..self.items.len())
+            .filter(|&index| {
+                let text = self.items[index].text.to_lowercase();
+                words.iter().all(|word| text.contains(word))
+            })
+            .collect();
+
+        let lines: Vec<String> = self.shown.iter().map(|&index| self.items[index].line.clone()).collect();
+        let list = self.list;
+        if let Some(list) = self.control_mut(list) {
+            list.clear();
+            for line in &lines {
+                list.add(line);
+            }
+        }
+    }
+
+    /// The item under the list's cursor.
+    fn selected(&self) -> Option<usize> {
+        let list = self.control(self.list)?;
+        self.shown.get(list.index()).copied()
+    }
+}
+
+impl ButtonEvents for SearchList {
+    fn on_pressed(&mut self, handle: Handle<Button>) -> EventProcessStatus {
+        if handle == self.cancel {
+            self.exit();
+        } else if let Some(button) = self.buttons.iter().position(|&button| button == handle)
+            && let Some(item) = self.selected()
+        {
+            self.exit_with((button, item));
+        }
+        EventProcessStatus::Processed
+    }
+}
+
+impl TextFieldEvents for SearchList {
+    fn on_text_changed(&mut self, handle: Handle<TextField>) -> EventProcessStatus {
+        if handle != self.search {
+            return EventProcessStatus::Ignored;
+        }
+        let Some(filter) = self.control(handle).map(|search| search.text().to_owned()) else {
+            return EventProcessStatus::Ignored;
+        };
+        self.refresh(&filter);
+        EventProcessStatus::Processed
+    }
+}
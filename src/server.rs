@@ -1,27 +1,81 @@
-use crate::protocol::{self, Message};
-use anyhow::{anyhow, Context};
+use crate::activity::{self, Activity, ActivityLog, ActivityWriter};
+use crate::auth;
+use crate::config::{self, Overrides};
+use crate::crash;
+use crate::daemon::{self, Ready};
+use crate::environment::{self, EnvVar};
+use crate::events::{self, DesktopCommand, DesktopEvent};
+use crate::hooks::{self, Hook, HookEvent};
+use crate::layout;
+use crate::protocol::{self, AttachedClient, ClientIdentity, ControlRequest, Framing, Message, ProtocolError, SessionInfo, WindowEvent};
+use crate::replay::{self, ReplayBuffer};
+use crate::transport::{self, BoxedStream, Incoming, ListenAddr, Listener};
+use anyhow::{anyhow, bail, Context};
 use nix::pty::{openpty, Winsize};
 use nix::sys::signal::{kill, Signal};
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::os::fd::{FromRawFd, IntoRawFd};
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd};
+use std::os::unix::fs::FileTypeExt;
 use std::os::unix::process::CommandExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{UnixListener, UnixStream};
-use tokio::sync::{broadcast, Mutex};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch, Mutex, Notify};
+use tokio::task::JoinHandle;
+use tracing::level_filters::LevelFilter;
+use tracing::{debug, error, info, warn, Instrument};
+
+/// Signals a child dies of by its own fault, rather than because it was told to.
+const CRASH_SIGNALS: [Signal; 5] = [Signal::SIGSEGV, Signal::SIGABRT, Signal::SIGBUS, Signal::SIGILL, Signal::SIGFPE];
 
 /// Default terminal size used when spawning the child PTY process.
 const DEFAULT_COLS: u16 = 220;
 const DEFAULT_ROWS: u16 = 50;
 
+/// Output read from the PTY within this long of the previous batch counts as
+/// a burst and is held back for `COALESCE_DELAY` to gather more.
+const COALESCE_WINDOW: Duration = Duration::from_millis(10);
+const COALESCE_DELAY: Duration = Duration::from_millis(4);
+
+/// A burst is published early once this much output was gathered.
+const COALESCE_MAX_BYTES: usize = 64 * 1024;
+
+/// Output a client may lag behind before its backlog is dropped for a resync.
+const MAX_PENDING_OUTPUT: usize = 1024 * 1024;
+
+/// How long a client has to complete the handshake before being dropped.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a killed child gets to exit after SIGTERM before SIGKILL.
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// How long clients get to hear about a shutdown before the server exits.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
+
+/// How often attached clients are checked against `--idle-timeout`.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long before the idle timeout a client is warned, at most half the timeout.
+const IDLE_WARNING: Duration = Duration::from_secs(60);
+
+/// Longest token typed into a locked client before the attempt is dropped.
+const MAX_UNLOCK_INPUT: usize = 256;
+
 /// Return the session directory, creating it if needed.
-fn session_dir() -> anyhow::Result<PathBuf> {
+///
+/// The directory holds sockets and tokens so it is kept private to the owner.
+pub fn session_dir() -> anyhow::Result<PathBuf> {
     let home = std::env::var("HOME").context("HOME env var not set")?;
     let dir = PathBuf::from(home).join(".local/share/desktop-tui");
     fs::create_dir_all(&dir)?;
+    auth::set_mode(&dir, 0o700)?;
     Ok(dir)
 }
 
@@ -30,18 +84,866 @@ pub fn socket_path(session: &str) -> anyhow::Result<PathBuf> {
     Ok(session_dir()?.join(format!("{}.sock", session)))
 }
 
-pub async fn serve(shortcut_dir: PathBuf, session: String) -> anyhow::Result<()> {
+/// Refuse names that are not a plain file name, or that `attach` would take
+/// for a `user@host` target.
+pub fn check_session_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty() || name.contains('/') || name.contains('@') {
+        bail!("invalid session name '{}', it must not be empty or contain '/' or '@'", name);
+    }
+    Ok(())
+}
+
+/// Whether a server still runs the session: its socket accepts connections
+/// or the pidfile of a daemonized server names a live desktop-tui process.
+pub fn session_alive(dir: &Path, session: &str) -> bool {
+    let sock_path = dir.join(format!("{}.sock", session));
+    std::os::unix::net::UnixStream::connect(&sock_path).is_ok()
+        || daemon::running_pid(&daemon::pid_path(dir, session)).is_some()
+}
+
+/// Names of the sessions a server still runs, for shell completion.
+pub fn running_sessions() -> Vec<String> {
+    let Ok(dir) = session_dir() else { return Vec::new() };
+    let Ok(entries) = fs::read_dir(&dir) else { return Vec::new() };
+
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("sock") => path.file_stem().and_then(|s| s.to_str()).map(str::to_owned),
+                _ => None,
+            }
+        })
+        .filter(|name| session_alive(&dir, name))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Remove what a crashed server left behind: its socket, token and pidfile.
+///
+/// A saved layout is kept so the session can still be resumed.
+pub fn remove_stale_session(dir: &Path, session: &str) -> anyhow::Result<()> {
+    let files = [
+        dir.join(format!("{}.sock", session)),
+        auth::token_path(dir, session),
+        daemon::pid_path(dir, session),
+    ];
+    for file in files {
+        if file.exists() {
+            fs::remove_file(&file).with_context(|| format!("failed to remove {:?}", file))?;
+        }
+    }
+    Ok(())
+}
+
+/// Options for `desktop-tui serve`.
+pub struct ServeOptions {
+    pub shortcut_dir: PathBuf,
+    pub session: String,
+    /// Additional listeners besides the session's Unix socket.
+    pub listen: Vec<ListenAddr>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// Fixed PTY size, overriding the size negotiated between clients.
+    pub force_size: Option<TermSize>,
+    /// Reopen the layout saved when the session was last terminated.
+    pub resume: bool,
+    /// Signaled once clients can connect, when running as a daemon.
+    pub ready: Option<Ready>,
+    /// Commands run on session events.
+    pub hooks: Vec<Hook>,
+    /// Shell command run in the PTY instead of the desktop.
+    pub command: Option<String>,
+    /// Variables set for the child on top of the server's environment.
+    pub env: Vec<EnvVar>,
+    /// Client variables taken over on attach, `environment::DEFAULT_UPDATE_ENVIRONMENT` if empty.
+    pub update_environment: Vec<String>,
+    /// Record output and session events for `desktop-tui logs`.
+    pub activity_log: bool,
+    /// Input-less time after which `idle_action` is taken on a client.
+    pub idle_timeout: Option<Duration>,
+    pub idle_action: IdleAction,
+    /// Config file the desktop is started with, and the flags overriding it.
+    pub config_file: Option<PathBuf>,
+    pub overrides: Overrides,
+    /// Log level and file of the server, passed on to the desktop.
+    pub log_level: LevelFilter,
+    pub log_file: Option<PathBuf>,
+}
+
+/// A terminal size, written `COLSxROWS` on the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TermSize {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+impl FromStr for TermSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (cols, rows) = s
+            .split_once('x')
+            .ok_or_else(|| anyhow!("size '{}' must be written COLSxROWS", s))?;
+        let size = TermSize {
+            cols: cols.trim().parse().context("invalid column count")?,
+            rows: rows.trim().parse().context("invalid row count")?,
+        };
+
+        if size.cols == 0 || size.rows == 0 {
+            bail!("size '{}' must not be empty", s);
+        }
+
+        Ok(size)
+    }
+}
+
+/// What happens to a client that sent no input for `--idle-timeout`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdleAction {
+    Detach,
+    /// Stop showing the session until the session token is typed again.
+    Lock,
+}
+
+impl FromStr for IdleAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "detach" => Ok(IdleAction::Detach),
+            "lock" => Ok(IdleAction::Lock),
+            _ => bail!("unknown idle action '{}', expected detach or lock", s),
+        }
+    }
+}
+
+/// Per-connection state the session needs to know about.
+struct ClientState {
+    /// Last size reported by the client, if any.
+    size: Option<TermSize>,
+    /// Whether the client negotiated clipboard sharing.
+    clipboard: bool,
+    /// Whether the client asked to be told about windows.
+    windows: bool,
+    output: Arc<ClientOutput>,
+    peer: String,
+    identity: Option<ClientIdentity>,
+    read_only: bool,
+    attached: Instant,
+    /// Linked session name the client attached through, see `serve --group`.
+    link: Option<String>,
+    /// Desktop window the client is shown alone, the whole desktop if `None`.
+    window: Option<u64>,
+    /// Set for `attach --window`, the window then stays put rather than
+    /// following `select-window`.
+    pinned: bool,
+}
+
+/// Output waiting to be written to one client.
+///
+/// Chunks are coalesced into a single buffer so a busy PTY does not turn
+/// into one frame per read. A client falling more than `MAX_PENDING_OUTPUT`
+/// behind loses its backlog and is resynchronized from the replay buffer,
+/// rather than silently missing bytes in the middle of escape sequences.
+struct ClientOutput {
+    pending: std::sync::Mutex<PendingOutput>,
+    ready: Notify,
+    /// Fired when the server detaches the client on its own.
+    detach: Notify,
+}
+
+#[derive(Default)]
+struct PendingOutput {
+    bytes: Vec<u8>,
+    resync: bool,
+    /// Latest clipboard to forward, older ones are superseded.
+    clipboard: Option<Vec<u8>>,
+    windows: Vec<WindowEvent>,
+    /// The client is to be shown another window, see `select-window`.
+    switched: bool,
+}
+
+impl ClientOutput {
+    fn new() -> Self {
+        Self {
+            pending: std::sync::Mutex::new(PendingOutput::default()),
+            ready: Notify::new(),
+            detach: Notify::new(),
+        }
+    }
+
+    fn push(&self, data: &[u8]) {
+        let mut pending = self.pending.lock().unwrap();
+
+        if pending.resync {
+            // Already due for a full resync, more backlog is pointless.
+        } else if pending.bytes.len() + data.len() > MAX_PENDING_OUTPUT {
+            pending.bytes = Vec::new();
+            pending.resync = true;
+        } else {
+            pending.bytes.extend_from_slice(data);
+        }
+
+        drop(pending);
+        self.ready.notify_one();
+    }
+
+    fn push_clipboard(&self, data: Vec<u8>) {
+        self.pending.lock().unwrap().clipboard = Some(data);
+        self.ready.notify_one();
+    }
+
+    fn push_windows(&self, events: impl IntoIterator<Item = WindowEvent>) {
+        self.pending.lock().unwrap().windows.extend(events);
+        self.ready.notify_one();
+    }
+
+    fn push_switch(&self) {
+        self.pending.lock().unwrap().switched = true;
+        self.ready.notify_one();
+    }
+
+    fn take(&self) -> PendingOutput {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    /// Take what is due on the screen, keeping the clipboard and window
+    /// events for later.
+    fn take_screen(&self) -> PendingOutput {
+        let mut pending = self.pending.lock().unwrap();
+        PendingOutput {
+            bytes: std::mem::take(&mut pending.bytes),
+            resync: std::mem::take(&mut pending.resync),
+            switched: std::mem::take(&mut pending.switched),
+            ..PendingOutput::default()
+        }
+    }
+}
+
+/// Another name serving the session, added with `serve --group`.
+struct Link {
+    token: String,
+    /// Window its clients are shown, the whole desktop if `None`.
+    window: Option<u64>,
+    listener: JoinHandle<()>,
+}
+
+/// A connection accepted on one of the session's listeners, with its peer
+/// and the linked name it came in through.
+type Accepted = (Incoming, String, Option<String>);
+
+/// State shared by the accept loop and every client connection.
+struct Session {
+    /// Current name, which decides the socket and token paths.
+    name: std::sync::Mutex<String>,
+    shortcut_dir: PathBuf,
+    started: Instant,
+    child_pid: Pid,
+    hooks: Vec<Hook>,
+    /// Commands for the desktop running in the PTY.
+    desktop: mpsc::Sender<DesktopCommand>,
+    /// Number of attached clients, for the desktop to slow down without any.
+    attached: watch::Sender<usize>,
+    /// Set once the child was stopped on purpose by a `kill` request.
+    killed: AtomicBool,
+    /// Set when the server is going away, clients are told so rather than detached.
+    shutting_down: AtomicBool,
+    master_fd: i32,
+    master_write: Mutex<tokio::fs::File>,
+    replay: std::sync::Mutex<ReplayBuffer>,
+    token: String,
+    force_size: Option<TermSize>,
+    clients: std::sync::Mutex<HashMap<u64, ClientState>>,
+    next_client_id: AtomicU64,
+    /// Open desktop windows by id, as `WindowEvent::Opened` with the current title.
+    windows: std::sync::Mutex<BTreeMap<u64, WindowEvent>>,
+    /// Client variables to take over, as names or `PREFIX*` patterns.
+    update_environment: Vec<String>,
+    /// Variables taken over so far, mirrored to `env_file` for the child.
+    environment: std::sync::Mutex<BTreeMap<String, Option<String>>>,
+    env_file: PathBuf,
+    activity: Option<ActivityWriter>,
+    idle_timeout: Option<Duration>,
+    idle_action: IdleAction,
+    /// Window shown to clients of the session's own name, the whole desktop if `None`.
+    current_window: std::sync::Mutex<Option<u64>>,
+    links: std::sync::Mutex<BTreeMap<String, Link>>,
+    /// Feeds connections from listeners of linked names to the accept loop.
+    incoming: mpsc::Sender<Accepted>,
+}
+
+impl Session {
+    /// Set the PTY window size and notify the child of the resize.
+    fn resize_pty(&self, size: TermSize) {
+        let winsize = Winsize {
+            ws_col: size.cols,
+            ws_row: size.rows,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        unsafe {
+            libc::ioctl(self.master_fd, libc::TIOCSWINSZ, &winsize as *const Winsize);
+        }
+        let _ = kill(self.child_pid, Signal::SIGWINCH);
+    }
+
+    /// Return the current PTY window size.
+    fn pty_size(&self) -> TermSize {
+        let mut winsize = Winsize {
+            ws_col: DEFAULT_COLS,
+            ws_row: DEFAULT_ROWS,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        unsafe {
+            libc::ioctl(self.master_fd, libc::TIOCGWINSZ, &mut winsize as *mut Winsize);
+        }
+        TermSize { cols: winsize.ws_col, rows: winsize.ws_row }
+    }
+
+    /// Record PTY output for replay and queue it for every client.
+    ///
+    /// Both happen under the replay lock so an attaching client sees every
+    /// byte exactly once: either in its replay or in its queue.
+    fn publish(&self, data: &[u8]) {
+        self.record(|| Activity::Output { data: String::from_utf8_lossy(data).into_owned() });
+        let mut replay = self.replay.lock().unwrap();
+        replay.push(data);
+
+        for client in self.clients.lock().unwrap().values().filter(|client| client.window.is_none()) {
+            client.output.push(data);
+        }
+    }
+
+    /// Queue output of a desktop window for the clients attached to it alone.
+    fn publish_window(&self, id: u64, data: &[u8]) {
+        for client in self.clients.lock().unwrap().values().filter(|client| client.window == Some(id)) {
+            client.output.push(data);
+        }
+    }
+
+    /// Find an open window by id, or else by title.
+    fn find_window(&self, window: &str) -> Option<u64> {
+        let windows = self.windows.lock().unwrap();
+        if let Ok(id) = window.parse()
+            && windows.contains_key(&id)
+        {
+            return Some(id);
+        }
+
+        windows.values().find_map(|event| match event {
+            WindowEvent::Opened { id, title, .. } if title == window => Some(*id),
+            _ => None,
+        })
+    }
+
+    /// Register a client, returning its id, output queue and the replay to send first.
+    ///
+    /// Clients attached to a single window get no replay, the window's
+    /// program is asked to redraw instead.
+    ///
+    /// Unless attached to a window of its own choice, a client is shown the
+    /// current window of the session name it attached through.
+    fn add_client(
+        &self,
+        handshake: &Handshake,
+        peer: String,
+        identity: Option<ClientIdentity>,
+        link: Option<String>,
+        pinned_window: Option<u64>,
+    ) -> (u64, Arc<ClientOutput>, Vec<u8>) {
+        let id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+        let output = Arc::new(ClientOutput::new());
+        let window = pinned_window.or_else(|| self.member_window(&link));
+
+        let replay = self.replay.lock().unwrap();
+        let client = ClientState {
+            size: None,
+            clipboard: handshake.has(protocol::FEATURE_CLIPBOARD),
+            windows: false,
+            output: Arc::clone(&output),
+            peer,
+            identity,
+            read_only: handshake.read_only,
+            attached: Instant::now(),
+            link,
+            window,
+            pinned: pinned_window.is_some(),
+        };
+        let count = {
+            let mut clients = self.clients.lock().unwrap();
+            clients.insert(id, client);
+            clients.len()
+        };
+        self.attached.send_replace(count);
+
+        match window {
+            Some(window) => {
+                self.negotiate_window_size(window);
+                (id, output, Vec::new())
+            }
+            None => (id, output, replay.snapshot()),
+        }
+    }
+
+    /// Replace a client's backlog with the replay buffer, for a client that fell behind.
+    fn resync(&self, output: &ClientOutput, window: Option<u64>) -> Vec<u8> {
+        let replay = self.replay.lock().unwrap();
+        output.take();
+        match window {
+            Some(_) => Vec::new(),
+            None => replay.snapshot(),
+        }
+    }
+
+    /// Window shown to clients of a client's session name, the whole desktop if `None`.
+    fn client_window(&self, id: u64) -> Option<u64> {
+        self.clients.lock().unwrap().get(&id).and_then(|client| client.window)
+    }
+
+    /// Token of the session name a client attached through.
+    fn client_token(&self, id: u64) -> Option<String> {
+        let link = self.clients.lock().unwrap().get(&id)?.link.clone();
+        self.member_token(&link)
+    }
+
+    /// Token for the session's own name, or for a linked one.
+    fn member_token(&self, link: &Option<String>) -> Option<String> {
+        match link {
+            None => Some(self.token.clone()),
+            Some(name) => self.links.lock().unwrap().get(name).map(|link| link.token.clone()),
+        }
+    }
+
+    /// Window shown to clients of the session's own name, or of a linked one.
+    fn member_window(&self, link: &Option<String>) -> Option<u64> {
+        match link {
+            None => *self.current_window.lock().unwrap(),
+            Some(name) => self.links.lock().unwrap().get(name).and_then(|link| link.window),
+        }
+    }
+
+    /// Show the clients of a session name another window, or the whole desktop.
+    fn select_window(&self, link: &Option<String>, window: Option<u64>) {
+        match link {
+            None => *self.current_window.lock().unwrap() = window,
+            Some(name) => {
+                if let Some(link) = self.links.lock().unwrap().get_mut(name) {
+                    link.window = window;
+                }
+            }
+        }
+
+        let mut previous = Vec::new();
+        for client in self.clients.lock().unwrap().values_mut() {
+            if &client.link == link && !client.pinned && client.window != window {
+                previous.extend(client.window);
+                client.window = window;
+                client.output.push_switch();
+            }
+        }
+
+        // Sizes change for whatever the clients stopped and started showing.
+        self.negotiate_size();
+        previous.sort();
+        previous.dedup();
+        for id in previous.into_iter().chain(window) {
+            self.negotiate_window_size(id);
+        }
+    }
+
+    /// Describe the session for `list`, under the name the client asked for.
+    fn info(&self, link: &Option<String>) -> SessionInfo {
+        let size = self.pty_size();
+        SessionInfo {
+            name: link.clone().unwrap_or_else(|| self.name.lock().unwrap().clone()),
+            child_pid: self.child_pid.as_raw(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            clients: self.clients.lock().unwrap().values().filter(|client| &client.link == link).count() as u32,
+            cols: size.cols,
+            rows: size.rows,
+            shortcut_dir: self.shortcut_dir.display().to_string(),
+        }
+    }
+
+    /// Add to the activity log, if the session keeps one.
+    fn record(&self, activity: impl FnOnce() -> Activity) {
+        if let Some(log) = &self.activity {
+            log.record(activity());
+        }
+    }
+
+    /// Run the hooks for `event`, describing the session on top of `env`.
+    fn run_hooks(&self, event: HookEvent, mut env: Vec<(&str, String)>) {
+        env.push((config::SESSION_ENV, self.name.lock().unwrap().clone()));
+        env.push(("DESKTOP_TUI_CLIENTS", self.clients.lock().unwrap().len().to_string()));
+        hooks::run(&self.hooks, event, &env);
+    }
+
+    /// Describe the clients attached through a session name, oldest first.
+    fn attached_clients(&self, link: &Option<String>) -> Vec<AttachedClient> {
+        let mut clients: Vec<AttachedClient> = self
+            .clients
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, client)| &client.link == link)
+            .map(|(id, client)| AttachedClient {
+                id: *id,
+                peer: client.peer.clone(),
+                identity: client.identity.clone(),
+                read_only: client.read_only,
+                attached_secs: client.attached.elapsed().as_secs(),
+            })
+            .collect();
+        clients.sort_by_key(|client| client.id);
+        clients
+    }
+
+    /// Hand copied text to every clipboard-aware client except the one it came
+    /// from, and to the desktop when a client pasted it.
+    fn share_clipboard(&self, data: Vec<u8>, from_client: Option<u64>) {
+        for (id, client) in self.clients.lock().unwrap().iter() {
+            if client.clipboard && Some(*id) != from_client {
+                client.output.push_clipboard(data.clone());
+            }
+        }
+
+        if from_client.is_some() {
+            let _ = self.desktop.try_send(DesktopCommand::SetClipboard { data });
+        }
+    }
+
+    /// Keep track of a window change and tell the clients watching windows.
+    ///
+    /// Done under the windows lock so a client starting to watch sees every
+    /// change exactly once, like `publish` does for output.
+    fn window_changed(&self, event: WindowEvent) {
+        let mut windows = self.windows.lock().unwrap();
+        match &event {
+            WindowEvent::Opened { id, .. } => {
+                windows.insert(*id, event.clone());
+            }
+            WindowEvent::TitleChanged { id, title } => {
+                if let Some(WindowEvent::Opened { title: current, .. }) = windows.get_mut(id) {
+                    *current = title.clone();
+                }
+            }
+            WindowEvent::Closed { id } => {
+                windows.remove(id);
+
+                // Session names showing it go back to the whole desktop,
+                // clients that chose it themselves have nothing left to see.
+                let mut current_window = self.current_window.lock().unwrap();
+                if *current_window == Some(*id) {
+                    *current_window = None;
+                }
+                for link in self.links.lock().unwrap().values_mut().filter(|link| link.window == Some(*id)) {
+                    link.window = None;
+                }
+
+                for client in self.clients.lock().unwrap().values_mut().filter(|client| client.window == Some(*id)) {
+                    if client.pinned {
+                        client.output.detach.notify_one();
+                    } else {
+                        client.window = None;
+                        client.output.push_switch();
+                    }
+                }
+            }
+        }
+
+        for client in self.clients.lock().unwrap().values().filter(|client| client.windows) {
+            client.output.push_windows([event.clone()]);
+        }
+        drop(windows);
+
+        if matches!(event, WindowEvent::Closed { .. }) {
+            self.negotiate_size();
+        }
+    }
+
+    /// Start telling a client about windows, beginning with those already open.
+    fn watch_windows(&self, id: u64) {
+        let windows = self.windows.lock().unwrap();
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&id) {
+            client.windows = true;
+            client.output.push_windows(windows.values().cloned());
+        }
+    }
+
+    /// Take over the variables a client reported, for programs started from
+    /// now on: the desktop applies them to new windows, shells can source
+    /// `env_file`.
+    fn update_environment(&self, vars: Vec<(String, String)>) {
+        // Only what was asked for, and nothing a shell would choke on.
+        let mut vars: Vec<(String, Option<String>)> = vars
+            .into_iter()
+            .filter(|(name, value)| {
+                environment::is_valid_name(name)
+                    && !value.contains('\0')
+                    && self.update_environment.iter().any(|pattern| environment::matches(pattern, name))
+            })
+            .map(|(name, value)| (name, Some(value)))
+            .collect();
+
+        // Like tmux, variables asked for by name that the client lacks are
+        // removed, e.g. the SSH_AUTH_SOCK of an earlier client.
+        for name in self.update_environment.iter().filter(|pattern| !pattern.ends_with('*')) {
+            if !vars.iter().any(|(var, _)| var == name) {
+                vars.push((name.clone(), None));
+            }
+        }
+
+        self.set_environment(vars);
+    }
+
+    /// Give programs started from now on these variables, `None` removing one.
+    fn set_environment(&self, mut vars: Vec<(String, Option<String>)>) {
+        let mut environment = self.environment.lock().unwrap();
+        vars.retain(|(name, value)| environment.get(name) != Some(value));
+        if vars.is_empty() {
+            return;
+        }
+        environment.extend(vars.iter().cloned());
+        if let Err(e) = environment::save(&self.env_file, &environment) {
+            warn!("Failed to write {:?}: {:#}", self.env_file, e);
+        }
+
+        let _ = self.desktop.try_send(DesktopCommand::UpdateEnvironment { vars });
+    }
+
+    /// Ask a client for the variables to take over, unless it only watches
+    /// or is too old to answer.
+    async fn request_environment(&self, writer: &mut (impl AsyncWriteExt + Unpin), handshake: &Handshake) -> anyhow::Result<()> {
+        if handshake.negotiated.version < 6 || handshake.read_only {
+            return Ok(());
+        }
+
+        send(writer, &Message::RequestEnvironment(self.update_environment.clone()), handshake.framing()).await
+    }
+
+    /// Ask the clients attached through a session name to detach.
+    fn detach_clients(&self, link: &Option<String>) -> usize {
+        let clients = self.clients.lock().unwrap();
+        let mut detached = 0;
+        for client in clients.values().filter(|client| &client.link == link) {
+            client.output.detach.notify_one();
+            detached += 1;
+        }
+        detached
+    }
+
+    /// Ask every attached client to detach.
+    fn detach_all(&self) -> usize {
+        let clients = self.clients.lock().unwrap();
+        for client in clients.values() {
+            client.output.detach.notify_one();
+        }
+        clients.len()
+    }
+
+    /// Tell every attached client the session is ending, then give their
+    /// connections a moment to deliver it.
+    async fn shutdown_clients(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+        if self.detach_all() == 0 {
+            return;
+        }
+
+        let deadline = Instant::now() + SHUTDOWN_GRACE;
+        while !self.clients.lock().unwrap().is_empty() && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    fn remove_client(&self, id: u64) {
+        let (removed, count) = {
+            let mut clients = self.clients.lock().unwrap();
+            (clients.remove(&id), clients.len())
+        };
+        self.attached.send_replace(count);
+        match removed.and_then(|client| client.window) {
+            Some(window) => self.negotiate_window_size(window),
+            None => self.negotiate_size(),
+        }
+    }
+
+    fn set_client_size(&self, id: u64, size: TermSize) {
+        let window = match self.clients.lock().unwrap().get_mut(&id) {
+            Some(client) => {
+                client.size = Some(size);
+                client.window
+            }
+            None => return,
+        };
+
+        match window {
+            Some(window) => self.negotiate_window_size(window),
+            None => self.negotiate_size(),
+        }
+    }
+
+    /// Resize the PTY so that every attached client can display it whole:
+    /// the smallest width and height among clients, unless a size is forced.
+    fn negotiate_size(&self) {
+        let target = self.force_size.or_else(|| {
+            let clients = self.clients.lock().unwrap();
+            let sizes = clients.values().filter(|client| client.window.is_none()).filter_map(|client| client.size);
+            sizes.reduce(|a, b| TermSize {
+                cols: a.cols.min(b.cols),
+                rows: a.rows.min(b.rows),
+            })
+        });
+
+        // With no sized client left, keep the last size.
+        if let Some(size) = target
+            && size != self.pty_size()
+        {
+            self.resize_pty(size);
+            self.record(|| Activity::Resized { cols: size.cols, rows: size.rows });
+        }
+    }
+
+    /// Have the desktop mirror a window's output while clients are attached
+    /// to it alone, sizing its PTY for them like `negotiate_size` does for
+    /// the desktop.
+    fn negotiate_window_size(&self, id: u64) {
+        let (attached, target) = {
+            let clients = self.clients.lock().unwrap();
+            let mut clients = clients.values().filter(|client| client.window == Some(id)).peekable();
+            let attached = clients.peek().is_some();
+            let target = clients.filter_map(|client| client.size).reduce(|a, b| TermSize {
+                cols: a.cols.min(b.cols),
+                rows: a.rows.min(b.rows),
+            });
+            (attached, target)
+        };
+
+        // Until a client reports its size the window keeps its own.
+        let command = match attached {
+            true => DesktopCommand::MirrorWindow { id, size: target.map(|size| (size.cols, size.rows)) },
+            false => DesktopCommand::UnmirrorWindow { id },
+        };
+        let _ = self.desktop.try_send(command);
+    }
+
+    /// Send buffered output to a client, then force the child to redraw.
+    ///
+    /// A client attached to a single window gets a cleared screen and a
+    /// redraw of that window instead.
+    async fn send_replay(
+        &self,
+        writer: &mut (impl AsyncWriteExt + Unpin),
+        history: Vec<u8>,
+        compress: bool,
+        framing: Framing,
+        window: Option<u64>,
+    ) -> anyhow::Result<()> {
+        if window.is_some() {
+            send(writer, &Message::Data(b"\x1b[2J\x1b[H".to_vec()), framing).await?;
+        } else if !history.is_empty() {
+            send(writer, &protocol::data_message(history, compress), framing).await?;
+        }
+
+        self.redraw(window).await;
+        Ok(())
+    }
+
+    /// Make the desktop, or one of its windows, draw itself again.
+    async fn redraw(&self, window: Option<u64>) {
+        match window {
+            Some(id) => {
+                let _ = self.desktop.send(DesktopCommand::RedrawWindow { id }).await;
+            }
+            None => self.force_redraw().await,
+        }
+    }
+
+    /// A SIGWINCH with an unchanged size is ignored by most programs, so the PTY
+    /// is shrunk by one row and restored, the same trick tmux uses.
+    async fn force_redraw(&self) {
+        let size = self.pty_size();
+        if size.rows > 1 {
+            self.resize_pty(TermSize { rows: size.rows - 1, ..size });
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        self.resize_pty(size);
+    }
+}
+
+#[tracing::instrument(name = "serve", skip_all, fields(session = %options.session))]
+pub async fn serve(options: ServeOptions) -> anyhow::Result<()> {
+    let ServeOptions {
+        shortcut_dir,
+        session,
+        listen,
+        tls_cert,
+        tls_key,
+        force_size,
+        resume,
+        ready,
+        hooks,
+        command,
+        env,
+        update_environment,
+        activity_log,
+        idle_timeout,
+        idle_action,
+        config_file,
+        overrides,
+        log_level,
+        log_file,
+    } = options;
+    check_session_name(&session)?;
     let sock_path = socket_path(&session)?;
+    let dir = session_dir()?;
+
+    // A resumed session starts from its saved layout and shortcut directory.
+    let saved_layout_path = layout::layout_path(&dir, &session);
+    let (shortcut_dir, restore) = if resume {
+        if !saved_layout_path.exists() {
+            bail!("No saved layout for session '{}', nothing to resume", session);
+        }
+        let saved = layout::load(&saved_layout_path)?;
+        info!("Resuming {} window(s) in {:?}.", saved.windows.len(), saved.shortcut_dir);
+        (saved.shortcut_dir, Some(saved_layout_path.clone()))
+    } else {
+        (fs::canonicalize(&shortcut_dir).unwrap_or(shortcut_dir), None)
+    };
 
-    // Remove stale socket if it exists.
+    // The desktop keeps its layout up to date here, it is only kept if the server is terminated.
+    let snapshot_path = layout::snapshot_path(&dir, std::process::id());
+
+    // Exists from the start so the child can always source it.
+    let env_file = environment::env_file_path(&dir, std::process::id());
+    environment::save(&env_file, &BTreeMap::new())?;
+    let update_environment = match update_environment.is_empty() {
+        true => environment::DEFAULT_UPDATE_ENVIRONMENT.iter().map(|name| name.to_string()).collect(),
+        false => update_environment,
+    };
+
+    // Never take the socket from a running server, only from a dead one.
+    if session_alive(&dir, &session) {
+        bail!("Session '{}' is already running", session);
+    }
     if sock_path.exists() {
-        fs::remove_file(&sock_path)?;
+        info!("Removing stale socket {:?}.", sock_path);
+        remove_stale_session(&dir, &session)?;
     }
 
+    // Clients must present this token before any PTY data is exchanged.
+    let token_path = auth::token_path(&dir, &session);
+    let token = auth::create_token(&token_path)?;
+
     // Open a PTY pair.
+    let initial_size = force_size.unwrap_or(TermSize { cols: DEFAULT_COLS, rows: DEFAULT_ROWS });
     let winsize = Winsize {
-        ws_col: DEFAULT_COLS,
-        ws_row: DEFAULT_ROWS,
+        ws_col: initial_size.cols,
+        ws_row: initial_size.rows,
         ws_xpixel: 0,
         ws_ypixel: 0,
     };
@@ -51,19 +953,76 @@ pub async fn serve(shortcut_dir: PathBuf, session: String) -> anyhow::Result<()>
     let master_fd = pty.master.into_raw_fd();
     let slave_fd = pty.slave.into_raw_fd();
 
-    // Build the child command. We re-exec the current binary with `run`.
-    let exe = std::env::current_exe().context("cannot determine current executable path")?;
-    let shortcut_dir_str = shortcut_dir
-        .to_str()
-        .ok_or_else(|| anyhow!("shortcut_dir is not valid UTF-8"))?
-        .to_owned();
+    // Spawn child with PTY slave as its stdio: pre_exec duplicates the slave
+    // FD onto the stdio descriptors inside the forked child, before exec
+    // replaces it with the desktop, or with `sh -c` running `--command`.
+    let mut cmd = match &command {
+        // The user's own command line, run like hooks are.
+        Some(command) => {
+            info!("Serving `{}` instead of the desktop.", command);
+            let mut cmd = std::process::Command::new("sh");
+            cmd.arg("-c").arg(command);
+            cmd
+        }
+        // Otherwise re-exec the current binary with `run`.
+        None => {
+            let exe = std::env::current_exe().context("cannot determine current executable path")?;
+            let shortcut_dir_str = shortcut_dir
+                .to_str()
+                .ok_or_else(|| anyhow!("shortcut_dir is not valid UTF-8"))?
+                .to_owned();
+
+            let mut cmd = std::process::Command::new(&exe);
+            cmd.arg("run").arg(&shortcut_dir_str);
+            cmd.arg("--layout-file").arg(&snapshot_path);
+            cmd.arg("--session").arg(&session);
+            if let Some(restore) = &restore {
+                cmd.arg("--restore").arg(restore);
+            }
+            if let Some(config_file) = &config_file {
+                cmd.arg("--config").arg(config_file);
+            }
+            // Only the flags, the desktop reads the rest from the file and
+            // the environment itself and must be able to reload it.
+            if let Some(theme) = overrides.theme {
+                cmd.arg("--theme").arg(theme.to_string());
+            }
+            if let Some(backend) = overrides.backend {
+                cmd.arg("--backend").arg(backend.to_string());
+            }
+            if let Some(color_mode) = overrides.color_mode {
+                cmd.arg("--color-mode").arg(color_mode.to_string());
+            }
+            if overrides.ascii_only {
+                cmd.arg("--ascii-only");
+            }
+            // The desktop cannot log to its stderr, the PTY, so it shares the session's log.
+            cmd.arg("--log-level").arg(log_level.to_string());
+            cmd.arg("--log-file").arg(log_file.clone().unwrap_or_else(|| daemon::log_path(&dir, &session)));
+            cmd
+        }
+    };
+
+    // Programs in the session, and desktop-tui commands run from them, go
+    // for this session. A rename updates it like a client's variables.
+    cmd.env(config::SESSION_ENV, &session);
+
+    // A server started outside a terminal, e.g. by a service manager, has no
+    // TERM to pass on, which leaves full-screen programs guessing.
+    if std::env::var_os("TERM").is_none() {
+        cmd.env("TERM", environment::DEFAULT_TERM);
+    }
+    cmd.env(environment::ENV_FILE_ENV, &env_file);
+    cmd.envs(env.iter().map(|var| (&var.name, &var.value)));
 
-    // Spawn child with PTY slave as its stdio.
-    // pre_exec is used (not exec() shell invocation) to avoid command injection:
-    // we duplicate the slave FD onto stdio descriptors inside the child process,
-    // then the OS exec replaces the process image with the exact binary path.
-    let mut cmd = std::process::Command::new(&exe);
-    cmd.arg("run").arg(&shortcut_dir_str);
+    // The desktop reports what the PTY output cannot tell, like bells, on
+    // this socket and receives commands like pasted clipboards on it. Other
+    // programs do not get it, their half of the pair is closed on exec.
+    let (event_socket, desktop_socket) = std::os::unix::net::UnixStream::pair()?;
+    let event_fd = command.is_none().then(|| desktop_socket.as_raw_fd());
+    if event_fd.is_some() {
+        cmd.env(events::EVENT_FD_ENV, events::EVENT_FD.to_string());
+    }
 
     // Safety: pre_exec runs in the forked child before exec.
     // We redirect stdin/stdout/stderr to the PTY slave and close the master.
@@ -79,6 +1038,15 @@ pub async fn serve(shortcut_dir: PathBuf, session: String) -> anyhow::Result<()>
                 libc::close(slave_fd);
             }
 
+            // Hand the event pipe over on a known descriptor, clearing close-on-exec.
+            if let Some(event_fd) = event_fd {
+                if event_fd == events::EVENT_FD {
+                    libc::fcntl(event_fd, libc::F_SETFD, 0);
+                } else {
+                    libc::dup2(event_fd, events::EVENT_FD);
+                }
+            }
+
             // Create a new session so the child owns the terminal.
             libc::setsid();
 
@@ -86,11 +1054,14 @@ pub async fn serve(shortcut_dir: PathBuf, session: String) -> anyhow::Result<()>
         });
     }
 
-    let child = cmd.spawn().context("failed to spawn desktop-tui run child")?;
+    let child = cmd.spawn().context("failed to spawn the session's child")?;
     let child_pid = Pid::from_raw(child.id() as i32);
 
     // Close slave FD in the parent now that the child has inherited it.
     unsafe { libc::close(slave_fd) };
+    drop(desktop_socket);
+    event_socket.set_nonblocking(true)?;
+    let (mut desktop_events, desktop_commands, attached) = events::spawn_channel(tokio::net::UnixStream::from_std(event_socket)?);
 
     // Wrap the master FD for async reading and writing.
     // Duplicate so we can have independent read and write handles.
@@ -99,150 +1070,904 @@ pub async fn serve(shortcut_dir: PathBuf, session: String) -> anyhow::Result<()>
     let master_file_write = unsafe { std::fs::File::from_raw_fd(master_fd_write) };
 
     let master_read = Arc::new(Mutex::new(tokio::fs::File::from_std(master_file_read)));
-    let master_write = Arc::new(Mutex::new(tokio::fs::File::from_std(master_file_write)));
 
-    // Broadcast channel: PTY output -> all connected clients.
-    let (pty_tx, _pty_rx) = broadcast::channel::<Vec<u8>>(256);
-    let pty_tx = Arc::new(pty_tx);
+    let activity = match activity_log {
+        true => Some(ActivityWriter::spawn(ActivityLog::open(activity::activity_path(&dir, &session))?)),
+        false => None,
+    };
+
+    // Listeners, including those of names linked later, funnel connections here.
+    let (incoming_tx, mut incoming_rx) = mpsc::channel::<Accepted>(16);
 
-    // Spawn task: continuously read from PTY master and broadcast.
+    let state = Arc::new(Session {
+        name: std::sync::Mutex::new(session.clone()),
+        shortcut_dir,
+        started: Instant::now(),
+        child_pid,
+        hooks,
+        desktop: desktop_commands,
+        attached,
+        killed: AtomicBool::new(false),
+        shutting_down: AtomicBool::new(false),
+        master_fd,
+        master_write: Mutex::new(tokio::fs::File::from_std(master_file_write)),
+        // Recent output replayed to clients attaching mid-session.
+        replay: std::sync::Mutex::new(ReplayBuffer::new(replay::DEFAULT_CAPACITY)),
+        token,
+        force_size,
+        clients: std::sync::Mutex::new(HashMap::new()),
+        next_client_id: AtomicU64::new(0),
+        windows: std::sync::Mutex::new(BTreeMap::new()),
+        update_environment,
+        environment: std::sync::Mutex::new(BTreeMap::new()),
+        env_file: env_file.clone(),
+        activity,
+        idle_timeout,
+        idle_action,
+        current_window: std::sync::Mutex::new(None),
+        links: std::sync::Mutex::new(BTreeMap::new()),
+        incoming: incoming_tx,
+    });
+
+    // Spawn task: continuously read from PTY master and queue output for clients.
     {
-        let pty_tx = Arc::clone(&pty_tx);
+        let state = Arc::clone(&state);
         let master_read = Arc::clone(&master_read);
         tokio::spawn(async move {
             let mut buf = vec![0u8; 4096];
-            loop {
-                let n = {
-                    let mut guard = master_read.lock().await;
-                    match guard.read(&mut buf).await {
-                        Ok(0) | Err(_) => break,
-                        Ok(n) => n,
+            let mut batch = Vec::new();
+            let mut last_publish: Option<Instant> = None;
+            let mut open = true;
+
+            while open {
+                let mut guard = master_read.lock().await;
+                match guard.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => batch.extend_from_slice(&buf[..n]),
+                }
+
+                // Sparse output goes out at once, a burst is gathered into
+                // fewer, larger frames. An unfinished read is kept by the
+                // file for the next call when the deadline cuts it short.
+                if last_publish.is_some_and(|at| at.elapsed() < COALESCE_WINDOW) {
+                    let deadline = tokio::time::Instant::now() + COALESCE_DELAY;
+                    while batch.len() < COALESCE_MAX_BYTES {
+                        match tokio::time::timeout_at(deadline, guard.read(&mut buf)).await {
+                            Ok(Ok(0)) | Ok(Err(_)) => {
+                                open = false;
+                                break;
+                            }
+                            Ok(Ok(n)) => batch.extend_from_slice(&buf[..n]),
+                            Err(_) => break,
+                        }
                     }
+                }
+                drop(guard);
+
+                state.publish(&batch);
+                batch.clear();
+                last_publish = Some(Instant::now());
+            }
+        });
+    }
+
+    // The session's Unix socket is always bound, extra listeners come from `--listen`.
+    let mut listeners = vec![Listener::bind_unix(&sock_path)?];
+    info!("Session '{}' listening on {:?}", session, sock_path);
+
+    for addr in &listen {
+        match addr {
+            ListenAddr::Unix(path) => {
+                // A stale socket is replaced, anything else at the path is the user's.
+                if let Ok(metadata) = fs::symlink_metadata(path) {
+                    if !metadata.file_type().is_socket() {
+                        bail!("{:?} exists and is not a socket, refusing to replace it", path);
+                    }
+                    fs::remove_file(path)?;
+                }
+                listeners.push(Listener::bind_unix(path)?);
+                info!("Session '{}' listening on {:?}", session, path);
+            }
+            ListenAddr::Tcp(tcp_addr) => {
+                let (Some(cert), Some(key)) = (&tls_cert, &tls_key) else {
+                    bail!("TCP listener {} requires --tls-cert and --tls-key", tcp_addr);
                 };
-                let data = buf[..n].to_vec();
-                // Ignore send errors (no receivers connected yet is fine).
-                let _ = pty_tx.send(data);
+                let acceptor = transport::tls_acceptor(cert, key)?;
+                listeners.push(Listener::bind_tcp(tcp_addr, acceptor).await?);
+                info!("Session '{}' listening on tcp://{} (TLS)", session, tcp_addr);
+                info!("Remote clients authenticate with the token in {:?}", token_path);
+            }
+        }
+    }
+
+    if let Some(ready) = ready {
+        ready.signal()?;
+    }
+
+    state.record(|| Activity::SessionStarted);
+    state.run_hooks(HookEvent::SessionCreated, vec![]);
+
+    // Forward desktop events to hooks and clients.
+    {
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            while let Some(event) = desktop_events.recv().await {
+                match event {
+                    DesktopEvent::Bell { window } => {
+                        info!("Bell in background window '{}'.", window);
+                        state.run_hooks(HookEvent::BellInBackgroundWindow, vec![("DESKTOP_TUI_WINDOW", window)]);
+                    }
+                    DesktopEvent::Clipboard { data } => state.share_clipboard(data, None),
+                    DesktopEvent::Window(event) => state.window_changed(event),
+                    DesktopEvent::WindowOutput { id, data } => state.publish_window(id, &data),
+                }
             }
         });
     }
 
-    // Unix socket listener.
-    let listener = UnixListener::bind(&sock_path).context("failed to bind Unix socket")?;
-    eprintln!("[serve] Session '{}' listening on {:?}", session, sock_path);
+    // Each listener accepts on its own task.
+    for listener in listeners {
+        spawn_listener(listener, state.incoming.clone(), None);
+    }
+
+    // Set when the session ends because the host (or a user) terminated it,
+    // rather than because the desktop was closed or killed on request.
+    let mut terminated = false;
+    // Set when the desktop died on its own with a failure, its layout is kept
+    // like a terminated one's. A `--command` program failing is not a crash.
+    let mut crashed = false;
+    let serves_desktop = command.is_none();
+    let crash_log = crash::crash_log_path()?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sighup = signal(SignalKind::hangup())?;
 
     // Accept clients in a loop.
     loop {
         // Check if child has exited.
         match waitpid(child_pid, Some(WaitPidFlag::WNOHANG)) {
-            Ok(WaitStatus::Exited(_, _)) | Ok(WaitStatus::Signaled(_, _, _)) => {
-                eprintln!("[serve] Child process exited, shutting down.");
+            Ok(WaitStatus::Exited(_, code)) if code != 0 && serves_desktop && !state.killed.load(Ordering::Relaxed) => {
+                error!("Child process failed with exit status {}, shutting down. Panics are recorded in {:?}.", code, crash_log);
+                state.record(|| Activity::ChildExited { status: code.to_string() });
+                state.run_hooks(HookEvent::ChildExited, vec![("DESKTOP_TUI_EXIT_STATUS", code.to_string())]);
+                crashed = true;
+                break;
+            }
+            Ok(WaitStatus::Exited(_, code)) => {
+                info!("Child process exited, shutting down.");
+                state.record(|| Activity::ChildExited { status: code.to_string() });
+                state.run_hooks(HookEvent::ChildExited, vec![("DESKTOP_TUI_EXIT_STATUS", code.to_string())]);
+                break;
+            }
+            Ok(WaitStatus::Signaled(_, signal, _)) if CRASH_SIGNALS.contains(&signal) => {
+                error!("Child process crashed with {}, shutting down. Panics are recorded in {:?}.", signal, crash_log);
+                state.record(|| Activity::ChildExited { status: signal.to_string() });
+                state.run_hooks(HookEvent::ChildExited, vec![("DESKTOP_TUI_EXIT_STATUS", signal.to_string())]);
+                crashed = true;
+                break;
+            }
+            Ok(WaitStatus::Signaled(_, signal, _)) => {
+                warn!("Child process killed by {}, shutting down.", signal);
+                state.record(|| Activity::ChildExited { status: signal.to_string() });
+                state.run_hooks(HookEvent::ChildExited, vec![("DESKTOP_TUI_EXIT_STATUS", signal.to_string())]);
+                // A shutting down host may signal the child before the server.
+                terminated |= !state.killed.load(Ordering::Relaxed);
                 break;
             }
             _ => {}
         }
 
-        // Accept a new connection with a short timeout so we can re-check child status.
-        let stream = tokio::select! {
-            accepted = listener.accept() => {
+        // Wait for a new connection with a short timeout so we can re-check child status.
+        let (incoming, peer, link) = tokio::select! {
+            accepted = incoming_rx.recv() => {
                 match accepted {
-                    Ok((stream, _)) => stream,
-                    Err(e) => {
-                        eprintln!("[serve] Accept error: {}", e);
-                        continue;
-                    }
+                    Some(accepted) => accepted,
+                    None => break,
                 }
             }
-            _ = tokio::time::sleep(std::time::Duration::from_millis(500)) => {
+            _ = tokio::time::sleep(Duration::from_millis(500)) => {
+                continue;
+            }
+            // Stop the child, the exit is noticed at the top of the loop.
+            name = async {
+                tokio::select! {
+                    _ = sigterm.recv() => "SIGTERM",
+                    _ = sigint.recv() => "SIGINT",
+                    _ = sighup.recv() => "SIGHUP",
+                }
+            } => {
+                info!("Received {}, stopping the session.", name);
+                terminated = true;
+                kill_child(&state);
                 continue;
             }
         };
 
-        eprintln!("[serve] Client connected.");
-        let pty_rx = pty_tx.subscribe();
-        let master_write = Arc::clone(&master_write);
+        info!("Client connected ({}).", peer);
+        let state = Arc::clone(&state);
+        let span = tracing::info_span!("client", %peer);
 
-        tokio::spawn(handle_client(stream, pty_rx, master_write, child_pid, master_fd));
+        tokio::spawn(async move {
+            let mut stream = match incoming.establish().await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Connection from {} failed: {:#}", peer, e);
+                    return;
+                }
+            };
+
+            // A name unlinked in the meantime has no token left.
+            let Some(token) = state.member_token(&link) else { return };
+            let handshake = match tokio::time::timeout(AUTH_TIMEOUT, handshake(&mut stream, &token)).await {
+                Ok(Ok(handshake)) => handshake,
+                Ok(Err(e)) => {
+                    warn!("Handshake with {} failed: {:#}", peer, e);
+                    return;
+                }
+                Err(_) => {
+                    warn!("Handshake with {} timed out.", peer);
+                    return;
+                }
+            };
+
+            // Version 1 clients always attach, newer ones say what they want.
+            let (identity, detach_others, window) = if handshake.negotiated.version >= 2 {
+                match tokio::time::timeout(AUTH_TIMEOUT, protocol::decode(&mut stream)).await {
+                    Ok(Ok(Message::Attach)) => (None, false, None),
+                    Ok(Ok(Message::AttachAs { identity, detach_others })) => (Some(identity), detach_others, None),
+                    Ok(Ok(Message::AttachWindow { identity, window })) => match state.find_window(&window) {
+                        Some(id) => (Some(identity), false, Some(id)),
+                        None => {
+                            let reason = format!("no window '{}' in this session", window);
+                            let _ = send(&mut stream, &Message::Rejected { reason }, handshake.framing()).await;
+                            return;
+                        }
+                    },
+                    Ok(Ok(Message::Control(request))) => {
+                        handle_control(&state, &mut stream, &handshake, request, &peer, &link).await;
+                        return;
+                    }
+                    Ok(Ok(Message::Query)) => {
+                        if send(&mut stream, &Message::Info(state.info(&link)), handshake.framing()).await.is_ok() && handshake.negotiated.version >= 4 {
+                            let _ = send(&mut stream, &Message::Clients(state.attached_clients(&link)), handshake.framing()).await;
+                        }
+                        return;
+                    }
+                    Ok(Ok(msg)) => {
+                        warn!("Client {} sent {:?} instead of attaching.", peer, msg);
+                        return;
+                    }
+                    Ok(Err(_)) | Err(_) => {
+                        debug!("Client {} left before attaching.", peer);
+                        return;
+                    }
+                }
+            } else {
+                (None, false, None)
+            };
+
+            if let Some(identity) = &identity {
+                info!("Client {} is {}.", peer, identity);
+            }
+
+            if let Some(window) = window {
+                info!("Client {} attached to window {}.", peer, window);
+            }
+
+            if let Some(link) = &link {
+                info!("Client {} attached through linked session '{}'.", peer, link);
+            }
+
+            if handshake.read_only {
+                info!("Client {} attached read-only.", peer);
+            }
+
+            // Like `tmux attach -d`: take the session over from everyone else.
+            if detach_others {
+                if handshake.read_only {
+                    let reason = String::from("read-only clients cannot detach others");
+                    let _ = send(&mut stream, &Message::Rejected { reason }, handshake.framing()).await;
+                    return;
+                }
+                let detached = state.detach_clients(&link);
+                info!("Client {} detached {} other client(s).", peer, detached);
+            }
+
+            let mut client_env = vec![
+                ("DESKTOP_TUI_CLIENT", peer.clone()),
+                ("DESKTOP_TUI_READ_ONLY", u8::from(handshake.read_only).to_string()),
+            ];
+            if let Some(identity) = &identity {
+                client_env.push(("DESKTOP_TUI_CLIENT_HOST", identity.hostname.clone()));
+                client_env.push(("DESKTOP_TUI_CLIENT_PID", identity.pid.to_string()));
+                client_env.push(("DESKTOP_TUI_CLIENT_TTY", identity.tty.clone().unwrap_or_default()));
+            }
+
+            let client = match &identity {
+                Some(identity) => format!("{} ({})", peer, identity),
+                None => peer.clone(),
+            };
+
+            // Only register once authenticated so nothing leaks beforehand.
+            let (client_id, output, history) = state.add_client(&handshake, peer.clone(), identity, link, window);
+            let window = state.client_window(client_id);
+            state.record(|| Activity::ClientAttached { client: client.clone() });
+            state.run_hooks(HookEvent::ClientAttached, client_env.clone());
+
+            let compress = handshake.has(protocol::FEATURE_ZSTD);
+            if state.send_replay(&mut stream, history, compress, handshake.framing(), window).await.is_ok()
+                && state.request_environment(&mut stream, &handshake).await.is_ok()
+            {
+                handle_client(&state, client_id, &output, stream, &handshake).await;
+            } else {
+                debug!("Client {} disconnected while attaching.", peer);
+            }
+
+            state.remove_client(client_id);
+            state.record(|| Activity::ClientDetached { client });
+            state.run_hooks(HookEvent::ClientDetached, client_env);
+        }.instrument(span));
     }
 
-    // Clean up socket file.
-    let _ = fs::remove_file(&sock_path);
+    state.shutdown_clients().await;
+
+    // Linked names end with the session.
+    let links: Vec<String> = state.links.lock().unwrap().keys().cloned().collect();
+    for name in links {
+        let _ = unlink_session(&state, &name);
+    }
+
+    // Clean up socket and token files, under the name the session ended with.
+    let session = state.name.lock().unwrap().clone();
+    let _ = fs::remove_file(socket_path(&session)?);
+    let _ = fs::remove_file(auth::token_path(&dir, &session));
+    let _ = fs::remove_file(daemon::pid_path(&dir, &session));
+    let _ = fs::remove_file(&env_file);
+
+    // Keep the last layout of a terminated or crashed session for `serve --resume`,
+    // a session that ended normally has nothing to resume.
+    let saved_layout_path = layout::layout_path(&dir, &session);
+    let resumable = terminated || crashed;
+    if resumable && snapshot_path.exists() {
+        fs::rename(&snapshot_path, &saved_layout_path)?;
+        info!("Layout saved, restart with `serve --resume --session {}`.", session);
+    } else if !resumable {
+        let _ = fs::remove_file(&snapshot_path);
+        let _ = fs::remove_file(&saved_layout_path);
+    }
+    for addr in &listen {
+        if let ListenAddr::Unix(path) = addr
+            && fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket())
+        {
+            let _ = fs::remove_file(path);
+        }
+    }
     Ok(())
 }
 
+/// What a client negotiated while connecting.
+struct Handshake {
+    read_only: bool,
+    negotiated: protocol::Negotiated,
+}
+
+impl Handshake {
+    fn has(&self, feature: &str) -> bool {
+        self.negotiated.has(feature)
+    }
+
+    fn framing(&self) -> Framing {
+        self.negotiated.framing()
+    }
+}
+
+/// Refuse a client with a reason it can display, then fail.
+///
+/// The whole handshake stays in legacy framing, which clients of any version read.
+async fn reject(stream: &mut BoxedStream, reason: String) -> anyhow::Result<Handshake> {
+    let _ = stream.write_all(&protocol::encode(&Message::Rejected { reason: reason.clone() }, Framing::Legacy)?).await;
+    bail!("{}", reason)
+}
+
+/// Exchange `Hello` with the client, then verify its `Auth` token.
+async fn handshake(stream: &mut BoxedStream, token: &str) -> anyhow::Result<Handshake> {
+    // A peer whose greeting cannot even be decoded speaks something else entirely.
+    let (version, features) = match protocol::decode(stream).await {
+        Ok(Message::Hello { version, features }) => (version, features),
+        Ok(_) => return reject(stream, String::from("expected Hello, the client is too old for this server")).await,
+        Err(e) => return reject(stream, format!("unreadable greeting ({:#}), is the client up to date?", e)).await,
+    };
+
+    let version = match protocol::negotiate_version(version) {
+        Ok(version) => version,
+        Err(reason) => return reject(stream, reason).await,
+    };
+    let features = protocol::common_features(&features);
+    stream.write_all(&protocol::encode(&Message::Hello { version, features: features.clone() }, Framing::Legacy)?).await?;
+
+    let reason = match protocol::decode(stream).await? {
+        Message::Auth { read_only, .. } if read_only && !features.iter().any(|f| f == protocol::FEATURE_READ_ONLY) => {
+            "read-only attach was not negotiated"
+        }
+        Message::Auth { token: given, read_only } if auth::tokens_match(token, &given) => {
+            stream.write_all(&protocol::encode(&Message::Authenticated, Framing::Legacy)?).await?;
+            let negotiated = protocol::Negotiated { version, features };
+            return Ok(Handshake { read_only, negotiated });
+        }
+        Message::Auth { .. } => "invalid token",
+        _ => "expected authentication",
+    };
+
+    reject(stream, reason.to_owned()).await
+}
+
 async fn handle_client(
-    stream: UnixStream,
-    mut pty_rx: broadcast::Receiver<Vec<u8>>,
-    master_write: Arc<Mutex<tokio::fs::File>>,
-    child_pid: Pid,
-    master_fd: i32,
+    state: &Session,
+    client_id: u64,
+    output: &ClientOutput,
+    stream: BoxedStream,
+    handshake: &Handshake,
 ) {
-    let (mut reader, mut writer) = stream.into_split();
+    let framing = handshake.framing();
+    let mut window = state.client_window(client_id);
+    let (reader, mut writer) = tokio::io::split(stream);
+    let (mut client_rx, mut reader_task) = protocol::spawn_reader(reader, framing);
 
-    loop {
+    let read_only = handshake.read_only;
+    let heartbeat = handshake.has(protocol::FEATURE_HEARTBEAT);
+    let compress = handshake.has(protocol::FEATURE_ZSTD);
+    // Output volume before and after compression, reported on disconnect.
+    let (mut raw_bytes, mut sent_bytes) = (0usize, 0usize);
+    let mut ping_timer = tokio::time::interval(protocol::HEARTBEAT_INTERVAL);
+    let mut last_seen = Instant::now();
+
+    // Read-only clients never send input, `--idle-timeout` leaves them alone.
+    let idle_timeout = if read_only { None } else { state.idle_timeout };
+    let mut idle_timer = tokio::time::interval(IDLE_CHECK_INTERVAL);
+    let mut last_input = Instant::now();
+    let mut idle_warned = false;
+    // Set while locked for being idle, holding what was typed towards the token.
+    let mut unlock_input: Option<Vec<u8>> = None;
+
+    'client: loop {
         tokio::select! {
             // Data from PTY -> send to client.
-            result = pty_rx.recv() => {
-                match result {
-                    Ok(data) => {
-                        let msg = Message::Data(data);
-                        match protocol::encode(&msg) {
-                            Ok(encoded) => {
-                                if writer.write_all(&encoded).await.is_err() {
-                                    break;
-                                }
-                            }
-                            Err(_) => break,
-                        }
+            _ = output.ready.notified() => {
+                // A locked client sees nothing until unlocked, then gets a
+                // full replay and the clipboard and window events kept for it.
+                if unlock_input.is_some() {
+                    if output.take_screen().switched {
+                        window = state.client_window(client_id);
                     }
-                    Err(broadcast::error::RecvError::Lagged(_)) => {
-                        // Skip lagged messages and continue.
-                        continue;
+                    continue;
+                }
+
+                let pending = output.take();
+
+                if let Some(data) = pending.clipboard
+                    && send(&mut writer, &Message::Clipboard(data), framing).await.is_err()
+                {
+                    break;
+                }
+
+                for event in pending.windows {
+                    if send(&mut writer, &Message::Window(event), framing).await.is_err() {
+                        break 'client;
+                    }
+                }
+
+                if pending.switched {
+                    window = state.client_window(client_id);
+                } else if pending.resync {
+                    warn!("Client fell behind, resynchronizing.");
+                }
+
+                if pending.switched || pending.resync {
+                    let history = state.resync(output, window);
+                    if state.send_replay(&mut writer, history, compress, framing, window).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+
+                if pending.bytes.is_empty() {
+                    continue;
+                }
+
+                raw_bytes += pending.bytes.len();
+                let msg = protocol::data_message(pending.bytes, compress);
+                match protocol::encode(&msg, framing) {
+                    Ok(encoded) => {
+                        sent_bytes += encoded.len();
+                        if writer.write_all(&encoded).await.is_err() {
+                            break;
+                        }
                     }
                     Err(_) => break,
                 }
             }
 
+            // Detached by a management request, or the server is going away.
+            _ = output.detach.notified() => {
+                if state.shutting_down.load(Ordering::Relaxed) {
+                    let _ = send(&mut writer, &Message::Shutdown, framing).await;
+                    debug!("Client told about the shutdown.");
+                } else {
+                    let _ = send(&mut writer, &Message::Detach, framing).await;
+                    info!("Client detached by request.");
+                }
+                break;
+            }
+
             // Message from client.
-            result = protocol::decode(&mut reader) => {
-                match result {
-                    Ok(Message::Data(bytes)) => {
-                        let mut guard = master_write.lock().await;
+            result = client_rx.recv() => {
+                let Some(msg) = result else {
+                    if let Ok(Some(e)) = (&mut reader_task).await
+                        && !matches!(e, ProtocolError::Closed)
+                    {
+                        warn!("Dropping client: {}", e);
+                    }
+                    break;
+                };
+                last_seen = Instant::now();
+
+                // A locked client may only type the token, resize or leave.
+                let msg = match (&mut unlock_input, msg) {
+                    (Some(typed), Message::Data(keys)) => {
+                        let Some(given) = type_unlock_input(typed, &keys) else { continue };
+
+                        if state.client_token(client_id).is_some_and(|token| auth::tokens_match(&token, &given)) {
+                            info!("Client unlocked.");
+                            unlock_input = None;
+                            last_input = Instant::now();
+                            idle_warned = false;
+                            let history = state.resync(output, window);
+                            if state.send_replay(&mut writer, history, compress, framing, window).await.is_err() {
+                                break;
+                            }
+                            output.ready.notify_one();
+                        } else {
+                            warn!("Client failed to unlock.");
+                            let notice = banner("Wrong token. Type the session token and press Enter to unlock.", false);
+                            if send(&mut writer, &notice, framing).await.is_err() {
+                                break;
+                            }
+                        }
+                        continue;
+                    }
+                    (Some(_), msg @ (Message::Resize { .. } | Message::Detach | Message::Ping)) => msg,
+                    (Some(_), _) => continue,
+                    (None, msg) => msg,
+                };
+
+                match msg {
+                    // Read-only clients only watch: their input and shutdown
+                    // requests never reach the session. Their size still
+                    // counts so the PTY fits on their screen too.
+                    Message::Data(_)
+                    | Message::Shutdown
+                    | Message::Clipboard(_)
+                    | Message::OpenWindow { .. }
+                    | Message::Environment(_) if read_only => {}
+                    Message::Data(bytes) => {
+                        last_input = Instant::now();
+                        if idle_warned {
+                            // Typing resets the idle timeout, take the warning off the screen.
+                            idle_warned = false;
+                            state.redraw(window).await;
+                        }
+
+                        if let Some(id) = window {
+                            if state.desktop.send(DesktopCommand::WindowInput { id, data: bytes }).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+
+                        let mut guard = state.master_write.lock().await;
                         if guard.write_all(&bytes).await.is_err() {
                             break;
                         }
                     }
-                    Ok(Message::Resize { cols, rows }) => {
-                        let winsize = Winsize {
-                            ws_col: cols,
-                            ws_row: rows,
-                            ws_xpixel: 0,
-                            ws_ypixel: 0,
-                        };
-                        // Set PTY window size.
-                        unsafe {
-                            libc::ioctl(
-                                master_fd,
-                                libc::TIOCSWINSZ,
-                                &winsize as *const Winsize,
+                    Message::Clipboard(data) => state.share_clipboard(data, Some(client_id)),
+                    Message::WatchWindows => state.watch_windows(client_id),
+                    Message::Environment(vars) => state.update_environment(vars),
+                    Message::OpenWindow { shortcut } => {
+                        let _ = state.desktop.try_send(DesktopCommand::OpenWindow { shortcut });
+                    }
+                    Message::Resize { cols, rows } => {
+                        state.set_client_size(client_id, TermSize { cols, rows });
+                    }
+                    Message::Detach => {
+                        info!("Client detached.");
+                        break;
+                    }
+                    Message::Shutdown => {
+                        info!("Client requested shutdown.");
+                        let _ = kill(state.child_pid, Signal::SIGTERM);
+                        break;
+                    }
+                    Message::Ping => {
+                        let answered = send(&mut writer, &Message::Pong, framing).await;
+                        if answered.is_err() {
+                            break;
+                        }
+                    }
+                    // Handshake messages carry no meaning once authenticated.
+                    _ => {}
+                }
+            }
+
+            // Probe the client, dropping it once it stays silent too long.
+            _ = ping_timer.tick(), if heartbeat => {
+                if last_seen.elapsed() > protocol::HEARTBEAT_TIMEOUT {
+                    warn!("Client stopped responding, dropping it.");
+                    break;
+                }
+                if send(&mut writer, &Message::Ping, framing).await.is_err() {
+                    break;
+                }
+            }
+
+            // Warn, then detach or lock a client nobody typed into for `--idle-timeout`.
+            _ = idle_timer.tick(), if idle_timeout.is_some() => {
+                let (Some(timeout), None) = (idle_timeout, &unlock_input) else { continue };
+                let idle = last_input.elapsed();
+
+                if idle >= timeout {
+                    let minutes = timeout.as_secs() / 60;
+                    match state.idle_action {
+                        IdleAction::Detach => {
+                            info!("Client idle for {} minute(s), detaching it.", minutes);
+                            let notice = banner(&format!("Detached after {} minute(s) without input.", minutes), false);
+                            let _ = send(&mut writer, &notice, framing).await;
+                            let _ = send(&mut writer, &Message::Detach, framing).await;
+                            break;
+                        }
+                        IdleAction::Lock => {
+                            info!("Client idle for {} minute(s), locking it.", minutes);
+                            unlock_input = Some(Vec::new());
+                            let notice = banner(
+                                &format!(
+                                    "Locked after {} minute(s) without input. Type the session token and press Enter to unlock.",
+                                    minutes
+                                ),
+                                true,
                             );
+                            if send(&mut writer, &notice, framing).await.is_err() {
+                                break;
+                            }
                         }
-                        // Notify the child of the resize.
-                        let _ = kill(child_pid, Signal::SIGWINCH);
                     }
-                    Ok(Message::Detach) => {
-                        eprintln!("[serve] Client detached.");
+                } else if !idle_warned && idle + IDLE_WARNING.min(timeout / 2) >= timeout {
+                    idle_warned = true;
+                    let action = match state.idle_action {
+                        IdleAction::Detach => "detached",
+                        IdleAction::Lock => "locked",
+                    };
+                    let left = (timeout - idle).as_secs();
+                    let notice = banner(&format!("Idle: this client will be {} in {} seconds without input.", action, left), false);
+                    if send(&mut writer, &notice, framing).await.is_err() {
                         break;
                     }
-                    Ok(Message::Shutdown) => {
-                        eprintln!("[serve] Client requested shutdown.");
-                        let _ = kill(child_pid, Signal::SIGTERM);
+                }
+            }
+        }
+    }
+
+    reader_task.abort();
+
+    if compress && raw_bytes > 0 {
+        info!(
+            "Client disconnected ({} bytes of output sent as {} bytes).",
+            raw_bytes, sent_bytes
+        );
+    } else {
+        info!("Client disconnected.");
+    }
+}
+
+/// A notice over the client's top line, sent as ordinary output so every
+/// client version shows it. With `clear` the rest of the screen is wiped.
+fn banner(text: &str, clear: bool) -> Message {
+    let clear = if clear { "\x1b[2J" } else { "" };
+    Message::Data(format!("{}\x1b7\x1b[1;1H\x1b[2K\x1b[7m {} \x1b[0m\x1b8", clear, text).into_bytes())
+}
+
+/// Collect keys typed into a locked client, returning the line once Enter is pressed.
+fn type_unlock_input(typed: &mut Vec<u8>, keys: &[u8]) -> Option<String> {
+    for &key in keys {
+        match key {
+            b'\r' | b'\n' => return Some(String::from_utf8_lossy(&std::mem::take(typed)).into_owned()),
+            0x7f | 0x08 => {
+                typed.pop();
+            }
+            _ if typed.len() < MAX_UNLOCK_INPUT => typed.push(key),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Carry out a management request and report the outcome to the client.
+async fn handle_control(
+    state: &Arc<Session>,
+    stream: &mut BoxedStream,
+    handshake: &Handshake,
+    request: ControlRequest,
+    peer: &str,
+    link: &Option<String>,
+) {
+    // Keys may contain anything typed into the session, only log their size.
+    match &request {
+        ControlRequest::SendKeys { data } => debug!("Client {} sent {} bytes of input.", peer, data.len()),
+        request => info!("Client {} requested {:?}.", peer, request),
+    }
+
+    let outcome = if handshake.read_only {
+        Err(anyhow!("read-only clients cannot manage the session"))
+    } else {
+        match request {
+            // Killing a linked name only takes that name away.
+            ControlRequest::Kill => match link {
+                Some(name) => unlink_session(state, name),
+                None => {
+                    kill_child(state);
+                    Ok(())
+                }
+            },
+            ControlRequest::Rename { .. } if link.is_some() => {
+                Err(anyhow!("linked sessions cannot be renamed, link another name instead"))
+            }
+            ControlRequest::Rename { name } => rename_session(state, &name),
+            ControlRequest::DetachClients => {
+                let detached = state.detach_clients(link);
+                info!("Detaching {} client(s).", detached);
+                Ok(())
+            }
+            ControlRequest::Link { name } => link_session(state, &name),
+            ControlRequest::SelectWindow { window: None } => {
+                state.select_window(link, None);
+                Ok(())
+            }
+            ControlRequest::SelectWindow { window: Some(window) } => match state.find_window(&window) {
+                Some(id) => {
+                    state.select_window(link, Some(id));
+                    Ok(())
+                }
+                None => Err(anyhow!("no window '{}' in this session", window)),
+            },
+            ControlRequest::SendKeys { data } => {
+                let mut guard = state.master_write.lock().await;
+                guard.write_all(&data).await.context("failed to write to the session")
+            }
+        }
+    };
+
+    let reply = match outcome {
+        Ok(()) => Message::Done,
+        Err(e) => {
+            warn!("Request from {} failed: {:#}", peer, e);
+            Message::Rejected { reason: format!("{:#}", e) }
+        }
+    };
+    let _ = send(stream, &reply, handshake.framing()).await;
+}
+
+/// SIGTERM the child and SIGKILL it if it is still around after `KILL_GRACE`.
+///
+/// The accept loop notices the exit and shuts the session down.
+fn kill_child(state: &Arc<Session>) {
+    let child_pid = state.child_pid;
+    state.killed.store(true, Ordering::Relaxed);
+    let _ = kill(child_pid, Signal::SIGTERM);
+
+    tokio::spawn(async move {
+        tokio::time::sleep(KILL_GRACE).await;
+        // Signal 0 only checks whether the child is still there.
+        if kill(child_pid, None).is_ok() {
+            warn!("Child ignored SIGTERM, sending SIGKILL.");
+            let _ = kill(child_pid, Signal::SIGKILL);
+        }
+    });
+}
+
+/// Accept connections on a listener until it fails for good or is aborted,
+/// tagging them with the linked name they came in through.
+fn spawn_listener(listener: Listener, incoming: mpsc::Sender<Accepted>, link: Option<String>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    if incoming.send((stream, peer, link.clone())).await.is_err() {
                         break;
                     }
-                    Err(_) => break,
                 }
+                Err(e) => warn!("Accept error: {}", e),
             }
         }
+    })
+}
+
+/// Serve the session under another name too, with a socket and token of its own.
+fn link_session(state: &Arc<Session>, name: &str) -> anyhow::Result<()> {
+    check_session_name(name)?;
+
+    let dir = session_dir()?;
+    if session_alive(&dir, name) {
+        bail!("session '{}' already exists", name);
     }
+    remove_stale_session(&dir, name)?;
+
+    let sock_path = socket_path(name)?;
+    let listener = Listener::bind_unix(&sock_path)?;
+    let token = auth::create_token(&auth::token_path(&dir, name))?;
+
+    let listener = spawn_listener(listener, state.incoming.clone(), Some(name.to_owned()));
+    state.links.lock().unwrap().insert(name.to_owned(), Link { token, window: None, listener });
+    info!("Session '{}' linked, listening on {:?}", name, sock_path);
+    Ok(())
+}
+
+/// Stop serving a linked name, detaching its clients.
+fn unlink_session(state: &Session, name: &str) -> anyhow::Result<()> {
+    let link = state.links.lock().unwrap().remove(name).context("session is not linked")?;
+    link.listener.abort();
+    state.detach_clients(&Some(name.to_owned()));
+
+    let dir = session_dir()?;
+    let _ = fs::remove_file(socket_path(name)?);
+    let _ = fs::remove_file(auth::token_path(&dir, name));
+    info!("Linked session '{}' removed.", name);
+    Ok(())
+}
 
-    eprintln!("[serve] Client disconnected.");
+/// Move the socket and token to the new name's paths, and tell the desktop
+/// and the programs started from now on.
+fn rename_session(state: &Session, new_name: &str) -> anyhow::Result<()> {
+    check_session_name(new_name)?;
+
+    let mut name = state.name.lock().unwrap();
+    let dir = session_dir()?;
+    if session_alive(&dir, new_name) {
+        bail!("session '{}' already exists", new_name);
+    }
+    remove_stale_session(&dir, new_name)?;
+
+    let new_sock_path = socket_path(new_name)?;
+    fs::rename(socket_path(&name)?, &new_sock_path).context("failed to rename the session socket")?;
+    fs::rename(auth::token_path(&dir, &name), auth::token_path(&dir, new_name))
+        .context("failed to rename the session token")?;
+
+    // Files that only exist for some sessions follow the new name too.
+    let optional_files = [
+        (layout::layout_path(&dir, &name), layout::layout_path(&dir, new_name)),
+        (daemon::pid_path(&dir, &name), daemon::pid_path(&dir, new_name)),
+        (daemon::log_path(&dir, &name), daemon::log_path(&dir, new_name)),
+    ];
+    for (from, to) in optional_files {
+        if from.exists() {
+            fs::rename(&from, &to).with_context(|| format!("failed to rename {:?}", from))?;
+        }
+    }
+
+    // The activity log is still written to, so its writer moves it.
+    if let Some(log) = &state.activity {
+        log.rename(activity::activity_path(&dir, new_name))?;
+    }
+    state.record(|| Activity::Renamed { name: new_name.to_owned() });
+
+    info!("Session '{}' renamed to '{}'.", name, new_name);
+    *name = new_name.to_owned();
+    drop(name);
+
+    state.set_environment(vec![(config::SESSION_ENV.to_owned(), Some(new_name.to_owned()))]);
+    let _ = state.desktop.try_send(DesktopCommand::Renamed { name: new_name.to_owned() });
+    Ok(())
+}
+
+/// Encode and write a single message.
+async fn send(writer: &mut (impl AsyncWriteExt + Unpin), msg: &Message, framing: Framing) -> anyhow::Result<()> {
+    writer.write_all(&protocol::encode(msg, framing)?).await?;
+    Ok(())
 }
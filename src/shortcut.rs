@@ -57,7 +57,26 @@ nest! {
                         pub b: u8,
                     }
                 >
+            },
+
+        // Open a window when the desktop starts
+        #[serde(default)]
+        pub autostart: Option<
+            #[derive(Clone, Debug, Serialize, Deserialize)]
+            pub struct AutostartOptions {
+                // Seconds after the desktop started
+                #[serde(default)]
+                pub delay: u64,
+                pub position: Option<
+                    #[derive(Clone, Debug, Serialize, Deserialize)]
+                    pub struct WindowPosition {
+                        pub x: i32,
+                        pub y: i32,
+                    }
+                >,
+                pub size: Option<WindowSize>,
             }
+        >
     }
 }
 
@@ -1,4 +1,14 @@
+use crate::accessibility::{cell_colors, remap};
+use crate::glyphs;
 use appcui::prelude::{CharFlags, Character, Color, Surface};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::sync::RwLock;
+use tracing::trace;
+
+/// Characters of an OSC sequence kept while waiting for its end, enough for
+/// a few MiB copied with OSC 52. Longer sequences are skipped.
+const MAX_OSC_LENGTH: usize = 4 * 1024 * 1024;
 
 #[derive(Clone, Copy)]
 struct CellData {
@@ -69,6 +79,20 @@ pub struct TerminalParser {
     saved_state: Option<TerminalState>,
     main_cells: Option<Vec<Vec<CellData>>>,
     main_state: Option<TerminalState>,
+    /// Set when BEL was written since the last `take_bell`.
+    bell: bool,
+    /// Text copied with OSC 52 since the last `take_clipboard`.
+    clipboard: Option<Vec<u8>>,
+    /// Set when the program asked for the clipboard with OSC 52.
+    clipboard_query: bool,
+    /// Title set with OSC 0 or 2 since the last `take_title`.
+    title: Option<String>,
+    /// Set while the program wants pastes between markers, mode 2004.
+    bracketed_paste: bool,
+    /// Body of an OSC sequence a read ended in the middle of, from after `ESC ]`.
+    osc_pending: Option<Vec<char>>,
+    /// Set while skipping the rest of an OSC sequence over `MAX_OSC_LENGTH`.
+    osc_too_long: bool,
 }
 
 impl TerminalParser {
@@ -96,15 +120,56 @@ impl TerminalParser {
             saved_state: None,
             main_cells: None,
             main_state: None,
+            bell: false,
+            clipboard: None,
+            clipboard_query: false,
+            title: None,
+            bracketed_paste: false,
+            osc_pending: None,
+            osc_too_long: false,
         }
     }
 
+    /// The characters on screen, without trailing blanks.
+    pub fn screen_text(&self) -> String {
+        let lines: Vec<String> = self
+            .cells
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.character).collect::<String>().trim_end().to_owned())
+            .collect();
+        let mut text = lines.join("\n").trim_end().to_owned();
+        text.push('\n');
+        text
+    }
+
+    /// Forget everything written so far, for a new program in the same window.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.width, self.height, self.state.default_background_color);
+    }
+
     pub fn parse_to_surface(&mut self, data: &[u8], mut surface: Surface) -> Surface {
         let text = String::from_utf8_lossy(data);
         let chars: Vec<char> = text.chars().collect();
 
         let mut i = 0;
 
+        // Finish the OSC sequence the previous read ended in.
+        if let Some(mut body) = self.osc_pending.take() {
+            let from = body.len().saturating_sub(1);
+            let previous = body.len();
+            body.extend_from_slice(&chars);
+            match osc_end(&body, from) {
+                Some(end) => {
+                    self.finish_osc(&body[..end]);
+                    i = end - previous;
+                }
+                None => {
+                    self.buffer_osc(body);
+                    i = chars.len();
+                }
+            }
+        }
+
         while i < chars.len() {
             if chars[i] == '\u{1b}' && i + 1 < chars.len() {
                 match chars[i + 1] {
@@ -117,11 +182,18 @@ impl TerminalParser {
                             .count();
                         i += consumed_chars;
                     }
-                    ']' => {
+                    ']' => match osc_end(&chars[i + 2..], 0) {
                         // OSC sequence
-                        let consumed = self.skip_osc(&chars[i..]);
-                        i += consumed;
-                    }
+                        Some(end) => {
+                            self.handle_osc(&chars[i + 2..i + 2 + end]);
+                            i += 2 + end;
+                        }
+                        // The rest comes with the next read, e.g. a large OSC 52 copy.
+                        None => {
+                            self.buffer_osc(chars[i + 2..].to_vec());
+                            i = chars.len();
+                        }
+                    },
                     'P' => {
                         // DCS sequence
                         let consumed = self.skip_dcs(&chars[i..]);
@@ -159,8 +231,9 @@ impl TerminalParser {
                         self.cells = vec![vec![CellData::default_with_bg(bg); self.width as usize]; self.height as usize];
                         i += 2;
                     }
-                    _ => {
+                    other => {
                         // skip unknown ESC sequences
+                        trace!("Skipping unknown escape sequence ESC {:?}", other);
                         i += 1;
                     }
                 }
@@ -177,7 +250,7 @@ impl TerminalParser {
                 surface.write_char(
                     col as i32,
                     row as i32,
-                    Character::new(cell.character, cell.foreground, cell.background, cell.flags),
+                    Character::new(glyphs::substitute(cell.character), cell.foreground, cell.background, cell.flags),
                 );
             }
         }
@@ -185,18 +258,70 @@ impl TerminalParser {
         surface
     }
 
-    fn skip_osc(&self, chars: &[char]) -> usize {
-        let mut i = 2; // skip ESC ]
-        while i < chars.len() {
-            if chars[i] == '\x07' {
-                return i + 1; // BEL terminates
-            }
-            if chars[i] == '\x1b' && i + 1 < chars.len() && chars[i + 1] == '\\' {
-                return i + 2; // ST terminates
+    /// Whether the program rang the bell since the last call.
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.bell)
+    }
+
+    /// Text the program copied with OSC 52, if any.
+    pub fn take_clipboard(&mut self) -> Option<Vec<u8>> {
+        self.clipboard.take()
+    }
+
+    /// Whether the program asked for the clipboard with OSC 52.
+    pub fn take_clipboard_query(&mut self) -> bool {
+        std::mem::take(&mut self.clipboard_query)
+    }
+
+    /// Title the program set with OSC 0 or 2, if any.
+    pub fn bracketed_paste(&self) -> bool {
+        self.bracketed_paste
+    }
+
+    pub fn take_title(&mut self) -> Option<String> {
+        self.title.take()
+    }
+
+    /// Handle the OSC sequences that matter to the desktop, `chars` runs from
+    /// after `ESC ]` up to and including the terminator.
+    fn handle_osc(&mut self, chars: &[char]) {
+        let body: String = chars
+            .iter()
+            .take_while(|&&c| c != '\x07' && c != '\x1b')
+            .collect();
+
+        // Clipboard: 52 ; selection ; base64 data, or `?` to read it.
+        if let Some((_, data)) = body.strip_prefix("52;").and_then(|rest| rest.split_once(';')) {
+            if data == "?" {
+                self.clipboard_query = true;
+            } else if let Ok(bytes) = BASE64.decode(data) {
+                self.clipboard = Some(bytes);
             }
-            i += 1;
         }
-        chars.len() // consume all if unterminated
+
+        // Title: 0 ; text (icon name and title) or 2 ; text (title only).
+        if let Some(title) = body.strip_prefix("0;").or_else(|| body.strip_prefix("2;")) {
+            self.title = Some(title.to_owned());
+        }
+    }
+
+    /// Keep an unterminated OSC sequence for the next read, unless it grew
+    /// too long to be worth waiting for.
+    fn buffer_osc(&mut self, body: Vec<char>) {
+        if self.osc_too_long || body.len() > MAX_OSC_LENGTH {
+            trace!("Skipping an OSC sequence over {} characters.", MAX_OSC_LENGTH);
+            self.osc_too_long = true;
+            // Only the last character matters, it may start the terminator.
+            self.osc_pending = Some(body.last().copied().into_iter().collect());
+        } else {
+            self.osc_pending = Some(body);
+        }
+    }
+
+    fn finish_osc(&mut self, body: &[char]) {
+        if !std::mem::take(&mut self.osc_too_long) {
+            self.handle_osc(body);
+        }
     }
 
     fn skip_dcs(&self, chars: &[char]) -> usize {
@@ -485,6 +610,7 @@ impl TerminalParser {
             }
             _ => {
                 // Ignore unknown sequences
+                trace!("Ignoring CSI {:?} {:?}", params, command);
                 let _ = surface;
             }
         }
@@ -505,7 +631,7 @@ impl TerminalParser {
                                 self.state = saved_state;
                             }
                         }
-                        2004 => {} // bracketed paste - no-op
+                        2004 => self.bracketed_paste = false,
                         _ => {}
                     }
                 }
@@ -527,7 +653,7 @@ impl TerminalParser {
                             self.state.cursor_x = 0;
                             self.state.cursor_y = 0;
                         }
-                        2004 => {} // bracketed paste - no-op
+                        2004 => self.bracketed_paste = true,
                         _ => {}
                     }
                 }
@@ -538,6 +664,7 @@ impl TerminalParser {
             }
             _ => {
                 // ignore unknown private sequences
+                trace!("Ignoring CSI ? {:?} {:?}", params, command);
             }
         }
     }
@@ -672,7 +799,7 @@ impl TerminalParser {
                             2 => {
                                 // Truecolor: 38;2;<r>;<g>;<b> or 48;2;<r>;<g>;<b>
                                 if let (Some(r), Some(g), Some(b)) = (iter.next(), iter.next(), iter.next()) {
-                                    let color = Color::RGB(r as u8, g as u8, b as u8);
+                                    let color = remap((r as u8, g as u8, b as u8));
 
                                     if is_foreground {
                                         self.state.foreground = color;
@@ -719,6 +846,9 @@ impl TerminalParser {
                     self.state.cursor_x -= 1;
                 }
             }
+            '\x07' => {
+                self.bell = true;
+            }
             c if c.is_control() => {
                 // Ignore other control characters
             }
@@ -740,6 +870,7 @@ impl TerminalParser {
                 } else {
                     (self.state.foreground, self.state.background)
                 };
+                let (fg, bg) = cell_colors(fg, bg);
 
                 let y = self.state.cursor_y as usize;
                 let x = self.state.cursor_x as usize;
@@ -772,8 +903,35 @@ impl TerminalParser {
     }
 }
 
+/// Length of an OSC sequence's body up to and including its BEL or ST
+/// terminator, looking from `from`. `None` while it is unterminated.
+fn osc_end(body: &[char], from: usize) -> Option<usize> {
+    (from..body.len()).find_map(|i| match body[i] {
+        '\x07' => Some(i + 1),
+        '\x1b' if body.get(i + 1) == Some(&'\\') => Some(i + 2),
+        _ => None,
+    })
+}
+
+/// OSC 52 sequence setting the terminal's clipboard to `data`.
+pub fn osc52(data: &[u8]) -> Vec<u8> {
+    format!("\x1b]52;c;{}\x07", BASE64.encode(data)).into_bytes()
+}
+
+/// Colors replacing the built-in 16 ANSI ones, black to bright white.
+static PALETTE: RwLock<Option<[(u8, u8, u8); 16]>> = RwLock::new(None);
+
+/// Use the configured palette for the 16 ANSI colors.
+pub fn set_palette(palette: Option<[(u8, u8, u8); 16]>) {
+    *PALETTE.write().unwrap() = palette;
+}
+
 /// Map 16 ANSI colors to RGB
 fn ansi_16_color(code: u32, bright: bool) -> Color {
+    if let Some(palette) = *PALETTE.read().unwrap() {
+        return remap(palette[(code.min(7) + if bright { 8 } else { 0 }) as usize]);
+    }
+
     let (r, g, b): (u8, u8, u8) = match code {
         0 => (0, 0, 0),       // Black
         1 => (128, 0, 0),     // Red
@@ -787,13 +945,13 @@ fn ansi_16_color(code: u32, bright: bool) -> Color {
     };
 
     if bright {
-        Color::RGB(
+        remap((
             r.saturating_mul(2).min(255),
             g.saturating_mul(2).min(255),
             b.saturating_mul(2).min(255)
-        )
+        ))
     } else {
-        Color::RGB(r, g, b)
+        remap((r, g, b))
     }
 }
 
@@ -814,16 +972,16 @@ fn ansi_256_color(idx: u32) -> Color {
             let r = (n / 36) % 6;
             let g = (n / 6) % 6;
             let b = n % 6;
-            Color::RGB(
+            remap((
                 (r * 51) as u8,
                 (g * 51) as u8,
                 (b * 51) as u8,
-            )
+            ))
         }
         232..=255 => {
             // Grayscale ramp (24 shades)
             let level = 8 + (idx - 232) * 10;
-            Color::RGB(level as u8, level as u8, level as u8)
+            remap((level as u8, level as u8, level as u8))
         }
         _ => Color::RGB(0, 0, 0),
     }
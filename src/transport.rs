@@ -0,0 +1,196 @@
+use anyhow::{anyhow, bail, Context};
+use std::fs::{self, DirBuilder, File};
+use std::io::BufReader;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Numbers the private directories Unix sockets are bound in.
+static NEXT_BIND: AtomicU64 = AtomicU64::new(1);
+
+/// Any bidirectional byte stream the protocol framing can run over.
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+pub type BoxedStream = Box<dyn Stream>;
+
+/// Address accepted by `serve --listen`.
+#[derive(Clone, Debug)]
+pub enum ListenAddr {
+    /// `unix:///path/to/socket`
+    Unix(PathBuf),
+    /// `tcp://host:port`
+    Tcp(String),
+}
+
+impl FromStr for ListenAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix://") {
+            Ok(ListenAddr::Unix(PathBuf::from(path)))
+        } else if let Some(addr) = s.strip_prefix("tcp://") {
+            Ok(ListenAddr::Tcp(addr.to_owned()))
+        } else {
+            bail!("unsupported listen address '{}' (expected tcp://host:port or unix:///path)", s)
+        }
+    }
+}
+
+/// A bound listener, either a local Unix socket or a TLS-wrapped TCP socket.
+pub enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener, TlsAcceptor),
+}
+
+/// A freshly accepted connection whose transport handshake has not run yet.
+///
+/// The TLS handshake is deferred to `establish` so a slow remote peer
+/// cannot stall the accept loop.
+pub enum Incoming {
+    Unix(UnixStream),
+    Tls(TcpStream, TlsAcceptor),
+}
+
+impl Listener {
+    /// Bind a Unix socket only its owner can connect to.
+    ///
+    /// The socket is bound and chmodded in a private directory next to
+    /// `path`, then linked into place, so it is never reachable by others
+    /// in between. Linking fails like binding does when `path` is taken.
+    pub fn bind_unix(path: &Path) -> anyhow::Result<Self> {
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let private = parent.join(format!(".bind.{}-{}", std::process::id(), NEXT_BIND.fetch_add(1, Ordering::Relaxed)));
+        DirBuilder::new().mode(0o700).create(&private).with_context(|| format!("failed to create {:?}", private))?;
+
+        let tmp_path = private.join("socket");
+        let bind = || -> anyhow::Result<UnixListener> {
+            let listener = UnixListener::bind(&tmp_path).context("failed to bind Unix socket")?;
+            fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o600))?;
+            fs::hard_link(&tmp_path, path).with_context(|| format!("failed to bind Unix socket {:?}", path))?;
+            Ok(listener)
+        };
+        let listener = bind();
+        let _ = fs::remove_file(&tmp_path);
+        let _ = fs::remove_dir(&private);
+        Ok(Listener::Unix(listener?))
+    }
+
+    pub async fn bind_tcp(addr: &str, acceptor: TlsAcceptor) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("failed to bind TCP listener on {}", addr))?;
+        Ok(Listener::Tcp(listener, acceptor))
+    }
+
+    /// Accept the next connection, returning it along with a printable peer description.
+    pub async fn accept(&self) -> std::io::Result<(Incoming, String)> {
+        match self {
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Incoming::Unix(stream), String::from("unix")))
+            }
+            Listener::Tcp(listener, acceptor) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok((Incoming::Tls(stream, acceptor.clone()), peer.to_string()))
+            }
+        }
+    }
+}
+
+impl Incoming {
+    /// Finish the transport handshake and return a stream ready for protocol framing.
+    pub async fn establish(self) -> anyhow::Result<BoxedStream> {
+        match self {
+            Incoming::Unix(stream) => Ok(Box::new(stream)),
+            Incoming::Tls(stream, acceptor) => {
+                let tls = acceptor.accept(stream).await.context("TLS handshake failed")?;
+                Ok(Box::new(tls))
+            }
+        }
+    }
+}
+
+/// Build a TLS acceptor from PEM encoded certificate chain and private key files.
+pub fn tls_acceptor(cert_path: &Path, key_path: &Path) -> anyhow::Result<TlsAcceptor> {
+    let certs = load_certs(cert_path)?;
+
+    let mut key_reader = BufReader::new(
+        File::open(key_path).with_context(|| format!("cannot open TLS key {:?}", key_path))?
+    );
+    let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut key_reader)?
+        .ok_or_else(|| anyhow!("no private key found in {:?}", key_path))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("invalid TLS certificate or key")?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+pub async fn connect_unix(path: &Path) -> anyhow::Result<BoxedStream> {
+    let stream = UnixStream::connect(path)
+        .await
+        .context("Failed to connect to session socket")?;
+    Ok(Box::new(stream))
+}
+
+/// Connect to a remote `host:port` over TLS.
+///
+/// The server certificate is verified against `ca_path` when given,
+/// otherwise against the bundled Mozilla root store.
+pub async fn connect_tcp(addr: &str, ca_path: Option<&Path>) -> anyhow::Result<BoxedStream> {
+    let mut roots = RootCertStore::empty();
+    match ca_path {
+        Some(path) => {
+            for cert in load_certs(path)? {
+                roots.add(cert).context("invalid CA certificate")?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(config));
+
+    let host = match addr.rsplit_once(':') {
+        Some((host, _)) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => bail!("remote address '{}' must be host:port", addr),
+    };
+    let server_name = ServerName::try_from(host.to_owned())
+        .map_err(|_| anyhow!("invalid server name '{}'", host))?;
+
+    let tcp = TcpStream::connect(addr)
+        .await
+        .with_context(|| format!("Failed to connect to {}", addr))?;
+    let tls = connector
+        .connect(server_name, tcp)
+        .await
+        .context("TLS handshake failed")?;
+
+    Ok(Box::new(tls))
+}
+
+fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(
+        File::open(path).with_context(|| format!("cannot open certificate {:?}", path))?
+    );
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+
+    if certs.is_empty() {
+        bail!("no certificates found in {:?}", path);
+    }
+
+    Ok(certs)
+}
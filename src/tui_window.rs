@@ -1,4 +1,12 @@
-use crate::terminal_emulation::TerminalParser;
+use crate::actions::Action;
+use crate::clipboard::{self, Source};
+use crate::events::{self, DesktopEvent};
+use crate::idle;
+use crate::layout;
+use crate::plugins;
+use crate::processes;
+use crate::protocol::WindowEvent;
+use crate::terminal_emulation::{osc52, TerminalParser};
 use anyhow::anyhow;
 use appcui::dialogs::{Location, OpenFileDialogFlags, SelectFolderDialogFlags};
 use appcui::graphics::{CharAttribute, CharFlags, Character, Color, Size, Surface};
@@ -7,24 +15,51 @@ use appcui::prelude::{canvas, Alignment, Canvas, EventProcessStatus, Handle, Lay
 use async_channel::{Receiver, Sender};
 use std::ffi::OsStr;
 use std::path::Path;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::debug;
 use virtual_terminal::{Command, Input, Output};
 use crate::shortcut::{BackgroundColor, TerminalOptions, WindowOptions, WindowSize};
 
-#[CustomControl(overwrite = OnKeyPressed)]
+/// Windows are numbered in opening order so clients can tell them apart.
+static NEXT_WINDOW_ID: AtomicU64 = AtomicU64::new(1);
+
+/// How often the foreground process shown in the title is looked up.
+const FOREGROUND_CHECK: Duration = Duration::from_secs(1);
+
+#[CustomControl(overwrite = OnKeyPressed+OnMouseEvent)]
 pub struct CustomKeyboardControl {
     pub should_exit: bool,
     pub tx: Sender<Input>,
     pub rx: Receiver<Output>,
+    /// Process the window started, once known.
+    pub pid: Option<u32>,
+    /// Whether the program wants pastes between markers, as last seen by the window.
+    pub bracketed_paste: bool,
 }
 
 #[Window(events = TimerEvents)]
 pub struct TuiWindow {
+    pub id: u64,
     pub canvas: Handle<Canvas>,
     pub terminal_parser: TerminalParser,
     pub custom_keyboard_control: Handle<CustomKeyboardControl>,
     pub horizontal_adjustment: u32,
-    pub vertical_adjustment: u32
+    pub vertical_adjustment: u32,
+    pub command: String,
+    pub args: Vec<String>,
+    pub pid: Option<u32>,
+    /// Size last given to the PTY, the window's own unless clients attached to it alone.
+    pub pty_size: (usize, usize),
+    /// Current pace of the timer reading the program's output.
+    pub poll_interval: Duration,
+    /// Name of the shortcut the window was opened from.
+    pub shortcut: String,
+    /// The shortcut's name, or the title the program set, without the foreground process.
+    pub program_title: String,
+    /// Process running in the foreground of the PTY, when it is not the window's own.
+    pub foreground: Option<String>,
+    pub foreground_checked: Instant,
 }
 
 impl TuiWindow {
@@ -34,6 +69,7 @@ impl TuiWindow {
         args: I,
         window_options: WindowOptions,
         terminal_options: TerminalOptions,
+        current_dir: Option<&Path>,
     ) -> anyhow::Result<Self> where S: AsRef<OsStr>, I: IntoIterator<Item = S> {
         let window_size = window_options.size
             .unwrap_or(WindowSize {
@@ -93,20 +129,12 @@ impl TuiWindow {
             modified_args.push(modified_arg);
         }
 
-        let cmd = Command::new(modified_program)
-            .args(modified_args)
-            .terminal_size((
-                inner_size.width as usize,
-                inner_size.height as usize
-            ));
-
-        let rx = cmd.out_rx();
-        let tx = cmd.in_tx();
-
-        tx.send_blocking(Input::Resize((
-            inner_size.width as usize,
-            inner_size.height as usize
-        )))?;
+        let (tx, rx, cmd) = prepare_program(
+            &modified_program,
+            &modified_args,
+            (inner_size.width as usize, inner_size.height as usize),
+            current_dir,
+        )?;
 
         let default_background_color = match terminal_options.background_color {
             None => Color::RGB(0, 0, 0),
@@ -115,6 +143,7 @@ impl TuiWindow {
 
         let mut tui_win = Self {
             base: win,
+            id: NEXT_WINDOW_ID.fetch_add(1, Ordering::Relaxed),
             canvas: Handle::None,
             custom_keyboard_control: Handle::None,
             terminal_parser: TerminalParser::new(
@@ -124,6 +153,15 @@ impl TuiWindow {
             ),
             horizontal_adjustment: horizontal_adjustment  as u32,
             vertical_adjustment: vertical_adjustment as u32,
+            command: modified_program,
+            args: modified_args,
+            pid: None,
+            pty_size: (inner_size.width as usize, inner_size.height as usize),
+            poll_interval: idle::ACTIVE_POLL,
+            shortcut: app_name.to_owned(),
+            program_title: app_name.to_owned(),
+            foreground: None,
+            foreground_checked: Instant::now(),
         };
 
         tui_win.canvas = tui_win.add(Canvas::new(
@@ -151,13 +189,15 @@ impl TuiWindow {
             Some(t) => t,
             None => return Err(anyhow!("Failed to get timer"))
         };
-        timer.start(Duration::from_millis(25));
+        timer.start(tui_win.poll_interval);
 
         tui_win.custom_keyboard_control = tui_win.add(CustomKeyboardControl {
             should_exit: false,
             base: ControlBase::new(Layout::fill(), true),
             tx,
             rx,
+            pid: None,
+            bracketed_paste: false,
         });
 
         tokio::spawn(cmd.run());
@@ -171,6 +211,87 @@ impl TuiWindow {
         Ok(tui_win)
     }
 
+    /// Type into the window's program.
+    pub fn send_input(&mut self, data: Vec<u8>) {
+        let custom_keyboard_control = self.custom_keyboard_control;
+        if let Some(control) = self.control_mut(custom_keyboard_control) {
+            control.tx.send_blocking(Input::Data(data)).ok();
+        }
+    }
+
+    /// Run an action on the window, from its key or the command palette.
+    pub fn run_action(&mut self, action: Action) {
+        if action == Action::RespawnWindow {
+            if let Err(e) = self.respawn() {
+                dialogs::error("Could not respawn the window", &format!("{:#}", e));
+            }
+            return;
+        }
+
+        let custom_keyboard_control = self.custom_keyboard_control;
+        if let Some(control) = self.control_mut(custom_keyboard_control) {
+            control.run_action(action);
+        }
+    }
+
+    /// The characters on screen, without trailing blanks.
+    pub fn screen_text(&self) -> String {
+        self.terminal_parser.screen_text()
+    }
+
+    /// Stop the program and run the same command again in this window, from the
+    /// directory the program was in.
+    pub fn respawn(&mut self) -> anyhow::Result<()> {
+        let current_dir = self.pid.and_then(layout::process_cwd);
+        let (tx, rx, cmd) = prepare_program(&self.command, &self.args, self.pty_size, current_dir.as_deref())?;
+
+        let custom_keyboard_control = self.custom_keyboard_control;
+        if let Some(control) = self.control_mut(custom_keyboard_control) {
+            control.tx.send_blocking(Input::Terminate).ok();
+            control.tx.close();
+            control.rx.close();
+            control.tx = tx;
+            control.rx = rx;
+            control.pid = None;
+        }
+        tokio::spawn(cmd.run());
+
+        debug!("Respawned '{}' in window {}.", self.command, self.id);
+        self.pid = None;
+        self.foreground = None;
+        self.program_title = self.shortcut.clone();
+        self.refresh_title();
+        self.terminal_parser.reset();
+        let c = self.canvas;
+        if let Some(cv) = self.control_mut(c) {
+            cv.drawing_surface_mut().clear(Character::new(' ', Color::Transparent, Color::Transparent, CharFlags::None));
+        }
+
+        Ok(())
+    }
+
+    /// Show the foreground process next to the title, checked about every second.
+    fn update_foreground(&mut self) {
+        if self.foreground_checked.elapsed() < FOREGROUND_CHECK {
+            return;
+        }
+        self.foreground_checked = Instant::now();
+
+        let foreground = self.pid.and_then(processes::foreground_name);
+        if foreground != self.foreground {
+            self.foreground = foreground;
+            self.refresh_title();
+        }
+    }
+
+    fn refresh_title(&mut self) {
+        let title = match &self.foreground {
+            Some(name) => format!("{} [{}]", self.program_title, name),
+            None => self.program_title.clone(),
+        };
+        self.set_title(&title);
+    }
+
     pub fn close_command(&mut self) {
         let custom_keyboard_control = self.custom_keyboard_control;
         let control = self.control_mut(custom_keyboard_control).unwrap();
@@ -183,20 +304,62 @@ impl TuiWindow {
 
 impl TimerEvents for TuiWindow {
     fn on_update(&mut self, _: u64) -> EventProcessStatus {
-        let (should_close, (rx_clone, tx_clone)) = {
-            let ckc = self.control(self.custom_keyboard_control).unwrap();
-
-            (ckc.should_exit, (ckc.rx.clone(), ckc.tx.clone()))
-        };
+        let should_close = self.control(self.custom_keyboard_control).unwrap().should_exit;
 
         if should_close {
             self.close_command();
             return EventProcessStatus::Processed;
         }
 
-        match rx_clone.try_recv() {
+        let (rx_clone, tx_clone) = {
+            let ckc = self.control(self.custom_keyboard_control).unwrap();
+
+            (ckc.rx.clone(), ckc.tx.clone())
+        };
+
+        // Clients attached to this window alone size its PTY and type into it.
+        let mut mirror = events::take_mirror(self.id);
+        let pty_size = match mirror.as_ref().and_then(|mirror| mirror.size) {
+            Some((cols, rows)) => (cols as usize, rows as usize),
+            None => {
+                let size = self.size();
+                (
+                    size.width.saturating_sub(self.horizontal_adjustment) as usize,
+                    size.height.saturating_sub(self.vertical_adjustment) as usize,
+                )
+            }
+        };
+
+        if pty_size != self.pty_size {
+            tx_clone.send_blocking(Input::Resize(pty_size)).ok();
+            self.pty_size = pty_size;
+        }
+
+        if let Some(mirror) = &mut mirror {
+            for data in mirror.input.drain(..) {
+                idle::touch();
+                tx_clone.send_blocking(Input::Data(data)).ok();
+            }
+
+            // Shrunk by a row now and restored on the next tick, like the server's force_redraw.
+            if mirror.redraw && pty_size.1 > 1 {
+                self.pty_size = (pty_size.0, pty_size.1 - 1);
+                tx_clone.send_blocking(Input::Resize(self.pty_size)).ok();
+            }
+        }
+
+        self.update_foreground();
+
+        let status = match rx_clone.try_recv() {
             Ok(msg) => match msg {
-                Output::Pid(_) => EventProcessStatus::Ignored,
+                Output::Pid(pid) => {
+                    self.pid = Some(pid);
+                    let custom_keyboard_control = self.custom_keyboard_control;
+                    if let Some(control) = self.control_mut(custom_keyboard_control) {
+                        control.pid = Some(pid);
+                    }
+                    EventProcessStatus::Ignored
+                },
                 Output::Stdout(command_output) => {
                     let size = self.size();
                     let inner_size = Size {
@@ -217,20 +380,49 @@ impl TimerEvents for TuiWindow {
                         (Surface::from_buffer(&buffer).unwrap(), should_resize)
                     };
 
+                    if mirror.is_some() {
+                        events::report(&DesktopEvent::WindowOutput { id: self.id, data: command_output.clone() });
+                    }
+
                     let new_surface = self.terminal_parser.parse_to_surface(&command_output, old_surface);
 
+                    if self.terminal_parser.take_bell() {
+                        plugins::ring(self.id, &self.program_title);
+                        if !self.has_focus() {
+                            events::report(&DesktopEvent::Bell { window: self.program_title.clone() });
+                        }
+                    }
+
+                    if let Some(data) = self.terminal_parser.take_clipboard() {
+                        clipboard::record(data.clone(), Source::Window(self.program_title.clone()));
+                        events::report(&DesktopEvent::Clipboard { data });
+                    }
+
+                    if let Some(title) = self.terminal_parser.take_title() {
+                        self.program_title = title.clone();
+                        self.refresh_title();
+                        events::report(&DesktopEvent::Window(WindowEvent::TitleChanged { id: self.id, title }));
+                    }
+
+                    let bracketed_paste = self.terminal_parser.bracketed_paste();
+                    let custom_keyboard_control = self.custom_keyboard_control;
+                    if let Some(control) = self.control_mut(custom_keyboard_control) {
+                        control.bracketed_paste = bracketed_paste;
+                    }
+
+                    if self.terminal_parser.take_clipboard_query()
+                        && let Some(data) = clipboard::current()
+                    {
+                        tx_clone.send_blocking(Input::Data(osc52(&data))).ok();
+                    }
+
                     let c = self.canvas;
                     let cv = self.control_mut(c).unwrap();
                     let surface = cv.drawing_surface_mut();
                     *surface = new_surface;
 
+                    // The PTY follows on the next tick, unless clients attached to this window size it.
                     if should_resize {
-                        tx_clone
-                            .send_blocking(Input::Resize((
-                                inner_size.width as usize,
-                                inner_size.height as usize
-                            )))
-                            .ok();
                         cv.set_size(inner_size.width as u16, inner_size.height as u16);
                         cv.resize_surface(inner_size);
                         self.terminal_parser.resize(inner_size.width, inner_size.height);
@@ -250,8 +442,57 @@ impl TimerEvents for TuiWindow {
                 }
             }
             Err(_) => EventProcessStatus::Ignored
+        };
+
+        // Quiet windows slow down while nobody uses the desktop.
+        let busy = matches!(status, EventProcessStatus::Processed) || mirror.is_some();
+        let poll_interval = idle::poll_interval(self.has_focus(), busy);
+        if poll_interval != self.poll_interval {
+            self.poll_interval = poll_interval;
+            if let Some(timer) = self.timer() {
+                timer.start(poll_interval);
+            }
+        }
+
+        status
+    }
+}
+
+/// Build the command running `program` in a PTY of `size`, with its input and output.
+fn prepare_program(program: &str, args: &[String], size: (usize, usize), current_dir: Option<&Path>) -> anyhow::Result<(Sender<Input>, Receiver<Output>, Command)> {
+    // Variables taken over from attaching clients are applied by `desktop-tui
+    // exec`, the desktop's own environment is left as it started.
+    let environment = events::session_environment();
+    let mut cmd = if environment.is_empty() {
+        Command::new(program).args(args)
+    } else {
+        let mut exec_args = vec![String::from("exec")];
+        for (name, value) in environment {
+            match value {
+                Some(value) => exec_args.extend([String::from("--set"), format!("{}={}", name, value)]),
+                None => exec_args.extend([String::from("--unset"), name]),
+            }
         }
+        exec_args.push(String::from("--"));
+        exec_args.push(program.to_owned());
+        exec_args.extend(args.iter().cloned());
+
+        let exe = std::env::current_exe()?.display().to_string();
+        Command::new(&exe).args(&exec_args)
+    };
+
+    cmd = cmd.terminal_size(size);
+
+    if let Some(current_dir) = current_dir {
+        cmd = cmd.current_dir(current_dir);
     }
+
+    let rx = cmd.out_rx();
+    let tx = cmd.in_tx();
+
+    tx.send_blocking(Input::Resize(size))?;
+
+    Ok((tx, rx, cmd))
 }
 
 fn replace_file_path(arg: String) -> anyhow::Result<String> {